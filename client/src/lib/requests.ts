@@ -1,11 +1,20 @@
+import { cookieGet } from '$lib/cookie';
+
+/// Echo the CSRF cookie back, the server refuses cookie authenticated changes without it
+function csrfHeaders(): Record<string, string> {
+    const token = cookieGet('csrf-token');
+    return token ? { 'X-CSRF-Token': token.toString() } : {};
+}
+
 async function post(url: string, body: Object): Promise<Response> {
     return fetch(url, {
         method: 'POST',
         headers: {
-            'Content-Type': 'application/json'
+            'Content-Type': 'application/json',
+            ...csrfHeaders()
         },
         body: JSON.stringify(body)
     });
 }
 
-export { post };
\ No newline at end of file
+export { csrfHeaders, post };
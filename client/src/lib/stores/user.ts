@@ -2,6 +2,7 @@ import {writable} from 'svelte/store';
 import type { User } from '$lib/types/user';
 import { cookieDelete, cookieGet } from '$lib/cookie';
 import { unixTime } from '$lib/time';
+import { csrfHeaders } from '$lib/requests';
 
 function createUser() {
     const token = cookieGet('auth-token');
@@ -22,7 +23,8 @@ function createUser() {
         // clear user store
         set(null);
 
-        // fetch logout endpoint to clear cookie
+        // revoke the token server side, then clear the cookie in case the request failed
+        await fetch('/api/logout', { method: 'POST', headers: csrfHeaders() }).catch(() => {});
         cookieDelete('auth-token');
     }
 
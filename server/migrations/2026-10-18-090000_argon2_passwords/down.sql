@@ -0,0 +1,5 @@
+-- Accounts that only have an Argon2id hash can no longer log in after this
+UPDATE users SET password_salt = '', password_hash = '' WHERE password_hash IS NULL;
+ALTER TABLE users ALTER COLUMN password_hash SET NOT NULL;
+ALTER TABLE users ALTER COLUMN password_salt SET NOT NULL;
+ALTER TABLE users DROP COLUMN password_phc;
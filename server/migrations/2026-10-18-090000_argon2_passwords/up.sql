@@ -0,0 +1,5 @@
+-- Argon2id hashes are stored as PHC strings, which embed their own salt and parameters.
+-- The legacy SHA-384 columns stay until each user logs in and is re-hashed.
+ALTER TABLE users ADD COLUMN password_phc VARCHAR(255);
+ALTER TABLE users ALTER COLUMN password_salt DROP NOT NULL;
+ALTER TABLE users ALTER COLUMN password_hash DROP NOT NULL;
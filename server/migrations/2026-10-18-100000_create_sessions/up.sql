@@ -0,0 +1,21 @@
+-- A session is one login. Every refresh token issued for it belongs to the same family,
+-- so reusing any rotated token can revoke the whole session.
+CREATE TABLE sessions (
+  id UUID PRIMARY KEY,
+  user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+  created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+  expires_at TIMESTAMP NOT NULL,
+  revoked_at TIMESTAMP
+);
+
+CREATE INDEX sessions_user_id_idx ON sessions (user_id);
+
+CREATE TABLE refresh_tokens (
+  id SERIAL PRIMARY KEY,
+  session_id UUID NOT NULL REFERENCES sessions (id) ON DELETE CASCADE,
+  token_hash BYTEA UNIQUE NOT NULL,
+  created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+  used_at TIMESTAMP
+);
+
+CREATE INDEX refresh_tokens_session_id_idx ON refresh_tokens (session_id);
@@ -0,0 +1,8 @@
+-- Access tokens revoked before their natural expiry. Rows can be purged once expires_at passes.
+CREATE TABLE revoked_tokens (
+  jti UUID PRIMARY KEY,
+  expires_at TIMESTAMP NOT NULL,
+  revoked_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+CREATE INDEX revoked_tokens_expires_at_idx ON revoked_tokens (expires_at);
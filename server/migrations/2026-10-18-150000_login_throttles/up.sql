@@ -0,0 +1,7 @@
+-- Failed login counters, keyed by e.g. 'email:bob@contoso.com' or 'ip:203.0.113.7'
+CREATE TABLE login_throttles (
+  key VARCHAR(320) PRIMARY KEY,
+  failures INTEGER NOT NULL,
+  last_failure_at TIMESTAMP NOT NULL,
+  blocked_until TIMESTAMP
+);
@@ -0,0 +1,14 @@
+CREATE TABLE personal_access_tokens (
+  id UUID PRIMARY KEY,
+  user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+  name VARCHAR(100) NOT NULL,
+  -- Space separated, e.g. 'lobby:read account:read'
+  scopes VARCHAR(255) NOT NULL,
+  token_hash BYTEA UNIQUE NOT NULL,
+  created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+  expires_at TIMESTAMP NOT NULL,
+  last_used_at TIMESTAMP,
+  revoked_at TIMESTAMP
+);
+
+CREATE INDEX personal_access_tokens_user_id_idx ON personal_access_tokens (user_id);
@@ -0,0 +1,13 @@
+-- Accounts at OpenID Connect providers that can sign in as a user
+CREATE TABLE user_identities (
+  id SERIAL PRIMARY KEY,
+  user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+  provider VARCHAR(50) NOT NULL,
+  -- The provider's `sub` claim, stable for as long as the account exists there
+  subject VARCHAR(255) NOT NULL,
+  email VARCHAR(255),
+  created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+  last_login_at TIMESTAMP,
+  UNIQUE (provider, subject),
+  UNIQUE (user_id, provider)
+);
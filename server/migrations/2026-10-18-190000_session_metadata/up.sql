@@ -0,0 +1,5 @@
+-- Where each session was started from, so users can tell their devices apart
+ALTER TABLE sessions
+  ADD COLUMN user_agent VARCHAR(512),
+  ADD COLUMN ip VARCHAR(45),
+  ADD COLUMN last_seen_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL;
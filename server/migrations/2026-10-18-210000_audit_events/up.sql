@@ -0,0 +1,18 @@
+-- Security relevant things that happened to accounts, and who did them
+CREATE TABLE audit_events (
+  id BIGSERIAL PRIMARY KEY,
+  kind VARCHAR(64) NOT NULL,
+  -- Who did it, if anyone was signed in or is known
+  actor_id INTEGER REFERENCES users (id) ON DELETE SET NULL,
+  -- Whose account it happened to
+  target_id INTEGER REFERENCES users (id) ON DELETE SET NULL,
+  ip VARCHAR(45),
+  user_agent VARCHAR(512),
+  request_id UUID NOT NULL,
+  detail JSONB DEFAULT '{}' NOT NULL,
+  created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+CREATE INDEX audit_events_actor_id_idx ON audit_events (actor_id);
+CREATE INDEX audit_events_target_id_idx ON audit_events (target_id);
+CREATE INDEX audit_events_kind_created_at_idx ON audit_events (kind, created_at);
@@ -0,0 +1,3 @@
+DROP TABLE lobbies;
+
+DROP TYPE lobby_visibility;
@@ -0,0 +1,8 @@
+CREATE TYPE lobby_visibility AS ENUM ('public', 'friends', 'private');
+
+CREATE TABLE lobbies (
+  id SERIAL PRIMARY KEY,
+  name VARCHAR(255) NOT NULL,
+  visibility lobby_visibility DEFAULT 'public' NOT NULL,
+  created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
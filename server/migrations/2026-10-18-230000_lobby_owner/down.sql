@@ -0,0 +1,3 @@
+DROP INDEX lobbies_owner_id_idx;
+
+ALTER TABLE lobbies DROP COLUMN owner_id;
@@ -0,0 +1,14 @@
+ALTER TABLE lobbies ADD COLUMN owner_id INTEGER REFERENCES users (id) ON DELETE CASCADE;
+
+-- Lobbies made before they had owners go to the first admin, or the first user when there is
+-- no admin yet, so someone can still manage them
+UPDATE lobbies SET owner_id = (
+  SELECT id FROM users
+  WHERE deleted_at IS NULL
+  ORDER BY role = 'admin' DESC, id
+  LIMIT 1
+);
+
+ALTER TABLE lobbies ALTER COLUMN owner_id SET NOT NULL;
+
+CREATE INDEX lobbies_owner_id_idx ON lobbies (owner_id);
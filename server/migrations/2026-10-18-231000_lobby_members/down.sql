@@ -0,0 +1,7 @@
+ALTER TABLE lobbies DROP CONSTRAINT lobbies_owner_id_fkey,
+  ADD CONSTRAINT lobbies_owner_id_fkey FOREIGN KEY (owner_id) REFERENCES users (id)
+    ON DELETE CASCADE;
+
+DROP TABLE lobby_members;
+
+ALTER TABLE lobbies DROP COLUMN max_players;
@@ -0,0 +1,20 @@
+ALTER TABLE lobbies ADD COLUMN max_players INTEGER DEFAULT 8 NOT NULL
+  CHECK (max_players BETWEEN 2 AND 64);
+
+CREATE TABLE lobby_members (
+  lobby_id INTEGER NOT NULL REFERENCES lobbies (id) ON DELETE CASCADE,
+  user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+  joined_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+  PRIMARY KEY (lobby_id, user_id)
+);
+
+CREATE INDEX lobby_members_user_id_idx ON lobby_members (user_id);
+
+-- Owners are members of their own lobbies
+INSERT INTO lobby_members (lobby_id, user_id, joined_at)
+SELECT id, owner_id, created_at FROM lobbies;
+
+-- Owners hand their lobbies over before their account is purged, see account::purge_deleted,
+-- rather than taking them away from everyone still in them
+ALTER TABLE lobbies DROP CONSTRAINT lobbies_owner_id_fkey,
+  ADD CONSTRAINT lobbies_owner_id_fkey FOREIGN KEY (owner_id) REFERENCES users (id);
@@ -0,0 +1,10 @@
+-- One way: user_id counts friend_id as a friend, and shares friends only lobbies with them
+CREATE TABLE friendships (
+  user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+  friend_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+  created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+  PRIMARY KEY (user_id, friend_id),
+  CHECK (user_id <> friend_id)
+);
+
+CREATE INDEX friendships_friend_id_idx ON friendships (friend_id);
@@ -0,0 +1 @@
+ALTER TABLE lobbies DROP COLUMN invite_code;
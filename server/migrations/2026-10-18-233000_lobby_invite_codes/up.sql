@@ -0,0 +1,2 @@
+-- Closed lobbies are deleted, so a code is unique among the lobbies still open
+ALTER TABLE lobbies ADD COLUMN invite_code VARCHAR(32) UNIQUE;
@@ -1,12 +1,34 @@
+use once_cell::sync::Lazy;
 use trie_rs::Trie;
 
-#[allow(dead_code)]
+use words::Words;
+
 mod words;
 
+static WORDS: Lazy<Words> = Lazy::new(Words::new);
+
 pub fn words_4() -> Trie<u8> {
     let mut trie = trie_rs::TrieBuilder::new();
-    for w in words::words_4() {
+    for w in WORDS.len_4() {
         trie.push(w);
     }
     trie.build()
 }
+
+/// Words that aren't allowed in anything users pick for others to see
+pub fn banned() -> impl Iterator<Item = &'static str> {
+    WORDS.banned().iter().map(String::as_str)
+}
+
+/// Four letter words that are fine for anyone to see or say out loud, in alphabetical order
+pub fn safe_words_4() -> Vec<&'static str> {
+    let banned = WORDS.banned();
+    let mut words: Vec<&'static str> = WORDS
+        .len_4()
+        .iter()
+        .filter(|word| !banned.contains(*word))
+        .map(String::as_str)
+        .collect();
+    words.sort_unstable();
+    words
+}
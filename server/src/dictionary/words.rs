@@ -10,18 +10,26 @@ pub struct Words {
 }
 
 impl Words {
-    fn new() -> Self {
+    pub fn new() -> Self {
         Self {
             len_4: raw_to_hash_set(WORDS_4_RAW),
             banned: raw_to_hash_set(WORDS_BANNED_RAW),
         }
     }
-}
 
-fn raw_to_hash_set(raw: &str) -> HashSet<String> {
-    raw.lines().map(|l| l.to_owned()).collect()
+    pub fn len_4(&self) -> &HashSet<String> {
+        &self.len_4
+    }
+
+    pub fn banned(&self) -> &HashSet<String> {
+        &self.banned
+    }
 }
 
-pub fn words_4() -> Vec<String> {
-    WORDS_4_RAW.lines().map(|l| l.to_owned()).collect()
+fn raw_to_hash_set(raw: &str) -> HashSet<String> {
+    raw.lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .map(|l| l.to_owned())
+        .collect()
 }
@@ -1,17 +1,19 @@
 use rustwebapp::db;
-use rustwebapp::mw;
+use rustwebapp::model::account;
 use rustwebapp::web::app_state::AppState;
-use rustwebapp::web::{self, routes};
+use rustwebapp::web::routes;
 
 #[cfg(not(feature = "embed_assets"))]
 use axum::response::Redirect;
 #[cfg(feature = "embed_assets")]
 use rustwebapp::assets;
 
+use std::net::SocketAddr;
+use std::time::Duration;
+
 use axum::routing::get;
-use axum::{middleware, Router};
+use axum::Router;
 use tokio::signal;
-use tower_cookies::CookieManagerLayer;
 use tracing::debug;
 use tracing::info;
 use tracing_subscriber::filter::EnvFilter;
@@ -19,6 +21,9 @@ use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 const DEFAULT_PORT: u16 = 3000;
 const DEFAULT_ADDR: &str = "0.0.0.0";
+const REVOCATION_SYNC_INTERVAL: Duration = Duration::from_secs(30);
+const THROTTLE_PURGE_INTERVAL: Duration = Duration::from_secs(60 * 5);
+const ACCOUNT_PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -58,17 +63,22 @@ async fn main() -> anyhow::Result<()> {
     // Create app state (xfer the db_pool into the app state for sharing across routes)
     let app_state = AppState::new(db_pool).await?;
 
-    let api_routes = routes::get_api_routes(&app_state).await?;
+    // Pick up token revocations made by other instances
+    app_state
+        .ctl_revocation
+        .clone()
+        .spawn_sync(REVOCATION_SYNC_INTERVAL);
 
-    let app = app
-        .nest("/api", api_routes)
-        .layer(middleware::map_response(web::main_response_mapper))
-        .layer(middleware::from_fn_with_state(
-            app_state,
-            mw::auth::ctx_resolver,
-        ))
-        .layer(middleware::map_request(web::main_request_mapper))
-        .layer(CookieManagerLayer::new());
+    // Forget failed logins once they are old enough
+    app_state
+        .ctl_throttle
+        .clone()
+        .spawn_purge(THROTTLE_PURGE_INTERVAL);
+
+    // Remove deleted accounts once their grace period is over
+    account::spawn_purge(app_state.db_pool.clone(), ACCOUNT_PURGE_INTERVAL);
+
+    let app = routes::get_app(app, app_state).await?;
 
     // get port from env or use default
     let port = std::env::var("PORT").unwrap_or(DEFAULT_PORT.to_string());
@@ -80,9 +90,12 @@ async fn main() -> anyhow::Result<()> {
 
     // Start server
     info!("🛫 Server running on: http://{}\n", address);
-    axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal())
-        .await?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown_signal())
+    .await?;
 
     info!("🛬 Goodbye!");
     Ok(())
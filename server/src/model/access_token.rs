@@ -0,0 +1,324 @@
+use std::{
+    fmt,
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize, Serializer};
+use tracing::debug;
+use uuid::Uuid;
+
+use crate::db::DbConn;
+use crate::schema::personal_access_tokens;
+use crate::service::crypto;
+
+/// Marks a bearer token as a personal access token rather than a JWT
+pub const TOKEN_PREFIX: &str = "pat_";
+const TOKEN_BYTES: usize = 32;
+const NAME_MAX_CHARS: usize = 100;
+pub const DEFAULT_LIFETIME_DAYS: u32 = 30;
+pub const MAX_LIFETIME_DAYS: u32 = 365;
+/// Don't write `last_used_at` more often than this for a busy token
+const LAST_USED_RESOLUTION: Duration = Duration::from_secs(60);
+
+// region: -- Scopes
+/// What a personal access token may be used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Scope {
+    LobbyRead,
+    LobbyWrite,
+    AccountRead,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 3] = [Scope::LobbyRead, Scope::LobbyWrite, Scope::AccountRead];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::LobbyRead => "lobby:read",
+            Scope::LobbyWrite => "lobby:write",
+            Scope::AccountRead => "account:read",
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Scope {
+    type Err = ErrorAccessToken;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Scope::ALL
+            .into_iter()
+            .find(|scope| scope.as_str() == s)
+            .ok_or_else(|| ErrorAccessToken::UnknownScope(s.to_string()))
+    }
+}
+
+impl Serialize for Scope {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+/// What a request is allowed to do
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Scopes {
+    /// A user's own session can do anything they can
+    #[default]
+    All,
+    /// A personal access token can only do what it was granted
+    Only(Vec<Scope>),
+}
+
+impl Scopes {
+    pub fn allows(&self, scope: Scope) -> bool {
+        match self {
+            Scopes::All => true,
+            Scopes::Only(scopes) => scopes.contains(&scope),
+        }
+    }
+}
+// endregion
+
+// region: -- Access Token Types
+#[derive(Debug, Clone, PartialEq, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::personal_access_tokens)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+struct StoredAccessToken {
+    id: Uuid,
+    user_id: i32,
+    name: String,
+    scopes: String,
+    created_at: SystemTime,
+    expires_at: SystemTime,
+    last_used_at: Option<SystemTime>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::personal_access_tokens)]
+struct AccessTokenForInsert {
+    id: Uuid,
+    user_id: i32,
+    name: String,
+    scopes: String,
+    token_hash: Vec<u8>,
+    expires_at: SystemTime,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AccessToken {
+    pub id: Uuid,
+    #[serde(skip)]
+    pub user_id: i32,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub created_at: SystemTime,
+    pub expires_at: SystemTime,
+    pub last_used_at: Option<SystemTime>,
+}
+
+impl From<StoredAccessToken> for AccessToken {
+    fn from(stored: StoredAccessToken) -> Self {
+        Self {
+            id: stored.id,
+            user_id: stored.user_id,
+            name: stored.name,
+            // Scopes are checked on the way in, so an unknown one here was since retired
+            scopes: stored
+                .scopes
+                .split_whitespace()
+                .filter_map(|s| s.parse().ok())
+                .collect(),
+            created_at: stored.created_at,
+            expires_at: stored.expires_at,
+            last_used_at: stored.last_used_at,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AccessTokenNewFields {
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub expires_in_days: Option<u32>,
+}
+// endregion
+
+// region: -- Access Token Controller
+#[derive(Debug, thiserror::Error, PartialEq, Clone, Serialize)]
+pub enum ErrorAccessToken {
+    #[error("{0}")]
+    Db(String),
+
+    #[error("Token name must be 1 to {NAME_MAX_CHARS} characters")]
+    InvalidName,
+
+    #[error("Unknown scope '{0}'")]
+    UnknownScope(String),
+
+    #[error("A token needs at least one scope")]
+    NoScopes,
+
+    #[error("Token lifetime must be 1 to {MAX_LIFETIME_DAYS} days")]
+    InvalidLifetime,
+
+    #[error("Access token not found")]
+    NotFound,
+
+    #[error("Access token is invalid, expired or revoked")]
+    InvalidToken,
+}
+
+impl From<diesel::result::Error> for ErrorAccessToken {
+    fn from(e: diesel::result::Error) -> Self {
+        ErrorAccessToken::Db(e.to_string())
+    }
+}
+
+/// Create a token for a user, returning it with the secret, which is never shown again
+pub async fn create(
+    mut conn: DbConn,
+    user_id: i32,
+    fields: AccessTokenNewFields,
+) -> Result<(AccessToken, String), ErrorAccessToken> {
+    let name = fields.name.trim().to_string();
+    if name.is_empty() || name.chars().count() > NAME_MAX_CHARS {
+        return Err(ErrorAccessToken::InvalidName);
+    }
+
+    let mut scopes = fields
+        .scopes
+        .iter()
+        .map(|s| s.parse())
+        .collect::<Result<Vec<Scope>, _>>()?;
+    scopes.sort_by_key(Scope::as_str);
+    scopes.dedup();
+    if scopes.is_empty() {
+        return Err(ErrorAccessToken::NoScopes);
+    }
+
+    let days = fields.expires_in_days.unwrap_or(DEFAULT_LIFETIME_DAYS);
+    if !(1..=MAX_LIFETIME_DAYS).contains(&days) {
+        return Err(ErrorAccessToken::InvalidLifetime);
+    }
+
+    let secret = format!("{TOKEN_PREFIX}{}", crypto::token(TOKEN_BYTES));
+    let stored = diesel::insert_into(personal_access_tokens::table)
+        .values(AccessTokenForInsert {
+            id: Uuid::new_v4(),
+            user_id,
+            name,
+            scopes: scopes
+                .iter()
+                .map(Scope::as_str)
+                .collect::<Vec<_>>()
+                .join(" "),
+            token_hash: crypto::token_hash(&secret),
+            expires_at: SystemTime::now() + Duration::from_secs(days as u64 * 60 * 60 * 24),
+        })
+        .returning(StoredAccessToken::as_returning())
+        .get_result(&mut conn)?;
+
+    debug!("Access token {} created for user {user_id}", stored.id);
+    Ok((stored.into(), secret))
+}
+
+/// Every token of a user that hasn't been revoked, newest first
+pub async fn list(mut conn: DbConn, user_id: i32) -> Result<Vec<AccessToken>, ErrorAccessToken> {
+    Ok(list_conn(&mut conn, user_id)?)
+}
+
+pub(crate) fn list_conn(
+    conn: &mut PgConnection,
+    user_id: i32,
+) -> Result<Vec<AccessToken>, diesel::result::Error> {
+    let stored = personal_access_tokens::table
+        .filter(personal_access_tokens::user_id.eq(user_id))
+        .filter(personal_access_tokens::revoked_at.is_null())
+        .order(personal_access_tokens::created_at.desc())
+        .select(StoredAccessToken::as_select())
+        .load(conn)?;
+    Ok(stored.into_iter().map(AccessToken::from).collect())
+}
+
+pub async fn revoke(mut conn: DbConn, user_id: i32, id: Uuid) -> Result<(), ErrorAccessToken> {
+    let revoked = diesel::update(
+        personal_access_tokens::table
+            .find(id)
+            .filter(personal_access_tokens::user_id.eq(user_id))
+            .filter(personal_access_tokens::revoked_at.is_null()),
+    )
+    .set(personal_access_tokens::revoked_at.eq(SystemTime::now()))
+    .execute(&mut conn)?;
+
+    match revoked {
+        0 => Err(ErrorAccessToken::NotFound),
+        _ => Ok(()),
+    }
+}
+
+/// Revoke every live token of a user, e.g. when their account is deleted
+pub(crate) fn revoke_all(
+    conn: &mut PgConnection,
+    user_id: i32,
+) -> Result<usize, diesel::result::Error> {
+    diesel::update(
+        personal_access_tokens::table
+            .filter(personal_access_tokens::user_id.eq(user_id))
+            .filter(personal_access_tokens::revoked_at.is_null()),
+    )
+    .set(personal_access_tokens::revoked_at.eq(SystemTime::now()))
+    .execute(conn)
+}
+
+/// Look up the token a request was made with, recording that it was used
+pub async fn authenticate(mut conn: DbConn, secret: &str) -> Result<AccessToken, ErrorAccessToken> {
+    let now = SystemTime::now();
+    let stored = personal_access_tokens::table
+        .filter(personal_access_tokens::token_hash.eq(crypto::token_hash(secret)))
+        .filter(personal_access_tokens::revoked_at.is_null())
+        .filter(personal_access_tokens::expires_at.gt(now))
+        .select(StoredAccessToken::as_select())
+        .first(&mut conn)
+        .optional()?
+        .ok_or(ErrorAccessToken::InvalidToken)?;
+
+    let stale = stored
+        .last_used_at
+        .is_none_or(|at| at + LAST_USED_RESOLUTION <= now);
+    if stale {
+        diesel::update(personal_access_tokens::table.find(stored.id))
+            .set(personal_access_tokens::last_used_at.eq(now))
+            .execute(&mut conn)?;
+    }
+
+    Ok(stored.into())
+}
+// endregion
+
+#[cfg(test)]
+mod tests {
+    use super::{Scope, Scopes};
+
+    #[test]
+    fn scope_round_trip() {
+        for scope in Scope::ALL {
+            assert_eq!(scope.as_str().parse::<Scope>().unwrap(), scope);
+        }
+        assert!("lobby:delete".parse::<Scope>().is_err());
+    }
+
+    #[test]
+    fn only_granted_scopes_are_allowed() {
+        let scopes = Scopes::Only(vec![Scope::LobbyRead]);
+        assert!(scopes.allows(Scope::LobbyRead));
+        assert!(!scopes.allows(Scope::LobbyWrite));
+        assert!(Scopes::All.allows(Scope::LobbyWrite));
+    }
+}
@@ -0,0 +1,222 @@
+use std::time::{Duration, SystemTime};
+
+use diesel::prelude::*;
+use serde::Serialize;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::db::{DbConn, DbPool};
+use crate::model::{
+    access_token::{self, AccessToken},
+    friendship::{self, Friend},
+    identity::{self, Identity},
+    lobby::{self, Lobby},
+    mfa,
+    session::{self, Session},
+    user::{ErrorUser, User, UserPublic},
+};
+use crate::schema::{lobbies, sessions, users};
+use crate::service::time;
+
+/// How long a deleted account can still be restored by signing in
+pub const DELETION_GRACE_PERIOD: Duration = Duration::from_secs(60 * 60 * 24 * 30);
+
+// region: -- Account Deletion
+/// How recently a session must have started for signing in to count as confirming a deletion
+pub const REAUTH_WINDOW: Duration = Duration::from_secs(60 * 5);
+
+/// How a user confirms it's really them before deleting their account
+#[derive(Debug, Clone, Copy)]
+pub enum Reauth<'a> {
+    Password(&'a str),
+    /// A code from their authenticator app, or a recovery code. Only for accounts without a
+    /// password, which otherwise must be entered.
+    MfaCode(&'a str),
+    /// The session they are deleting from started within [`REAUTH_WINDOW`], e.g. straight back
+    /// from the OpenID Connect provider. Only for accounts without a password.
+    FreshSession(Uuid),
+}
+
+/// Delete a user's account once they have confirmed it's them. The account is only marked
+/// deleted, every session and access token is revoked, it leaves every lobby it was in, and
+/// [`purge_deleted`] removes it for good after the grace period. Returns when that will happen.
+pub async fn delete(
+    mut conn: DbConn,
+    user_id: i32,
+    reauth: Reauth<'_>,
+) -> Result<SystemTime, ErrorUser> {
+    let now = SystemTime::now();
+    let now_unix = time::now_unix().map_err(|e| ErrorUser::Db(e.to_string()))?;
+
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let user: User = users::table.find(user_id).for_update().first(conn)?;
+        if let Err(e) = check_reauth(conn, &user, reauth, now, now_unix)? {
+            return Ok(Err(e));
+        }
+
+        diesel::update(users::table.find(user_id))
+            .set(users::deleted_at.eq(now))
+            .execute(conn)?;
+        let sessions = session::revoke_all(conn, user_id, None)?;
+        let tokens = access_token::revoke_all(conn, user_id)?;
+        // Other members shouldn't wait out the grace period for a new host
+        let lobbies = lobby::leave_all(conn, user_id)?;
+
+        debug!(
+            "🗑️  User {user_id} deleted, revoked {sessions} sessions and {tokens} tokens, \
+             left {lobbies} lobbies"
+        );
+        Ok(Ok(now + DELETION_GRACE_PERIOD))
+    })
+    .map_err(|e| ErrorUser::Db(e.to_string()))?
+}
+
+fn check_reauth(
+    conn: &mut PgConnection,
+    user: &User,
+    reauth: Reauth,
+    now: SystemTime,
+    now_unix: u64,
+) -> Result<Result<(), ErrorUser>, diesel::result::Error> {
+    match reauth {
+        Reauth::Password(password) => match user.verify_password(password).is_valid() {
+            true => Ok(Ok(())),
+            false => Ok(Err(ErrorUser::WrongPassword)),
+        },
+        Reauth::MfaCode(_) | Reauth::FreshSession(_) if user.has_password() => {
+            Ok(Err(ErrorUser::ReauthRequired))
+        }
+        Reauth::MfaCode(code) => {
+            Ok(mfa::check_code(conn, user.id, code, now_unix)?.map_err(|_| ErrorUser::WrongCode))
+        }
+        Reauth::FreshSession(session_id) => {
+            let started: Option<SystemTime> = sessions::table
+                .find(session_id)
+                .filter(sessions::user_id.eq(user.id))
+                .filter(sessions::revoked_at.is_null())
+                .select(sessions::created_at)
+                .first(conn)
+                .optional()?;
+            let fresh = started
+                .and_then(|started| now.duration_since(started).ok())
+                .is_some_and(|age| age <= REAUTH_WINDOW);
+            match fresh {
+                true => Ok(Ok(())),
+                false => Ok(Err(ErrorUser::ReauthRequired)),
+            }
+        }
+    }
+}
+
+/// Undo a deletion that is still in its grace period, returning whether there was one
+pub async fn restore(mut conn: DbConn, user_id: i32) -> Result<bool, ErrorUser> {
+    let restored = diesel::update(
+        users::table
+            .find(user_id)
+            .filter(users::deleted_at.is_not_null()),
+    )
+    .set(users::deleted_at.eq(None::<SystemTime>))
+    .execute(&mut conn)
+    .map_err(|e| ErrorUser::Db(e.to_string()))?;
+
+    if restored > 0 {
+        info!("♻️  User {user_id} signed in and restored their deleted account");
+    }
+    Ok(restored > 0)
+}
+
+/// Remove accounts deleted before `before` along with everything that belongs to them,
+/// returning how many were purged
+pub async fn purge_deleted(mut conn: DbConn, before: SystemTime) -> Result<usize, ErrorUser> {
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let user_ids: Vec<i32> = users::table
+            .filter(users::deleted_at.le(before))
+            .select(users::id)
+            .for_update()
+            .load(conn)?;
+        // Lobbies they still own are handed over first, the foreign key won't let them go
+        // with their owner
+        for &user_id in &user_ids {
+            lobby::leave_all(conn, user_id)?;
+        }
+        diesel::delete(users::table.filter(users::id.eq_any(&user_ids))).execute(conn)
+    })
+    .map_err(|e| ErrorUser::Db(e.to_string()))
+}
+
+/// Keep purging accounts whose grace period is over in the background
+pub fn spawn_purge(db_pool: DbPool, every: Duration) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(every);
+        loop {
+            interval.tick().await;
+            let purged = match db_pool.get() {
+                Ok(conn) => purge_deleted(conn, SystemTime::now() - DELETION_GRACE_PERIOD).await,
+                Err(e) => Err(ErrorUser::Db(e.to_string())),
+            };
+            match purged {
+                Ok(0) => {}
+                Ok(purged) => info!("🗑️  Purged {purged} deleted accounts"),
+                Err(e) => warn!("⚠️  Failed to purge deleted accounts: {e}"),
+            }
+        }
+    })
+}
+// endregion
+
+// region: -- Account Export
+/// Everything stored about a user, for them to take with them
+#[derive(Debug, Serialize)]
+pub struct AccountExport {
+    pub exported_at: SystemTime,
+    pub profile: UserPublic,
+    pub has_password: bool,
+    pub mfa_enabled: bool,
+    pub deleted_at: Option<SystemTime>,
+    /// Every session, including ones that have ended
+    pub sessions: Vec<Session>,
+    pub identities: Vec<Identity>,
+    pub access_tokens: Vec<AccessToken>,
+    /// Lobbies the user owns
+    pub lobbies: Vec<Lobby>,
+    pub friends: Vec<Friend>,
+}
+
+/// Read in one transaction, so the parts of the export agree with each other
+pub async fn export(mut conn: DbConn, user_id: i32) -> Result<AccountExport, ErrorUser> {
+    conn.build_transaction()
+        .read_only()
+        .repeatable_read()
+        .run::<_, diesel::result::Error, _>(|conn| {
+            let Some(user) = users::table.find(user_id).first::<User>(conn).optional()? else {
+                return Ok(Err(ErrorUser::NotFound));
+            };
+            let mfa_enabled = match mfa::is_enabled_conn(conn, user_id) {
+                Ok(enabled) => enabled,
+                Err(e) => return Ok(Err(ErrorUser::Db(e.to_string()))),
+            };
+
+            Ok(Ok(AccountExport {
+                exported_at: SystemTime::now(),
+                has_password: user.has_password(),
+                mfa_enabled,
+                deleted_at: user.deleted_at,
+                sessions: sessions::table
+                    .filter(sessions::user_id.eq(user_id))
+                    .order(sessions::created_at)
+                    .select(Session::as_select())
+                    .load(conn)?,
+                identities: identity::list_conn(conn, user_id)?,
+                access_tokens: access_token::list_conn(conn, user_id)?,
+                lobbies: lobbies::table
+                    .filter(lobbies::owner_id.eq(user_id))
+                    .order(lobbies::id)
+                    .select(Lobby::as_select())
+                    .load(conn)?,
+                friends: friendship::list_conn(conn, user_id)?,
+                profile: user.into(),
+            }))
+        })
+        .map_err(|e| ErrorUser::Db(e.to_string()))?
+}
+// endregion
@@ -0,0 +1,288 @@
+use std::{
+    fmt,
+    io::Write,
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+
+use diesel::{
+    deserialize::{self, FromSql, FromSqlRow},
+    expression::AsExpression,
+    pg::{Pg, PgValue},
+    prelude::*,
+    serialize::{self, IsNull, Output, ToSql},
+    sql_types::Text,
+};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::db::DbConn;
+use crate::schema::audit_events;
+
+pub const DEFAULT_LIMIT: i64 = 50;
+pub const MAX_LIMIT: i64 = 200;
+const USER_AGENT_MAX_CHARS: usize = 512;
+
+// region: -- Audit Kinds
+/// What happened
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, AsExpression, FromSqlRow)]
+#[diesel(sql_type = Text)]
+pub enum AuditKind {
+    LoginSucceeded,
+    LoginFailed,
+    Registered,
+    EmailChanged,
+    PasswordChanged,
+    PasswordReset,
+    SessionRevoked,
+    AccessTokenRevoked,
+    RoleChanged,
+    LockoutCleared,
+    ImpersonationStarted,
+    /// Any request made with an impersonation token
+    ImpersonatedRequest,
+    LoggedOut,
+    /// A refresh token was presented twice, so the session it belongs to was revoked
+    RefreshTokenReused,
+    AccountDeleted,
+    MfaEnabled,
+    MfaDisabled,
+    /// An admin changed a lobby someone else owns
+    LobbyUpdatedByAdmin,
+    LobbyDeletedByAdmin,
+}
+
+impl AuditKind {
+    pub const ALL: [AuditKind; 19] = [
+        AuditKind::LoginSucceeded,
+        AuditKind::LoginFailed,
+        AuditKind::Registered,
+        AuditKind::EmailChanged,
+        AuditKind::PasswordChanged,
+        AuditKind::PasswordReset,
+        AuditKind::SessionRevoked,
+        AuditKind::AccessTokenRevoked,
+        AuditKind::RoleChanged,
+        AuditKind::LockoutCleared,
+        AuditKind::ImpersonationStarted,
+        AuditKind::ImpersonatedRequest,
+        AuditKind::LoggedOut,
+        AuditKind::RefreshTokenReused,
+        AuditKind::AccountDeleted,
+        AuditKind::MfaEnabled,
+        AuditKind::MfaDisabled,
+        AuditKind::LobbyUpdatedByAdmin,
+        AuditKind::LobbyDeletedByAdmin,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditKind::LoginSucceeded => "login_succeeded",
+            AuditKind::LoginFailed => "login_failed",
+            AuditKind::Registered => "registered",
+            AuditKind::EmailChanged => "email_changed",
+            AuditKind::PasswordChanged => "password_changed",
+            AuditKind::PasswordReset => "password_reset",
+            AuditKind::SessionRevoked => "session_revoked",
+            AuditKind::AccessTokenRevoked => "access_token_revoked",
+            AuditKind::RoleChanged => "role_changed",
+            AuditKind::LockoutCleared => "lockout_cleared",
+            AuditKind::ImpersonationStarted => "impersonation_started",
+            AuditKind::ImpersonatedRequest => "impersonated_request",
+            AuditKind::LoggedOut => "logged_out",
+            AuditKind::RefreshTokenReused => "refresh_token_reused",
+            AuditKind::AccountDeleted => "account_deleted",
+            AuditKind::MfaEnabled => "mfa_enabled",
+            AuditKind::MfaDisabled => "mfa_disabled",
+            AuditKind::LobbyUpdatedByAdmin => "lobby_updated_by_admin",
+            AuditKind::LobbyDeletedByAdmin => "lobby_deleted_by_admin",
+        }
+    }
+}
+
+impl fmt::Display for AuditKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, thiserror::Error, PartialEq)]
+#[error("Unknown audit event kind '{0}'")]
+pub struct ErrorUnknownAuditKind(String);
+
+impl FromStr for AuditKind {
+    type Err = ErrorUnknownAuditKind;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        AuditKind::ALL
+            .into_iter()
+            .find(|kind| kind.as_str() == s)
+            .ok_or_else(|| ErrorUnknownAuditKind(s.to_string()))
+    }
+}
+
+impl Serialize for AuditKind {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for AuditKind {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+impl ToSql<Text, Pg> for AuditKind {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Text, Pg> for AuditKind {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        Ok(std::str::from_utf8(bytes.as_bytes())?.parse()?)
+    }
+}
+// endregion
+
+// region: -- Audit Types
+#[derive(Debug, Clone, PartialEq, Serialize, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::audit_events)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct AuditEvent {
+    pub id: i64,
+    pub kind: AuditKind,
+    /// Who did it, if anyone was signed in or is known
+    pub actor_id: Option<i32>,
+    /// Whose account it happened to
+    pub target_id: Option<i32>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub request_id: Uuid,
+    pub detail: Value,
+    pub created_at: SystemTime,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = crate::schema::audit_events)]
+pub struct AuditEventNew {
+    pub kind: AuditKind,
+    pub actor_id: Option<i32>,
+    pub target_id: Option<i32>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub request_id: Uuid,
+    pub detail: Value,
+}
+
+/// Which events to find, newest first. Every field left empty matches everything.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AuditFilter {
+    pub kind: Option<AuditKind>,
+    pub actor_id: Option<i32>,
+    pub target_id: Option<i32>,
+    /// Events where the user is either the actor or the target
+    pub user_id: Option<i32>,
+    pub ip: Option<String>,
+    pub request_id: Option<Uuid>,
+    /// Seconds since the Unix epoch
+    pub since: Option<u64>,
+    /// Seconds since the Unix epoch
+    pub until: Option<u64>,
+    /// Only events older than this id, to page through results
+    pub before: Option<i64>,
+    pub limit: Option<i64>,
+}
+// endregion
+
+// region: -- Audit Controller
+#[derive(Debug, thiserror::Error, PartialEq, Clone, Serialize)]
+pub enum ErrorAudit {
+    #[error("{0}")]
+    Db(String),
+}
+
+impl From<diesel::result::Error> for ErrorAudit {
+    fn from(e: diesel::result::Error) -> Self {
+        ErrorAudit::Db(e.to_string())
+    }
+}
+
+pub async fn record(mut conn: DbConn, event: AuditEventNew) -> Result<AuditEvent, ErrorAudit> {
+    let event = AuditEventNew {
+        user_agent: event
+            .user_agent
+            .map(|user_agent| user_agent.chars().take(USER_AGENT_MAX_CHARS).collect()),
+        ..event
+    };
+
+    Ok(diesel::insert_into(audit_events::table)
+        .values(event)
+        .returning(AuditEvent::as_returning())
+        .get_result(&mut conn)?)
+}
+
+pub async fn query(mut conn: DbConn, filter: &AuditFilter) -> Result<Vec<AuditEvent>, ErrorAudit> {
+    let mut query = audit_events::table
+        .select(AuditEvent::as_select())
+        .order(audit_events::id.desc())
+        .limit(filter.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT))
+        .into_boxed();
+
+    if let Some(kind) = filter.kind {
+        query = query.filter(audit_events::kind.eq(kind));
+    }
+    if let Some(actor_id) = filter.actor_id {
+        query = query.filter(audit_events::actor_id.eq(actor_id));
+    }
+    if let Some(target_id) = filter.target_id {
+        query = query.filter(audit_events::target_id.eq(target_id));
+    }
+    if let Some(user_id) = filter.user_id {
+        query = query.filter(
+            audit_events::actor_id
+                .eq(user_id)
+                .or(audit_events::target_id.eq(user_id)),
+        );
+    }
+    if let Some(ip) = &filter.ip {
+        query = query.filter(audit_events::ip.eq(ip));
+    }
+    if let Some(request_id) = filter.request_id {
+        query = query.filter(audit_events::request_id.eq(request_id));
+    }
+    if let Some(since) = filter.since {
+        query = query.filter(audit_events::created_at.ge(unix_time(since)));
+    }
+    if let Some(until) = filter.until {
+        query = query.filter(audit_events::created_at.lt(unix_time(until)));
+    }
+    if let Some(before) = filter.before {
+        query = query.filter(audit_events::id.lt(before));
+    }
+
+    Ok(query.load(&mut conn)?)
+}
+
+fn unix_time(secs: u64) -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+}
+// endregion
+
+#[cfg(test)]
+mod tests {
+    use super::AuditKind;
+
+    #[test]
+    fn kind_round_trip() {
+        for kind in AuditKind::ALL {
+            assert_eq!(kind.as_str().parse::<AuditKind>().unwrap(), kind);
+        }
+        assert!("logged_in".parse::<AuditKind>().is_err());
+    }
+}
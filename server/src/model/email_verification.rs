@@ -0,0 +1,85 @@
+use std::time::{Duration, SystemTime};
+
+use diesel::prelude::*;
+
+use crate::db::DbConn;
+use crate::model::user::{ErrorUser, User};
+use crate::schema::{email_verification_tokens, users};
+use crate::service::crypto;
+
+const VERIFICATION_TOKEN_BYTES: usize = 32;
+pub const VERIFICATION_TOKEN_LIFETIME: Duration = Duration::from_secs(60 * 60 * 24);
+
+#[derive(Debug, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::email_verification_tokens)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+struct VerificationToken {
+    id: i32,
+    user_id: i32,
+    email: String,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::email_verification_tokens)]
+struct VerificationTokenForInsert {
+    user_id: i32,
+    email: String,
+    token_hash: Vec<u8>,
+    expires_at: SystemTime,
+}
+
+/// Create a token proving ownership of the user's current email address
+pub async fn create_token(mut conn: DbConn, user: &User) -> Result<String, ErrorUser> {
+    if user.email_verified_at.is_some() {
+        return Err(ErrorUser::EmailAlreadyVerified);
+    }
+
+    let token = crypto::token(VERIFICATION_TOKEN_BYTES);
+    diesel::insert_into(email_verification_tokens::table)
+        .values(VerificationTokenForInsert {
+            user_id: user.id,
+            email: user.email.clone(),
+            token_hash: crypto::token_hash(&token),
+            expires_at: SystemTime::now() + VERIFICATION_TOKEN_LIFETIME,
+        })
+        .execute(&mut conn)
+        .map_err(|e| ErrorUser::Db(e.to_string()))?;
+
+    Ok(token)
+}
+
+/// Mark the email address a token was sent to as verified. Each token works once.
+pub async fn verify(mut conn: DbConn, token: &str) -> Result<User, ErrorUser> {
+    let token_hash = crypto::token_hash(token);
+    let now = SystemTime::now();
+
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let Some(stored) = email_verification_tokens::table
+            .filter(email_verification_tokens::token_hash.eq(&token_hash))
+            .filter(email_verification_tokens::used_at.is_null())
+            .filter(email_verification_tokens::expires_at.gt(now))
+            .select(VerificationToken::as_select())
+            .for_update()
+            .first(conn)
+            .optional()?
+        else {
+            return Ok(Err(ErrorUser::InvalidToken));
+        };
+
+        diesel::update(email_verification_tokens::table.find(stored.id))
+            .set(email_verification_tokens::used_at.eq(now))
+            .execute(conn)?;
+
+        // The user changed their address after this token was sent
+        let user: User = users::table.find(stored.user_id).first(conn)?;
+        if !user.email.eq_ignore_ascii_case(&stored.email) {
+            return Ok(Err(ErrorUser::InvalidToken));
+        }
+
+        let user = diesel::update(users::table.find(user.id))
+            .set(users::email_verified_at.eq(now))
+            .get_result::<User>(conn)?;
+        Ok(Ok(user))
+    })
+    .map_err(|e| ErrorUser::Db(e.to_string()))?
+}
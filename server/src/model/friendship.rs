@@ -0,0 +1,121 @@
+use std::{collections::HashSet, time::SystemTime};
+
+use diesel::{
+    prelude::*,
+    result::{DatabaseErrorKind, Error::DatabaseError},
+};
+use serde::Serialize;
+
+use crate::db::DbConn;
+use crate::schema::{friendships, users};
+
+// region: -- Friendship Types
+/// Someone a user counts as a friend
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Friend {
+    pub user_id: i32,
+    /// Only once they count the user as a friend too, so adding ids one after another can't be
+    /// used to look people up
+    pub display_name: Option<String>,
+    /// When they were added
+    pub created_at: SystemTime,
+}
+// endregion
+
+// region: -- Friendship Controller
+#[derive(Debug, thiserror::Error, PartialEq, Clone, Serialize)]
+pub enum ErrorFriendship {
+    #[error("{0}")]
+    Db(String),
+
+    #[error("User not found")]
+    NotFound,
+
+    #[error("You can't add yourself as a friend")]
+    SelfFriend,
+}
+
+impl From<diesel::result::Error> for ErrorFriendship {
+    fn from(e: diesel::result::Error) -> Self {
+        ErrorFriendship::Db(e.to_string())
+    }
+}
+
+/// Count `friend_id` as a friend of `user_id`. Friendship is one way: it is up to each user
+/// who sees their friends only lobbies. Returns whether they weren't a friend already.
+pub async fn add(mut conn: DbConn, user_id: i32, friend_id: i32) -> Result<bool, ErrorFriendship> {
+    if user_id == friend_id {
+        return Err(ErrorFriendship::SelfFriend);
+    }
+
+    let friend_exists: bool = diesel::select(diesel::dsl::exists(
+        users::table
+            .find(friend_id)
+            .filter(users::deleted_at.is_null()),
+    ))
+    .get_result(&mut conn)?;
+    if !friend_exists {
+        return Err(ErrorFriendship::NotFound);
+    }
+
+    let added = diesel::insert_into(friendships::table)
+        .values((
+            friendships::user_id.eq(user_id),
+            friendships::friend_id.eq(friend_id),
+        ))
+        .on_conflict_do_nothing()
+        .execute(&mut conn)
+        .map_err(|e| match e {
+            // Deleted between the check and the insert
+            DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _) => ErrorFriendship::NotFound,
+            e => e.into(),
+        })?;
+    Ok(added > 0)
+}
+
+pub async fn remove(mut conn: DbConn, user_id: i32, friend_id: i32) -> Result<(), ErrorFriendship> {
+    match diesel::delete(friendships::table.find((user_id, friend_id))).execute(&mut conn)? {
+        0 => Err(ErrorFriendship::NotFound),
+        _ => Ok(()),
+    }
+}
+
+pub async fn list(mut conn: DbConn, user_id: i32) -> Result<Vec<Friend>, ErrorFriendship> {
+    Ok(list_conn(&mut conn, user_id)?)
+}
+
+/// Oldest first. Ordering by name would give away where hidden names fall.
+pub(crate) fn list_conn(
+    conn: &mut PgConnection,
+    user_id: i32,
+) -> Result<Vec<Friend>, diesel::result::Error> {
+    let mutual = friended_by(conn, user_id)?;
+    let friends: Vec<(i32, String, SystemTime)> = friendships::table
+        .inner_join(users::table.on(users::id.eq(friendships::friend_id)))
+        .filter(friendships::user_id.eq(user_id))
+        .order((friendships::created_at, users::id))
+        .select((users::id, users::display_name, friendships::created_at))
+        .load(conn)?;
+    Ok(friends
+        .into_iter()
+        .map(|(user_id, display_name, created_at)| Friend {
+            user_id,
+            display_name: mutual.contains(&user_id).then_some(display_name),
+            created_at,
+        })
+        .collect())
+}
+
+/// Everyone who counts `user_id` as a friend
+pub(crate) fn friended_by(
+    conn: &mut PgConnection,
+    user_id: i32,
+) -> Result<HashSet<i32>, diesel::result::Error> {
+    Ok(friendships::table
+        .filter(friendships::friend_id.eq(user_id))
+        .select(friendships::user_id)
+        .load::<i32>(conn)?
+        .into_iter()
+        .collect())
+}
+// endregion
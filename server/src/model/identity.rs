@@ -0,0 +1,233 @@
+use std::time::SystemTime;
+
+use diesel::{
+    prelude::*,
+    result::{DatabaseErrorKind, Error::DatabaseError},
+};
+use rand::Rng;
+use serde::Serialize;
+use tracing::debug;
+
+use crate::db::DbConn;
+use crate::model::user::{self, ErrorUser, User};
+use crate::schema::{user_identities, users};
+use crate::service::display_name;
+use crate::service::oidc::OidcProfile;
+
+// region: -- Identity Types
+/// An account at an identity provider that can sign in as a user
+#[derive(Debug, Clone, PartialEq, Serialize, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::user_identities)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Identity {
+    pub id: i32,
+    #[serde(skip)]
+    pub user_id: i32,
+    pub provider: String,
+    #[serde(skip)]
+    pub subject: String,
+    pub email: Option<String>,
+    pub created_at: SystemTime,
+    pub last_login_at: Option<SystemTime>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::user_identities)]
+struct IdentityForInsert<'a> {
+    user_id: i32,
+    provider: &'a str,
+    subject: &'a str,
+    email: Option<&'a str>,
+    last_login_at: Option<SystemTime>,
+}
+// endregion
+
+// region: -- Identity Controller
+#[derive(Debug, thiserror::Error, PartialEq, Clone, Serialize)]
+pub enum ErrorIdentity {
+    #[error("{0}")]
+    Db(String),
+
+    #[error("Linked account not found")]
+    NotFound,
+
+    #[error("The identity provider did not share a valid email address")]
+    EmailRequired,
+
+    #[error("The identity provider has not verified your email address")]
+    EmailUnverified,
+
+    #[error(
+        "An account with this email already exists, log in and link the provider from your profile"
+    )]
+    EmailInUse,
+
+    #[error("That {0} account is already linked to another user")]
+    IdentityInUse(String),
+
+    #[error("An account from {0} is already linked")]
+    ProviderAlreadyLinked(String),
+
+    #[error("Set a password or link another provider before unlinking your only way to sign in")]
+    LastSignInMethod,
+}
+
+impl From<diesel::result::Error> for ErrorIdentity {
+    fn from(e: diesel::result::Error) -> Self {
+        ErrorIdentity::Db(e.to_string())
+    }
+}
+
+/// Sign in through a provider: the user linked to the identity, or a new user when it has
+/// never been seen. An email address that already has an account is never taken over, its
+/// owner has to link the provider while signed in. New users need an address the provider
+/// has verified, or anyone could register someone else's email at a lax provider.
+pub async fn login(mut conn: DbConn, profile: &OidcProfile) -> Result<User, ErrorIdentity> {
+    let now = SystemTime::now();
+
+    // Nothing is kept when this fails, so errors can simply roll back
+    conn.transaction::<_, ErrorIdentity, _>(|conn| {
+        if let Some(identity) = find(conn, profile)? {
+            diesel::update(user_identities::table.find(identity.id))
+                .set((
+                    user_identities::email.eq(&profile.email),
+                    user_identities::last_login_at.eq(now),
+                ))
+                .execute(conn)?;
+            return Ok(users::table.find(identity.user_id).first::<User>(conn)?);
+        }
+
+        let email = profile
+            .email
+            .as_deref()
+            .ok_or(ErrorIdentity::EmailRequired)?;
+        if !profile.email_verified {
+            return Err(ErrorIdentity::EmailUnverified);
+        }
+        let user = user::create_without_password(conn, &display_name(profile, email), email, true)
+            .map_err(|e| match e {
+                ErrorUser::EmailAlreadyExists => ErrorIdentity::EmailInUse,
+                ErrorUser::InvalidEmail => ErrorIdentity::EmailRequired,
+                e => ErrorIdentity::Db(e.to_string()),
+            })?;
+
+        insert(conn, user.id, profile, Some(now))?;
+        debug!(
+            "🪪 User {} registered through {}",
+            user.id, profile.provider
+        );
+        Ok(user)
+    })
+}
+
+/// Link a provider to a signed in user
+pub async fn link(
+    mut conn: DbConn,
+    user_id: i32,
+    profile: &OidcProfile,
+) -> Result<Identity, ErrorIdentity> {
+    conn.transaction::<_, ErrorIdentity, _>(|conn| {
+        if let Some(identity) = find(conn, profile)? {
+            return match identity.user_id == user_id {
+                true => Ok(identity),
+                false => Err(ErrorIdentity::IdentityInUse(profile.provider.clone())),
+            };
+        }
+
+        let identity = insert(conn, user_id, profile, None).map_err(|e| match e {
+            // One account per provider per user
+            DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                ErrorIdentity::ProviderAlreadyLinked(profile.provider.clone())
+            }
+            e => e.into(),
+        })?;
+        debug!("🪪 User {user_id} linked {}", profile.provider);
+        Ok(identity)
+    })
+}
+
+/// Every provider linked to a user
+pub async fn list(mut conn: DbConn, user_id: i32) -> Result<Vec<Identity>, ErrorIdentity> {
+    Ok(list_conn(&mut conn, user_id)?)
+}
+
+pub(crate) fn list_conn(
+    conn: &mut PgConnection,
+    user_id: i32,
+) -> Result<Vec<Identity>, diesel::result::Error> {
+    user_identities::table
+        .filter(user_identities::user_id.eq(user_id))
+        .order(user_identities::created_at)
+        .select(Identity::as_select())
+        .load(conn)
+}
+
+/// Unlink a provider, as long as the user still has another way to sign in
+pub async fn unlink(mut conn: DbConn, user_id: i32, id: i32) -> Result<(), ErrorIdentity> {
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let user = users::table
+            .find(user_id)
+            .for_update()
+            .first::<User>(conn)?;
+        let linked: Vec<i32> = user_identities::table
+            .filter(user_identities::user_id.eq(user_id))
+            .select(user_identities::id)
+            .load(conn)?;
+
+        if !linked.contains(&id) {
+            return Ok(Err(ErrorIdentity::NotFound));
+        }
+        if linked.len() == 1 && !user.has_password() {
+            return Ok(Err(ErrorIdentity::LastSignInMethod));
+        }
+
+        diesel::delete(user_identities::table.find(id)).execute(conn)?;
+        debug!("🪪 User {user_id} unlinked identity {id}");
+        Ok(Ok(()))
+    })?
+}
+
+fn find(
+    conn: &mut PgConnection,
+    profile: &OidcProfile,
+) -> Result<Option<Identity>, diesel::result::Error> {
+    user_identities::table
+        .filter(user_identities::provider.eq(&profile.provider))
+        .filter(user_identities::subject.eq(&profile.subject))
+        .select(Identity::as_select())
+        .for_update()
+        .first(conn)
+        .optional()
+}
+
+fn insert(
+    conn: &mut PgConnection,
+    user_id: i32,
+    profile: &OidcProfile,
+    last_login_at: Option<SystemTime>,
+) -> Result<Identity, diesel::result::Error> {
+    diesel::insert_into(user_identities::table)
+        .values(IdentityForInsert {
+            user_id,
+            provider: &profile.provider,
+            subject: &profile.subject,
+            email: profile.email.as_deref(),
+            last_login_at,
+        })
+        .returning(Identity::as_returning())
+        .get_result(conn)
+}
+
+/// The name the provider knows the user by, or the start of their email address. When neither
+/// makes an acceptable display name the user gets a generated one they can change later.
+fn display_name(profile: &OidcProfile, email: &str) -> String {
+    let local_part = email.split('@').next().unwrap_or(email);
+    profile
+        .name
+        .as_deref()
+        .into_iter()
+        .chain([local_part])
+        .find_map(|name| display_name::normalize(name).ok())
+        .unwrap_or_else(|| format!("player-{:06}", rand::thread_rng().gen_range(0..1_000_000)))
+}
+// endregion
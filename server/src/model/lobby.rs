@@ -1,94 +1,904 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    collections::HashSet,
+    fmt,
+    io::Write,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
 
+use diesel::{
+    deserialize::{self, FromSql, FromSqlRow},
+    expression::AsExpression,
+    pg::{Pg, PgValue},
+    prelude::*,
+    r2d2::PoolError,
+    result::{DatabaseErrorKind, Error::DatabaseError},
+    serialize::{self, IsNull, Output, ToSql},
+};
 use serde::{Deserialize, Serialize};
-use std::time::{SystemTime, UNIX_EPOCH};
 use thiserror::Error;
 
-#[derive(Debug, Error)]
+use crate::db::DbPool;
+use crate::model::{friendship, role::Role};
+use crate::schema::{friendships, lobbies, lobby_members, sql_types::LobbyVisibility};
+use crate::service::invite_code;
+
+pub const MAX_NAME_CHARS: usize = 64;
+pub const DEFAULT_MAX_PLAYERS: i32 = 8;
+pub const MIN_PLAYERS: i32 = 2;
+pub const MAX_PLAYERS: i32 = 64;
+/// New codes to try when the one generated is already in use
+const INVITE_CODE_ATTEMPTS: usize = 5;
+
+#[derive(Debug, Error, Clone, PartialEq, Serialize)]
 pub enum ErrorLobby {
     #[error("Lobby not found")]
     NotFound,
 
+    #[error("Only the lobby's owner can do this")]
+    Forbidden,
+
+    #[error("Lobby name must be 1 to {MAX_NAME_CHARS} characters")]
+    InvalidName,
+
+    #[error("Lobbies hold {MIN_PLAYERS} to {MAX_PLAYERS} players")]
+    InvalidMaxPlayers,
+
+    #[error("Lobby is full")]
+    Full,
+
+    #[error("Already a member of this lobby")]
+    AlreadyMember,
+
+    #[error("Not a member of this lobby")]
+    NotMember,
+
+    #[error("This lobby can only be joined with an invite")]
+    InviteRequired,
+
+    #[error("Invite code not found or expired")]
+    InviteNotFound,
+
+    #[error("Invite code already in use")]
+    InviteCodeTaken,
+
+    #[error("{0}")]
+    Db(String),
+
     #[error("Internal server error")]
     Internal,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+impl From<diesel::result::Error> for ErrorLobby {
+    fn from(e: diesel::result::Error) -> Self {
+        ErrorLobby::Db(e.to_string())
+    }
+}
+
+impl From<PoolError> for ErrorLobby {
+    fn from(e: PoolError) -> Self {
+        ErrorLobby::Db(e.to_string())
+    }
+}
+
+// region: Lobby Types
+#[derive(
+    Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, AsExpression, FromSqlRow,
+)]
 #[serde(rename_all = "lowercase")]
+#[diesel(sql_type = LobbyVisibility)]
 pub enum Visibility {
     Public,
     Friends,
     Private,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+impl Visibility {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Visibility::Public => "public",
+            Visibility::Friends => "friends",
+            Visibility::Private => "private",
+        }
+    }
+}
+
+impl fmt::Display for Visibility {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, thiserror::Error, PartialEq)]
+#[error("Unknown lobby visibility '{0}'")]
+pub struct ErrorUnknownVisibility(String);
+
+impl FromStr for Visibility {
+    type Err = ErrorUnknownVisibility;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "public" => Ok(Visibility::Public),
+            "friends" => Ok(Visibility::Friends),
+            "private" => Ok(Visibility::Private),
+            _ => Err(ErrorUnknownVisibility(s.to_string())),
+        }
+    }
+}
+
+impl ToSql<LobbyVisibility, Pg> for Visibility {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<LobbyVisibility, Pg> for Visibility {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        Ok(std::str::from_utf8(bytes.as_bytes())?.parse()?)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::lobbies)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
 pub struct Lobby {
     pub id: i32,
+    pub owner_id: i32,
     pub name: String,
     pub visibility: Visibility,
-    pub created_at: u64,
+    pub max_players: i32,
+    pub created_at: SystemTime,
+    /// Only shown to members, see [`LobbyDetails`]
+    #[serde(skip)]
+    pub invite_code: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::lobby_members)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct LobbyMember {
+    pub user_id: i32,
+    pub joined_at: SystemTime,
 }
 
-#[derive(Debug, Deserialize)]
+/// A lobby with its roster, longest standing member first
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct LobbyDetails {
+    #[serde(flatten)]
+    pub lobby: Lobby,
+    pub members: Vec<LobbyMember>,
+    /// For members and whoever manages the lobby to share
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub invite_code: Option<String>,
+}
+
+impl Lobby {
+    /// Whether this lobby shows up when `user_id` lists lobbies. Public ones always do, friends
+    /// only ones for their owner and whoever the owner counts as a friend, and private ones never.
+    /// `friended_by` is everyone who counts `user_id` as a friend.
+    fn is_listed_for(&self, user_id: i32, friended_by: &HashSet<i32>) -> bool {
+        match self.visibility {
+            Visibility::Public => true,
+            Visibility::Friends => self.owner_id == user_id || friended_by.contains(&self.owner_id),
+            Visibility::Private => false,
+        }
+    }
+}
+
+impl LobbyDetails {
+    pub fn is_member(&self, user_id: i32) -> bool {
+        self.members.iter().any(|member| member.user_id == user_id)
+    }
+}
+
+/// What became of a lobby when someone left it
+#[derive(Debug, Serialize, Clone, PartialEq)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum Departure {
+    Left,
+    /// The owner left and the longest standing member now hosts
+    HostTransferred {
+        owner_id: i32,
+    },
+    /// The last member left and the lobby is gone
+    Closed,
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct LobbyForCreate {
     pub name: String,
     pub visibility: Visibility,
+    #[serde(default = "default_max_players")]
+    pub max_players: i32,
+}
+
+fn default_max_players() -> i32 {
+    DEFAULT_MAX_PLAYERS
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = crate::schema::lobbies)]
+pub struct LobbyForInsert {
+    pub owner_id: i32,
+    pub name: String,
+    pub visibility: Visibility,
+    pub max_players: i32,
+}
+
+/// Fields left out are kept as they are
+#[derive(Debug, Clone, Default, Deserialize, AsChangeset)]
+#[diesel(table_name = crate::schema::lobbies)]
+pub struct LobbyForUpdate {
+    pub name: Option<String>,
+    pub visibility: Option<Visibility>,
+}
+
+/// Who is asking the controller for something
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LobbyActor {
+    pub user_id: i32,
+    pub role: Role,
+}
+
+impl LobbyActor {
+    /// Owners manage their own lobbies, admins manage everyone's
+    fn can_manage(&self, lobby: &Lobby) -> bool {
+        lobby.owner_id == self.user_id || self.role.has(Role::Admin)
+    }
+
+    /// Whether this user may look up a lobby by its id: one they could list, one they are in,
+    /// or any lobby for an admin
+    fn can_see(&self, details: &LobbyDetails, friended_by: &HashSet<i32>) -> bool {
+        details.lobby.is_listed_for(self.user_id, friended_by)
+            || self.role.has(Role::Admin)
+            || details.is_member(self.user_id)
+    }
+}
+
+/// Trim a lobby name and check its length, returning the form to store
+fn normalize_name(name: &str) -> Result<String, ErrorLobby> {
+    let name = name.trim();
+    match name.chars().count() {
+        1..=MAX_NAME_CHARS => Ok(name.to_string()),
+        _ => Err(ErrorLobby::InvalidName),
+    }
+}
+// endregion
+
+// region: Lobby Store
+/// Where lobbies are kept. [`PgLobbyStore`] in production, [`MemoryLobbyStore`] for tests
+/// that don't need a database.
+#[async_trait::async_trait]
+pub trait LobbyStore: fmt::Debug + Send + Sync {
+    /// Create a lobby with its owner as the only member
+    async fn create_lobby(&self, lobby: LobbyForInsert) -> Result<Lobby, ErrorLobby>;
+
+    async fn get_lobby(&self, id: i32) -> Result<Lobby, ErrorLobby>;
+
+    /// Every lobby `user_id` may list, see [`Lobby::is_listed_for`]
+    async fn get_lobbies(&self, user_id: i32) -> Result<Vec<Lobby>, ErrorLobby>;
+
+    async fn get_members(&self, id: i32) -> Result<Vec<LobbyMember>, ErrorLobby>;
+
+    /// Everyone who counts `user_id` as a friend, and so shares friends only lobbies with them
+    async fn friended_by(&self, user_id: i32) -> Result<HashSet<i32>, ErrorLobby>;
+
+    async fn update_lobby(&self, id: i32, update: LobbyForUpdate) -> Result<Lobby, ErrorLobby>;
+
+    async fn delete_lobby(&self, id: i32) -> Result<(), ErrorLobby>;
+
+    /// Replace a lobby's invite code, failing with [`ErrorLobby::InviteCodeTaken`] when another
+    /// lobby has it
+    async fn set_invite_code(&self, id: i32, code: &str) -> Result<(), ErrorLobby>;
+
+    async fn find_by_invite_code(&self, code: &str) -> Result<Lobby, ErrorLobby>;
+
+    /// Add a member, as long as there is room
+    async fn join_lobby(&self, id: i32, user_id: i32) -> Result<(), ErrorLobby>;
+
+    /// Remove a member, handing the lobby to the longest standing member when its owner leaves
+    /// and closing it when nobody is left
+    async fn leave_lobby(&self, id: i32, user_id: i32) -> Result<Departure, ErrorLobby>;
 }
 
-// region: Lobby Controller
 #[derive(Debug, Clone)]
-pub struct LobbyController {
-    lobbies: Arc<Mutex<Vec<Option<Lobby>>>>,
+pub struct PgLobbyStore {
+    db_pool: DbPool,
 }
 
-impl LobbyController {
-    pub async fn new() -> Result<Self, ErrorLobby> {
-        let lobbies = Arc::default();
-        Ok(Self { lobbies })
+impl PgLobbyStore {
+    pub fn new(db_pool: DbPool) -> Self {
+        Self { db_pool }
     }
+}
 
-    pub async fn create_lobby(
+/// Lock a lobby's row so membership changes to it happen one at a time
+fn lock_lobby(conn: &mut PgConnection, id: i32) -> Result<Lobby, ErrorLobby> {
+    lobbies::table
+        .find(id)
+        .select(Lobby::as_select())
+        .for_update()
+        .first(conn)
+        .optional()?
+        .ok_or(ErrorLobby::NotFound)
+}
+
+#[async_trait::async_trait]
+impl LobbyStore for PgLobbyStore {
+    async fn create_lobby(&self, lobby: LobbyForInsert) -> Result<Lobby, ErrorLobby> {
+        let mut conn = self.db_pool.get()?;
+        conn.transaction(|conn| {
+            let lobby = diesel::insert_into(lobbies::table)
+                .values(lobby)
+                .returning(Lobby::as_returning())
+                .get_result(conn)?;
+            diesel::insert_into(lobby_members::table)
+                .values((
+                    lobby_members::lobby_id.eq(lobby.id),
+                    lobby_members::user_id.eq(lobby.owner_id),
+                    lobby_members::joined_at.eq(lobby.created_at),
+                ))
+                .execute(conn)?;
+            Ok(lobby)
+        })
+    }
+
+    async fn get_lobby(&self, id: i32) -> Result<Lobby, ErrorLobby> {
+        let mut conn = self.db_pool.get()?;
+        lobbies::table
+            .find(id)
+            .select(Lobby::as_select())
+            .first(&mut conn)
+            .optional()?
+            .ok_or(ErrorLobby::NotFound)
+    }
+
+    async fn get_lobbies(&self, user_id: i32) -> Result<Vec<Lobby>, ErrorLobby> {
+        let mut conn = self.db_pool.get()?;
+        // The rules of `Lobby::is_listed_for`, without loading lobbies the user won't see
+        let friended_by = friendships::table
+            .filter(friendships::friend_id.eq(user_id))
+            .select(friendships::user_id);
+        Ok(lobbies::table
+            .filter(
+                lobbies::visibility
+                    .eq(Visibility::Public)
+                    .or(lobbies::visibility.eq(Visibility::Friends).and(
+                        lobbies::owner_id
+                            .eq(user_id)
+                            .or(lobbies::owner_id.eq_any(friended_by)),
+                    )),
+            )
+            .order(lobbies::id)
+            .select(Lobby::as_select())
+            .load(&mut conn)?)
+    }
+
+    async fn get_members(&self, id: i32) -> Result<Vec<LobbyMember>, ErrorLobby> {
+        let mut conn = self.db_pool.get()?;
+        Ok(lobby_members::table
+            .filter(lobby_members::lobby_id.eq(id))
+            .order((lobby_members::joined_at, lobby_members::user_id))
+            .select(LobbyMember::as_select())
+            .load(&mut conn)?)
+    }
+
+    async fn friended_by(&self, user_id: i32) -> Result<HashSet<i32>, ErrorLobby> {
+        let mut conn = self.db_pool.get()?;
+        Ok(friendship::friended_by(&mut conn, user_id)?)
+    }
+
+    async fn update_lobby(&self, id: i32, update: LobbyForUpdate) -> Result<Lobby, ErrorLobby> {
+        // Diesel refuses an empty changeset, and there is nothing to write anyway
+        if update.name.is_none() && update.visibility.is_none() {
+            return self.get_lobby(id).await;
+        }
+
+        let mut conn = self.db_pool.get()?;
+        diesel::update(lobbies::table.find(id))
+            .set(update)
+            .returning(Lobby::as_returning())
+            .get_result(&mut conn)
+            .optional()?
+            .ok_or(ErrorLobby::NotFound)
+    }
+
+    async fn delete_lobby(&self, id: i32) -> Result<(), ErrorLobby> {
+        let mut conn = self.db_pool.get()?;
+        match diesel::delete(lobbies::table.find(id)).execute(&mut conn)? {
+            0 => Err(ErrorLobby::NotFound),
+            _ => Ok(()),
+        }
+    }
+
+    async fn set_invite_code(&self, id: i32, code: &str) -> Result<(), ErrorLobby> {
+        let mut conn = self.db_pool.get()?;
+        let updated = diesel::update(lobbies::table.find(id))
+            .set(lobbies::invite_code.eq(code))
+            .execute(&mut conn)
+            .map_err(|e| match e {
+                DatabaseError(DatabaseErrorKind::UniqueViolation, _) => ErrorLobby::InviteCodeTaken,
+                e => e.into(),
+            })?;
+        match updated {
+            0 => Err(ErrorLobby::NotFound),
+            _ => Ok(()),
+        }
+    }
+
+    async fn find_by_invite_code(&self, code: &str) -> Result<Lobby, ErrorLobby> {
+        let mut conn = self.db_pool.get()?;
+        lobbies::table
+            .filter(lobbies::invite_code.eq(code))
+            .select(Lobby::as_select())
+            .first(&mut conn)
+            .optional()?
+            .ok_or(ErrorLobby::InviteNotFound)
+    }
+
+    async fn join_lobby(&self, id: i32, user_id: i32) -> Result<(), ErrorLobby> {
+        let mut conn = self.db_pool.get()?;
+        conn.transaction(|conn| {
+            let lobby = lock_lobby(conn, id)?;
+            let members = lobby_members::table.filter(lobby_members::lobby_id.eq(id));
+
+            let already_member = diesel::select(diesel::dsl::exists(
+                members.filter(lobby_members::user_id.eq(user_id)),
+            ))
+            .get_result(conn)?;
+            if already_member {
+                return Err(ErrorLobby::AlreadyMember);
+            }
+            let count: i64 = members.count().get_result(conn)?;
+            if count >= i64::from(lobby.max_players) {
+                return Err(ErrorLobby::Full);
+            }
+
+            diesel::insert_into(lobby_members::table)
+                .values((
+                    lobby_members::lobby_id.eq(id),
+                    lobby_members::user_id.eq(user_id),
+                ))
+                .execute(conn)?;
+            Ok(())
+        })
+    }
+
+    async fn leave_lobby(&self, id: i32, user_id: i32) -> Result<Departure, ErrorLobby> {
+        let mut conn = self.db_pool.get()?;
+        conn.transaction(|conn| {
+            let lobby = lock_lobby(conn, id)?;
+            remove_member(conn, &lobby, user_id)?.ok_or(ErrorLobby::NotMember)
+        })
+    }
+}
+
+/// Take a member out of a lobby locked with [`lock_lobby`], handing it over or closing it as
+/// [`LobbyStore::leave_lobby`] describes. `None` when they weren't a member.
+fn remove_member(
+    conn: &mut PgConnection,
+    lobby: &Lobby,
+    user_id: i32,
+) -> Result<Option<Departure>, diesel::result::Error> {
+    let left = diesel::delete(lobby_members::table.find((lobby.id, user_id))).execute(conn)?;
+    if left == 0 {
+        return Ok(None);
+    }
+
+    let next_host: Option<i32> = lobby_members::table
+        .filter(lobby_members::lobby_id.eq(lobby.id))
+        .order((lobby_members::joined_at, lobby_members::user_id))
+        .select(lobby_members::user_id)
+        .first(conn)
+        .optional()?;
+    let departure = match next_host {
+        None => {
+            diesel::delete(lobbies::table.find(lobby.id)).execute(conn)?;
+            Departure::Closed
+        }
+        Some(owner_id) if lobby.owner_id == user_id => {
+            diesel::update(lobbies::table.find(lobby.id))
+                .set(lobbies::owner_id.eq(owner_id))
+                .execute(conn)?;
+            Departure::HostTransferred { owner_id }
+        }
+        Some(_) => Departure::Left,
+    };
+    Ok(Some(departure))
+}
+
+/// Take a user out of every lobby they are in, as if they had left each one, e.g. when their
+/// account is deleted. Returns how many they left.
+pub(crate) fn leave_all(
+    conn: &mut PgConnection,
+    user_id: i32,
+) -> Result<usize, diesel::result::Error> {
+    // Locked in id order, so two of these can't deadlock on a lobby they share
+    let joined: Vec<Lobby> = lobbies::table
+        .filter(
+            lobbies::id.eq_any(
+                lobby_members::table
+                    .filter(lobby_members::user_id.eq(user_id))
+                    .select(lobby_members::lobby_id),
+            ),
+        )
+        .order(lobbies::id)
+        .select(Lobby::as_select())
+        .for_update()
+        .load(conn)?;
+    for lobby in &joined {
+        remove_member(conn, lobby, user_id)?;
+    }
+    Ok(joined.len())
+}
+
+#[derive(Debug)]
+struct MemoryLobby {
+    lobby: Lobby,
+    members: Vec<LobbyMember>,
+}
+
+/// Lobbies in a `Vec`, lost when the process exits. Ids start at 1, like the table's.
+#[derive(Debug, Default)]
+pub struct MemoryLobbyStore {
+    lobbies: Mutex<Vec<Option<MemoryLobby>>>,
+    /// `(user_id, friend_id)` pairs, as in the friendships table
+    friendships: HashSet<(i32, i32)>,
+}
+
+impl MemoryLobbyStore {
+    /// Have `user_id` count `friend_id` as a friend
+    pub fn with_friendship(mut self, user_id: i32, friend_id: i32) -> Self {
+        self.friendships.insert((user_id, friend_id));
+        self
+    }
+
+    fn index(id: i32) -> Option<usize> {
+        usize::try_from(id).ok()?.checked_sub(1)
+    }
+
+    fn find(lobbies: &mut [Option<MemoryLobby>], id: i32) -> Result<&mut MemoryLobby, ErrorLobby> {
+        Self::index(id)
+            .and_then(|index| lobbies.get_mut(index))
+            .and_then(Option::as_mut)
+            .ok_or(ErrorLobby::NotFound)
+    }
+}
+
+#[async_trait::async_trait]
+impl LobbyStore for MemoryLobbyStore {
+    async fn create_lobby(
         &self,
-        LobbyForCreate { name, visibility }: LobbyForCreate,
+        LobbyForInsert {
+            owner_id,
+            name,
+            visibility,
+            max_players,
+        }: LobbyForInsert,
     ) -> Result<Lobby, ErrorLobby> {
         let mut lobbies = self.lobbies.lock().map_err(|_| ErrorLobby::Internal)?;
-        let id = lobbies.len() as i32;
         let lobby = Lobby {
-            id,
+            id: lobbies.len() as i32 + 1,
+            owner_id,
             name,
             visibility,
-            created_at: SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .map_err(|_| {
-                    eprintln!(
-                        "Ah! Time Travel! The system time appears to be before the Unix epoch!"
-                    );
-                    ErrorLobby::Internal
-                })?
-                .as_secs(),
+            max_players,
+            created_at: SystemTime::now(),
+            invite_code: None,
+        };
+        let owner = LobbyMember {
+            user_id: owner_id,
+            joined_at: lobby.created_at,
         };
 
-        lobbies.push(Some(lobby.clone()));
+        lobbies.push(Some(MemoryLobby {
+            lobby: lobby.clone(),
+            members: vec![owner],
+        }));
         Ok(lobby)
     }
 
-    pub async fn get_lobby(&self, id: i32) -> anyhow::Result<Lobby> {
-        let lobbies = self.lobbies.lock().unwrap();
-        lobbies[id as usize]
-            .clone()
-            .ok_or_else(|| anyhow::anyhow!("Lobby not found"))
+    async fn get_lobby(&self, id: i32) -> Result<Lobby, ErrorLobby> {
+        let mut lobbies = self.lobbies.lock().map_err(|_| ErrorLobby::Internal)?;
+        Ok(Self::find(&mut lobbies, id)?.lobby.clone())
+    }
+
+    async fn get_lobbies(&self, user_id: i32) -> Result<Vec<Lobby>, ErrorLobby> {
+        let friended_by = self.friended_by(user_id).await?;
+        let lobbies = self.lobbies.lock().map_err(|_| ErrorLobby::Internal)?;
+        Ok(lobbies
+            .iter()
+            .flatten()
+            .map(|details| &details.lobby)
+            .filter(|lobby| lobby.is_listed_for(user_id, &friended_by))
+            .cloned()
+            .collect())
     }
 
-    pub async fn get_lobbies(&self) -> anyhow::Result<Vec<Lobby>> {
-        let lobbies = self.lobbies.lock().unwrap();
-        Ok(lobbies.iter().filter_map(|l| l.clone()).collect())
+    async fn get_members(&self, id: i32) -> Result<Vec<LobbyMember>, ErrorLobby> {
+        let lobbies = self.lobbies.lock().map_err(|_| ErrorLobby::Internal)?;
+        Ok(Self::index(id)
+            .and_then(|index| lobbies.get(index))
+            .and_then(Option::as_ref)
+            .map(|details| details.members.clone())
+            .unwrap_or_default())
     }
 
-    pub async fn delete_lobby(&self, id: i32) -> anyhow::Result<()> {
-        let mut lobbies = self.lobbies.lock().unwrap();
-        lobbies[id as usize]
-            .take()
-            .ok_or_else(|| anyhow::anyhow!("Lobby not found"))?;
+    async fn friended_by(&self, user_id: i32) -> Result<HashSet<i32>, ErrorLobby> {
+        Ok(self
+            .friendships
+            .iter()
+            .filter(|(_, friend_id)| *friend_id == user_id)
+            .map(|(user_id, _)| *user_id)
+            .collect())
+    }
+
+    async fn update_lobby(&self, id: i32, update: LobbyForUpdate) -> Result<Lobby, ErrorLobby> {
+        let mut lobbies = self.lobbies.lock().map_err(|_| ErrorLobby::Internal)?;
+        let lobby = &mut Self::find(&mut lobbies, id)?.lobby;
+
+        if let Some(name) = update.name {
+            lobby.name = name;
+        }
+        if let Some(visibility) = update.visibility {
+            lobby.visibility = visibility;
+        }
+        Ok(lobby.clone())
+    }
+
+    async fn delete_lobby(&self, id: i32) -> Result<(), ErrorLobby> {
+        let mut lobbies = self.lobbies.lock().map_err(|_| ErrorLobby::Internal)?;
+        Self::index(id)
+            .and_then(|index| lobbies.get_mut(index))
+            .and_then(Option::take)
+            .ok_or(ErrorLobby::NotFound)?;
         Ok(())
     }
+
+    async fn set_invite_code(&self, id: i32, code: &str) -> Result<(), ErrorLobby> {
+        let mut lobbies = self.lobbies.lock().map_err(|_| ErrorLobby::Internal)?;
+        let taken = lobbies
+            .iter()
+            .flatten()
+            .any(|other| other.lobby.id != id && other.lobby.invite_code.as_deref() == Some(code));
+        let lobby = &mut Self::find(&mut lobbies, id)?.lobby;
+        if taken {
+            return Err(ErrorLobby::InviteCodeTaken);
+        }
+
+        lobby.invite_code = Some(code.to_string());
+        Ok(())
+    }
+
+    async fn find_by_invite_code(&self, code: &str) -> Result<Lobby, ErrorLobby> {
+        let lobbies = self.lobbies.lock().map_err(|_| ErrorLobby::Internal)?;
+        lobbies
+            .iter()
+            .flatten()
+            .find(|other| other.lobby.invite_code.as_deref() == Some(code))
+            .map(|other| other.lobby.clone())
+            .ok_or(ErrorLobby::InviteNotFound)
+    }
+
+    async fn join_lobby(&self, id: i32, user_id: i32) -> Result<(), ErrorLobby> {
+        let mut lobbies = self.lobbies.lock().map_err(|_| ErrorLobby::Internal)?;
+        let MemoryLobby { lobby, members } = Self::find(&mut lobbies, id)?;
+
+        if members.iter().any(|member| member.user_id == user_id) {
+            return Err(ErrorLobby::AlreadyMember);
+        }
+        if members.len() >= lobby.max_players as usize {
+            return Err(ErrorLobby::Full);
+        }
+
+        members.push(LobbyMember {
+            user_id,
+            joined_at: SystemTime::now(),
+        });
+        Ok(())
+    }
+
+    async fn leave_lobby(&self, id: i32, user_id: i32) -> Result<Departure, ErrorLobby> {
+        let mut lobbies = self.lobbies.lock().map_err(|_| ErrorLobby::Internal)?;
+        let MemoryLobby { lobby, members } = Self::find(&mut lobbies, id)?;
+
+        let position = members
+            .iter()
+            .position(|member| member.user_id == user_id)
+            .ok_or(ErrorLobby::NotMember)?;
+        members.remove(position);
+
+        let departure = match members.first() {
+            None => Departure::Closed,
+            Some(next_host) if lobby.owner_id == user_id => {
+                lobby.owner_id = next_host.user_id;
+                Departure::HostTransferred {
+                    owner_id: next_host.user_id,
+                }
+            }
+            Some(_) => Departure::Left,
+        };
+        if departure == Departure::Closed {
+            if let Some(slot) = Self::index(id).and_then(|index| lobbies.get_mut(index)) {
+                *slot = None;
+            }
+        }
+        Ok(departure)
+    }
+}
+// endregion
+
+// region: Lobby Controller
+#[derive(Debug, Clone)]
+pub struct LobbyController {
+    store: Arc<dyn LobbyStore>,
+}
+
+impl LobbyController {
+    /// Lobbies kept in Postgres, shared by every instance of the server
+    pub fn new(db_pool: DbPool) -> Self {
+        Self::with_store(PgLobbyStore::new(db_pool))
+    }
+
+    /// Lobbies kept in memory, for tests
+    pub fn in_memory() -> Self {
+        Self::with_store(MemoryLobbyStore::default())
+    }
+
+    pub fn with_store(store: impl LobbyStore + 'static) -> Self {
+        Self {
+            store: Arc::new(store),
+        }
+    }
+
+    pub async fn create_lobby(
+        &self,
+        actor: &LobbyActor,
+        lobby: LobbyForCreate,
+    ) -> Result<Lobby, ErrorLobby> {
+        if !(MIN_PLAYERS..=MAX_PLAYERS).contains(&lobby.max_players) {
+            return Err(ErrorLobby::InvalidMaxPlayers);
+        }
+
+        self.store
+            .create_lobby(LobbyForInsert {
+                owner_id: actor.user_id,
+                name: normalize_name(&lobby.name)?,
+                visibility: lobby.visibility,
+                max_players: lobby.max_players,
+            })
+            .await
+    }
+
+    /// A lobby along with who is in it. Lobbies the user may not see are reported as not found,
+    /// so their ids give nothing away.
+    pub async fn get_lobby(&self, actor: &LobbyActor, id: i32) -> Result<LobbyDetails, ErrorLobby> {
+        let mut details = LobbyDetails {
+            lobby: self.store.get_lobby(id).await?,
+            members: self.store.get_members(id).await?,
+            invite_code: None,
+        };
+        let friended_by = self.store.friended_by(actor.user_id).await?;
+        if !actor.can_see(&details, &friended_by) {
+            return Err(ErrorLobby::NotFound);
+        }
+        if details.is_member(actor.user_id) || actor.can_manage(&details.lobby) {
+            details.invite_code = details.lobby.invite_code.clone();
+        }
+        Ok(details)
+    }
+
+    /// Every lobby the user may list, see [`Lobby::is_listed_for`]
+    pub async fn get_lobbies(&self, actor: &LobbyActor) -> Result<Vec<Lobby>, ErrorLobby> {
+        self.store.get_lobbies(actor.user_id).await
+    }
+
+    pub async fn update_lobby(
+        &self,
+        actor: &LobbyActor,
+        id: i32,
+        update: LobbyForUpdate,
+    ) -> Result<Lobby, ErrorLobby> {
+        self.get_managed_lobby(actor, id).await?;
+        let update = LobbyForUpdate {
+            name: update.name.as_deref().map(normalize_name).transpose()?,
+            ..update
+        };
+        self.store.update_lobby(id, update).await
+    }
+
+    /// The lobby as it was before it was deleted
+    pub async fn delete_lobby(&self, actor: &LobbyActor, id: i32) -> Result<Lobby, ErrorLobby> {
+        let lobby = self.get_managed_lobby(actor, id).await?;
+        self.store.delete_lobby(id).await?;
+        Ok(lobby)
+    }
+
+    pub async fn join_lobby(
+        &self,
+        actor: &LobbyActor,
+        id: i32,
+    ) -> Result<LobbyDetails, ErrorLobby> {
+        let details = self.get_lobby(actor, id).await?;
+        if details.lobby.visibility == Visibility::Private && !details.is_member(actor.user_id) {
+            return Err(ErrorLobby::InviteRequired);
+        }
+
+        self.store.join_lobby(id, actor.user_id).await?;
+        self.get_lobby(actor, id).await
+    }
+
+    /// Join with an invite code, whatever the lobby's visibility. A code that doesn't match an open
+    /// lobby is [`ErrorLobby::InviteNotFound`], for the caller to count as a guess.
+    pub async fn join_with_invite(
+        &self,
+        actor: &LobbyActor,
+        code: &str,
+    ) -> Result<LobbyDetails, ErrorLobby> {
+        let code = invite_code::normalize(code).ok_or(ErrorLobby::InviteNotFound)?;
+        let lobby = self.store.find_by_invite_code(&code).await?;
+
+        self.store.join_lobby(lobby.id, actor.user_id).await?;
+        self.get_lobby(actor, lobby.id).await
+    }
+
+    /// Give a lobby a new invite code, so the old one stops working
+    pub async fn rotate_invite_code(
+        &self,
+        actor: &LobbyActor,
+        id: i32,
+    ) -> Result<String, ErrorLobby> {
+        self.get_managed_lobby(actor, id).await?;
+        for _ in 0..INVITE_CODE_ATTEMPTS {
+            let code = invite_code::generate();
+            match self.store.set_invite_code(id, &code).await {
+                Err(ErrorLobby::InviteCodeTaken) => continue,
+                result => return result.map(|()| code),
+            }
+        }
+        Err(ErrorLobby::InviteCodeTaken)
+    }
+
+    pub async fn leave_lobby(&self, actor: &LobbyActor, id: i32) -> Result<Departure, ErrorLobby> {
+        self.get_lobby(actor, id).await?;
+        self.store.leave_lobby(id, actor.user_id).await
+    }
+
+    async fn get_managed_lobby(&self, actor: &LobbyActor, id: i32) -> Result<Lobby, ErrorLobby> {
+        let LobbyDetails { lobby, .. } = self.get_lobby(actor, id).await?;
+        if !actor.can_manage(&lobby) {
+            return Err(ErrorLobby::Forbidden);
+        }
+        Ok(lobby)
+    }
+}
+// endregion
+
+#[cfg(test)]
+mod tests {
+    use super::{normalize_name, ErrorLobby, Visibility};
+
+    #[test]
+    fn visibility_round_trip() {
+        for visibility in [Visibility::Public, Visibility::Friends, Visibility::Private] {
+            assert_eq!(
+                visibility.as_str().parse::<Visibility>().unwrap(),
+                visibility
+            );
+        }
+        assert!("secret".parse::<Visibility>().is_err());
+    }
+
+    #[test]
+    fn names_are_trimmed_and_limited() {
+        assert_eq!(normalize_name("  Friday Night "), Ok("Friday Night".into()));
+        assert_eq!(normalize_name("   "), Err(ErrorLobby::InvalidName));
+        assert_eq!(normalize_name(&"é".repeat(64)), Ok("é".repeat(64)));
+        assert_eq!(
+            normalize_name(&"a".repeat(65)),
+            Err(ErrorLobby::InvalidName)
+        );
+    }
 }
@@ -0,0 +1,275 @@
+use std::time::SystemTime;
+
+use data_encoding::BASE32_NOPAD;
+use diesel::prelude::*;
+use rand::{thread_rng, RngCore};
+use serde::Serialize;
+use tracing::{debug, warn};
+
+use crate::db::DbConn;
+use crate::schema::{recovery_codes, user_totp};
+use crate::service::{crypto, time, totp::Totp};
+
+/// Shown next to the account in authenticator apps
+pub const TOTP_ISSUER: &str = "RustWebApp";
+const RECOVERY_CODE_COUNT: usize = 10;
+/// 80 bits, enough that a fast hash is safe to store them with
+const RECOVERY_CODE_BYTES: usize = 10;
+
+#[derive(Debug, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::user_totp)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+struct UserTotp {
+    secret: Vec<u8>,
+    last_used_step: Option<i64>,
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Clone, Serialize)]
+pub enum ErrorMfa {
+    #[error("{0}")]
+    Db(String),
+
+    #[error("Two-factor authentication is already enabled")]
+    AlreadyEnabled,
+
+    #[error("Two-factor authentication is not set up")]
+    NotEnrolled,
+
+    #[error("Two-factor code is invalid")]
+    InvalidCode,
+}
+
+impl From<diesel::result::Error> for ErrorMfa {
+    fn from(e: diesel::result::Error) -> Self {
+        ErrorMfa::Db(e.to_string())
+    }
+}
+
+/// Start (or restart) enrollment with a new secret. It isn't used for login until confirmed.
+pub async fn enroll(mut conn: DbConn, user_id: i32) -> Result<Totp, ErrorMfa> {
+    if is_enabled_conn(&mut conn, user_id)? {
+        return Err(ErrorMfa::AlreadyEnabled);
+    }
+
+    let totp = Totp::generate();
+    diesel::insert_into(user_totp::table)
+        .values((
+            user_totp::user_id.eq(user_id),
+            user_totp::secret.eq(totp.secret()),
+        ))
+        .on_conflict(user_totp::user_id)
+        .do_update()
+        .set((
+            user_totp::secret.eq(totp.secret()),
+            user_totp::created_at.eq(SystemTime::now()),
+            user_totp::last_used_step.eq(None::<i64>),
+        ))
+        .execute(&mut conn)?;
+
+    Ok(totp)
+}
+
+/// Finish enrollment with a code from the authenticator app. Returns fresh recovery codes,
+/// which are only ever shown this once.
+pub async fn confirm(mut conn: DbConn, user_id: i32, code: &str) -> Result<Vec<String>, ErrorMfa> {
+    let now = now_unix()?;
+
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let Some(stored) = user_totp::table
+            .find(user_id)
+            .filter(user_totp::confirmed_at.is_null())
+            .select(UserTotp::as_select())
+            .for_update()
+            .first(conn)
+            .optional()?
+        else {
+            return Ok(Err(ErrorMfa::NotEnrolled));
+        };
+
+        let Some(step) = Totp::new(stored.secret).verify(code, now) else {
+            return Ok(Err(ErrorMfa::InvalidCode));
+        };
+
+        diesel::update(user_totp::table.find(user_id))
+            .set((
+                user_totp::confirmed_at.eq(SystemTime::now()),
+                user_totp::last_used_step.eq(step as i64),
+            ))
+            .execute(conn)?;
+
+        let codes = replace_recovery_codes(conn, user_id)?;
+        debug!("Two-factor authentication enabled for user {user_id}");
+        Ok(Ok(codes))
+    })?
+}
+
+pub async fn is_enabled(mut conn: DbConn, user_id: i32) -> Result<bool, ErrorMfa> {
+    is_enabled_conn(&mut conn, user_id)
+}
+
+/// Check a second factor: a code from the authenticator app, or an unused recovery code
+pub async fn verify(mut conn: DbConn, user_id: i32, code: &str) -> Result<(), ErrorMfa> {
+    let now = now_unix()?;
+    conn.transaction(|conn| check_code(conn, user_id, code, now))?
+}
+
+/// Turn two-factor authentication off, which takes a valid code just like logging in
+pub async fn disable(mut conn: DbConn, user_id: i32, code: &str) -> Result<(), ErrorMfa> {
+    let now = now_unix()?;
+
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        if let Err(e) = check_code(conn, user_id, code, now)? {
+            return Ok(Err(e));
+        }
+
+        diesel::delete(user_totp::table.find(user_id)).execute(conn)?;
+        diesel::delete(recovery_codes::table.filter(recovery_codes::user_id.eq(user_id)))
+            .execute(conn)?;
+        debug!("Two-factor authentication disabled for user {user_id}");
+        Ok(Ok(()))
+    })?
+}
+
+/// Check a second factor inside a transaction, using it up
+pub(crate) fn check_code(
+    conn: &mut PgConnection,
+    user_id: i32,
+    code: &str,
+    now: u64,
+) -> Result<Result<(), ErrorMfa>, diesel::result::Error> {
+    let Some(stored) = user_totp::table
+        .find(user_id)
+        .filter(user_totp::confirmed_at.is_not_null())
+        .select(UserTotp::as_select())
+        .for_update()
+        .first(conn)
+        .optional()?
+    else {
+        return Ok(Err(ErrorMfa::NotEnrolled));
+    };
+
+    let code = code.trim();
+    if code.chars().all(|c| c.is_ascii_digit()) {
+        let Some(step) = Totp::new(stored.secret).verify(code, now) else {
+            return Ok(Err(ErrorMfa::InvalidCode));
+        };
+
+        // Each code works once, even inside the window it is valid for
+        if stored
+            .last_used_step
+            .is_some_and(|last| step as i64 <= last)
+        {
+            warn!("⚠️  Replayed two-factor code for user {user_id}");
+            return Ok(Err(ErrorMfa::InvalidCode));
+        }
+
+        diesel::update(user_totp::table.find(user_id))
+            .set(user_totp::last_used_step.eq(step as i64))
+            .execute(conn)?;
+        return Ok(Ok(()));
+    }
+
+    let used = diesel::update(
+        recovery_codes::table
+            .filter(recovery_codes::user_id.eq(user_id))
+            .filter(recovery_codes::code_hash.eq(recovery_code_hash(code)))
+            .filter(recovery_codes::used_at.is_null()),
+    )
+    .set(recovery_codes::used_at.eq(SystemTime::now()))
+    .execute(conn)?;
+
+    if used == 1 {
+        debug!("Recovery code used by user {user_id}");
+        Ok(Ok(()))
+    } else {
+        Ok(Err(ErrorMfa::InvalidCode))
+    }
+}
+
+pub(crate) fn is_enabled_conn(conn: &mut PgConnection, user_id: i32) -> Result<bool, ErrorMfa> {
+    let confirmed = user_totp::table
+        .find(user_id)
+        .filter(user_totp::confirmed_at.is_not_null())
+        .count()
+        .get_result::<i64>(conn)?;
+    Ok(confirmed > 0)
+}
+
+fn replace_recovery_codes(
+    conn: &mut PgConnection,
+    user_id: i32,
+) -> Result<Vec<String>, diesel::result::Error> {
+    diesel::delete(recovery_codes::table.filter(recovery_codes::user_id.eq(user_id)))
+        .execute(conn)?;
+
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT).map(|_| recovery_code()).collect();
+    let rows: Vec<_> = codes
+        .iter()
+        .map(|code| {
+            (
+                recovery_codes::user_id.eq(user_id),
+                recovery_codes::code_hash.eq(recovery_code_hash(code)),
+            )
+        })
+        .collect();
+    diesel::insert_into(recovery_codes::table)
+        .values(rows)
+        .execute(conn)?;
+
+    Ok(codes)
+}
+
+/// A code like `abcd-efgh-ijkl-mnop`
+fn recovery_code() -> String {
+    let mut bytes = [0u8; RECOVERY_CODE_BYTES];
+    thread_rng().fill_bytes(&mut bytes);
+    let encoded = BASE32_NOPAD.encode(&bytes).to_lowercase();
+
+    encoded
+        .as_bytes()
+        .chunks(4)
+        .map(|chunk| std::str::from_utf8(chunk).expect("base32 is ASCII"))
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Hash a recovery code, ignoring case, spaces and dashes the way a user might type it
+fn recovery_code_hash(code: &str) -> Vec<u8> {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    crypto::token_hash(&normalized)
+}
+
+fn now_unix() -> Result<u64, ErrorMfa> {
+    time::now_unix().map_err(|e| ErrorMfa::Db(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{recovery_code, recovery_code_hash};
+
+    #[test]
+    fn recovery_code_format() {
+        let code = recovery_code();
+        assert_eq!(code.len(), 19);
+        assert_eq!(code.split('-').count(), 4);
+        assert!(code
+            .chars()
+            .all(|c| c == '-' || c.is_ascii_lowercase() || c.is_ascii_digit()));
+    }
+
+    #[test]
+    fn recovery_code_hash_ignores_formatting() {
+        assert_eq!(
+            recovery_code_hash("abcd-efgh-ijkl-mnop"),
+            recovery_code_hash(" ABCD EFGH IJKL MNOP ")
+        );
+        assert_ne!(
+            recovery_code_hash("abcd-efgh-ijkl-mnop"),
+            recovery_code_hash("abcd-efgh-ijkl-mnoq")
+        );
+    }
+}
@@ -1,2 +1,12 @@
+pub mod access_token;
+pub mod account;
+pub mod audit;
+pub mod email_verification;
+pub mod friendship;
+pub mod identity;
 pub mod lobby;
+pub mod mfa;
+pub mod password_reset;
+pub mod role;
+pub mod session;
 pub mod user;
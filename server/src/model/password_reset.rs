@@ -0,0 +1,94 @@
+use std::time::{Duration, SystemTime};
+
+use diesel::prelude::*;
+use tracing::debug;
+
+use crate::db::DbConn;
+use crate::model::session;
+use crate::model::user::{set_password_phc, valid_password, ErrorUser, User};
+use crate::schema::{password_reset_tokens, users};
+use crate::service::{crypto, password};
+
+const RESET_TOKEN_BYTES: usize = 32;
+pub const RESET_TOKEN_LIFETIME: Duration = Duration::from_secs(60 * 30);
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::password_reset_tokens)]
+struct ResetTokenForInsert {
+    user_id: i32,
+    token_hash: Vec<u8>,
+    expires_at: SystemTime,
+}
+
+/// Create a token that lets whoever receives it set a new password for the user
+pub async fn create_token(mut conn: DbConn, user: &User) -> Result<String, ErrorUser> {
+    let token = crypto::token(RESET_TOKEN_BYTES);
+    diesel::insert_into(password_reset_tokens::table)
+        .values(ResetTokenForInsert {
+            user_id: user.id,
+            token_hash: crypto::token_hash(&token),
+            expires_at: SystemTime::now() + RESET_TOKEN_LIFETIME,
+        })
+        .execute(&mut conn)
+        .map_err(|e| ErrorUser::Db(e.to_string()))?;
+
+    Ok(token)
+}
+
+/// Set a new password with a reset token. The token and every other outstanding token for the
+/// user are used up, and all of the user's sessions are revoked.
+pub async fn reset(mut conn: DbConn, token: &str, new_password: &str) -> Result<User, ErrorUser> {
+    let token_hash = crypto::token_hash(token);
+    let now = SystemTime::now();
+
+    // Check the password is acceptable for its owner before the slow hash, the token is
+    // checked again for real once it is locked
+    let owner: Option<(String, String)> = password_reset_tokens::table
+        .inner_join(users::table)
+        .filter(password_reset_tokens::token_hash.eq(&token_hash))
+        .filter(password_reset_tokens::used_at.is_null())
+        .filter(password_reset_tokens::expires_at.gt(now))
+        .select((users::email, users::display_name))
+        .first(&mut conn)
+        .optional()
+        .map_err(|e| ErrorUser::Db(e.to_string()))?;
+    let Some((email, display_name)) = owner else {
+        return Err(ErrorUser::InvalidToken);
+    };
+
+    valid_password(new_password, &email, &display_name)?;
+    let password_phc =
+        password::hash(new_password).map_err(|e| ErrorUser::PasswordHash(e.to_string()))?;
+
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let Some(user_id) = password_reset_tokens::table
+            .filter(password_reset_tokens::token_hash.eq(&token_hash))
+            .filter(password_reset_tokens::used_at.is_null())
+            .filter(password_reset_tokens::expires_at.gt(now))
+            .select(password_reset_tokens::user_id)
+            .for_update()
+            .first::<i32>(conn)
+            .optional()?
+        else {
+            return Ok(Err(ErrorUser::InvalidToken));
+        };
+
+        diesel::update(
+            password_reset_tokens::table
+                .filter(password_reset_tokens::user_id.eq(user_id))
+                .filter(password_reset_tokens::used_at.is_null()),
+        )
+        .set(password_reset_tokens::used_at.eq(now))
+        .execute(conn)?;
+
+        let user = set_password_phc(conn, user_id, password_phc)?;
+
+        let revoked = session::revoke_all(conn, user.id, None)?;
+        debug!(
+            "Password reset for user {}, revoked {revoked} sessions",
+            user.id
+        );
+        Ok(Ok(user))
+    })
+    .map_err(|e| ErrorUser::Db(e.to_string()))?
+}
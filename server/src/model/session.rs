@@ -0,0 +1,312 @@
+use std::{
+    net::IpAddr,
+    time::{Duration, SystemTime},
+};
+
+use diesel::prelude::*;
+use serde::Serialize;
+use tracing::{trace, warn};
+use uuid::Uuid;
+
+use crate::db::DbConn;
+use crate::schema::{refresh_tokens, sessions};
+use crate::service::crypto;
+
+const REFRESH_TOKEN_BYTES: usize = 32;
+
+/// How long a session stays alive without being refreshed
+pub const SESSION_LIFETIME: Duration = Duration::from_secs(60 * 60 * 24 * 30);
+/// How stale `last_seen_at` may get before a request updates it
+const LAST_SEEN_RESOLUTION: Duration = Duration::from_secs(60);
+const USER_AGENT_MAX_CHARS: usize = 512;
+
+// region: -- Session Types
+#[derive(Debug, Clone, PartialEq, Serialize, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::sessions)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Session {
+    pub id: Uuid,
+    #[serde(skip)]
+    pub user_id: i32,
+    pub created_at: SystemTime,
+    pub expires_at: SystemTime,
+    pub revoked_at: Option<SystemTime>,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub last_seen_at: SystemTime,
+}
+
+/// The device a session is started from
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SessionClient {
+    pub user_agent: Option<String>,
+    pub ip: Option<IpAddr>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::schema::sessions)]
+struct SessionForInsert {
+    id: Uuid,
+    user_id: i32,
+    expires_at: SystemTime,
+    user_agent: Option<String>,
+    ip: Option<String>,
+}
+
+#[derive(Debug, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::refresh_tokens)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+struct RefreshToken {
+    id: i32,
+    session_id: Uuid,
+    used_at: Option<SystemTime>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::refresh_tokens)]
+struct RefreshTokenForInsert {
+    session_id: Uuid,
+    token_hash: Vec<u8>,
+}
+// endregion
+
+// region: -- Session Controller
+#[derive(Debug, thiserror::Error, PartialEq, Clone, Serialize)]
+pub enum ErrorSession {
+    #[error("{0}")]
+    Db(String),
+
+    #[error("Session not found")]
+    NotFound,
+
+    #[error("Refresh token is invalid")]
+    InvalidToken,
+
+    #[error("Session has expired")]
+    Expired,
+
+    #[error("Session has been revoked")]
+    Revoked,
+
+    #[error("Refresh token was already used, session revoked")]
+    Reused { user_id: i32, session_id: Uuid },
+}
+
+impl From<diesel::result::Error> for ErrorSession {
+    fn from(e: diesel::result::Error) -> Self {
+        ErrorSession::Db(e.to_string())
+    }
+}
+
+/// Start a new session for a user, returning it with its first refresh token
+pub async fn create(
+    mut conn: DbConn,
+    user_id: i32,
+    client: &SessionClient,
+) -> Result<(Session, String), ErrorSession> {
+    let now = SystemTime::now();
+    let session_insert = SessionForInsert {
+        id: Uuid::new_v4(),
+        user_id,
+        expires_at: now + SESSION_LIFETIME,
+        user_agent: client
+            .user_agent
+            .as_ref()
+            .map(|user_agent| user_agent.chars().take(USER_AGENT_MAX_CHARS).collect()),
+        ip: client.ip.map(|ip| ip.to_string()),
+    };
+
+    conn.transaction(|conn| {
+        let session = diesel::insert_into(sessions::table)
+            .values(session_insert)
+            .returning(Session::as_returning())
+            .get_result(conn)?;
+        let token = issue_refresh_token(conn, session.id)?;
+        trace!("Session created: {session:?}");
+        Ok((session, token))
+    })
+}
+
+/// Exchange a refresh token for a new one in the same session.
+///
+/// Every refresh token is single use. Presenting one that was already exchanged means it
+/// leaked, so the whole session (every token in the family) is revoked.
+pub async fn refresh(mut conn: DbConn, token: &str) -> Result<(Session, String), ErrorSession> {
+    let token_hash = crypto::token_hash(token);
+    let now = SystemTime::now();
+
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let Some(stored) = refresh_tokens::table
+            .filter(refresh_tokens::token_hash.eq(&token_hash))
+            .select(RefreshToken::as_select())
+            .for_update()
+            .first(conn)
+            .optional()?
+        else {
+            return Ok(Err(ErrorSession::InvalidToken));
+        };
+
+        let session: Session = sessions::table
+            .find(stored.session_id)
+            .select(Session::as_select())
+            .for_update()
+            .first(conn)?;
+
+        if session.revoked_at.is_some() {
+            return Ok(Err(ErrorSession::Revoked));
+        }
+
+        if stored.used_at.is_some() {
+            warn!("⚠️  Refresh token reused, revoking session {}", session.id);
+            diesel::update(sessions::table.find(session.id))
+                .set(sessions::revoked_at.eq(now))
+                .execute(conn)?;
+            return Ok(Err(ErrorSession::Reused {
+                user_id: session.user_id,
+                session_id: session.id,
+            }));
+        }
+
+        if session.expires_at <= now {
+            return Ok(Err(ErrorSession::Expired));
+        }
+
+        diesel::update(refresh_tokens::table.find(stored.id))
+            .set(refresh_tokens::used_at.eq(now))
+            .execute(conn)?;
+
+        let session = diesel::update(sessions::table.find(session.id))
+            .set((
+                sessions::expires_at.eq(now + SESSION_LIFETIME),
+                sessions::last_seen_at.eq(now),
+            ))
+            .returning(Session::as_returning())
+            .get_result(conn)?;
+
+        let token = issue_refresh_token(conn, session.id)?;
+        Ok(Ok((session, token)))
+    })?
+}
+
+/// Revoke a session so none of its refresh tokens can be used again
+pub async fn revoke(mut conn: DbConn, session_id: Uuid) -> Result<(), ErrorSession> {
+    diesel::update(
+        sessions::table
+            .find(session_id)
+            .filter(sessions::revoked_at.is_null()),
+    )
+    .set(sessions::revoked_at.eq(SystemTime::now()))
+    .execute(&mut conn)?;
+    Ok(())
+}
+
+/// Check that a session an access token belongs to is still live, noting that it was seen
+pub async fn touch(mut conn: DbConn, session_id: Uuid) -> Result<(), ErrorSession> {
+    let now = SystemTime::now();
+    let session: Session = sessions::table
+        .find(session_id)
+        .select(Session::as_select())
+        .first(&mut conn)
+        .optional()?
+        .ok_or(ErrorSession::NotFound)?;
+
+    if session.revoked_at.is_some() {
+        return Err(ErrorSession::Revoked);
+    }
+    if session.expires_at <= now {
+        return Err(ErrorSession::Expired);
+    }
+
+    // Busy sessions don't need a write on every request
+    let stale = now
+        .duration_since(session.last_seen_at)
+        .is_ok_and(|age| age >= LAST_SEEN_RESOLUTION);
+    if stale {
+        diesel::update(sessions::table.find(session_id))
+            .set(sessions::last_seen_at.eq(now))
+            .execute(&mut conn)?;
+    }
+    Ok(())
+}
+
+/// A user's live sessions, most recently seen first
+pub async fn list_active(mut conn: DbConn, user_id: i32) -> Result<Vec<Session>, ErrorSession> {
+    Ok(sessions::table
+        .filter(sessions::user_id.eq(user_id))
+        .filter(sessions::revoked_at.is_null())
+        .filter(sessions::expires_at.gt(SystemTime::now()))
+        .order(sessions::last_seen_at.desc())
+        .select(Session::as_select())
+        .load(&mut conn)?)
+}
+
+/// Revoke one of a user's own live sessions, e.g. one on a lost device
+pub async fn revoke_owned(
+    mut conn: DbConn,
+    user_id: i32,
+    session_id: Uuid,
+) -> Result<(), ErrorSession> {
+    let revoked = diesel::update(
+        sessions::table
+            .find(session_id)
+            .filter(sessions::user_id.eq(user_id))
+            .filter(sessions::revoked_at.is_null()),
+    )
+    .set(sessions::revoked_at.eq(SystemTime::now()))
+    .execute(&mut conn)?;
+
+    match revoked {
+        0 => Err(ErrorSession::NotFound),
+        _ => Ok(()),
+    }
+}
+
+/// Revoke every live session of a user except `keep`, returning how many were revoked
+pub async fn revoke_for_user(
+    mut conn: DbConn,
+    user_id: i32,
+    keep: Option<Uuid>,
+) -> Result<usize, ErrorSession> {
+    Ok(revoke_all(&mut conn, user_id, keep)?)
+}
+
+/// Revoke every live session of a user, e.g. after their password changed
+pub(crate) fn revoke_all(
+    conn: &mut PgConnection,
+    user_id: i32,
+    keep: Option<Uuid>,
+) -> Result<usize, diesel::result::Error> {
+    let mut query = diesel::update(sessions::table)
+        .filter(sessions::user_id.eq(user_id))
+        .filter(sessions::revoked_at.is_null())
+        .into_boxed();
+    if let Some(keep) = keep {
+        query = query.filter(sessions::id.ne(keep));
+    }
+    query
+        .set(sessions::revoked_at.eq(SystemTime::now()))
+        .execute(conn)
+}
+
+pub async fn get_by_id(mut conn: DbConn, session_id: Uuid) -> Result<Session, ErrorSession> {
+    sessions::table
+        .find(session_id)
+        .select(Session::as_select())
+        .first(&mut conn)
+        .map_err(ErrorSession::from)
+}
+
+fn issue_refresh_token(
+    conn: &mut PgConnection,
+    session_id: Uuid,
+) -> Result<String, diesel::result::Error> {
+    let token = crypto::token(REFRESH_TOKEN_BYTES);
+    diesel::insert_into(refresh_tokens::table)
+        .values(RefreshTokenForInsert {
+            session_id,
+            token_hash: crypto::token_hash(&token),
+        })
+        .execute(conn)?;
+    Ok(token)
+}
+// endregion
@@ -6,16 +6,19 @@ use diesel::{deserialize::Queryable, prelude::Insertable, Selectable};
 use once_cell::sync::Lazy;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use sha2::Digest;
 use std::time::SystemTime;
-use tracing::trace;
+use tracing::{trace, warn};
+
+use crate::service::display_name;
+use crate::service::password_policy::{self, PasswordOwner};
 
 use crate::db::DbConn;
+use crate::model::role::Role;
+use crate::schema::users;
 use crate::service::jwt::Claims;
+use crate::service::password::{self, Verification};
 use crate::web::error::MainError;
-use crate::{schema::users, service};
 
-const USER_SALT_LEN: usize = 32;
 static EMAIL_REGEX: Lazy<Regex> = Lazy::new(|| {
     Regex::new(r"^[a-zA-Z0-9._%+-]+@[a-zA-Z0-9.-]+\.[a-zA-Z]{2,}$")
         .expect("Failed to compile email regex")
@@ -29,10 +32,35 @@ pub struct User {
     pub id: i32,
     pub display_name: String,
     pub email: String,
-    pub password_salt: String,
-    pub password_hash: Vec<u8>,
+    pub password_salt: Option<String>,
+    pub password_hash: Option<Vec<u8>>,
     pub created_at: SystemTime,
     pub updated_at: SystemTime,
+    pub password_phc: Option<String>,
+    pub email_verified_at: Option<SystemTime>,
+    pub role: Role,
+    /// When the user asked for their account to be deleted, it is purged after a grace period
+    pub deleted_at: Option<SystemTime>,
+}
+
+impl User {
+    /// Whether the user can log in with a password, rather than only through a linked provider
+    pub fn has_password(&self) -> bool {
+        self.password_phc.is_some() || self.password_hash.is_some()
+    }
+
+    /// Check a password against the stored Argon2id hash, or the legacy SHA-384 hash for
+    /// accounts that have not logged in since the upgrade.
+    pub(crate) fn verify_password(&self, password: &str) -> Verification {
+        match (&self.password_phc, &self.password_salt, &self.password_hash) {
+            (Some(phc), _, _) => password::verify(password, phc),
+            (None, Some(salt), Some(hash)) => password::verify_legacy_sha384(password, salt, hash),
+            _ => {
+                password::verify_dummy(password);
+                Verification::Invalid
+            }
+        }
+    }
 }
 
 #[derive(Deserialize)]
@@ -60,8 +88,7 @@ impl fmt::Debug for UserNewFields {
 struct UserForInsert {
     display_name: String,
     email: String,
-    password_salt: String,
-    password_hash: Vec<u8>,
+    password_phc: String,
 }
 
 impl std::fmt::Debug for UserForInsert {
@@ -70,38 +97,28 @@ impl std::fmt::Debug for UserForInsert {
             .field("display_name", &self.display_name)
             .field("email", &self.email)
             .field(
-                "password_salt",
+                "password_phc",
                 &format!(
                     "{}...",
-                    self.password_salt.chars().take(5).collect::<String>()
-                ),
-            )
-            .field(
-                "password_hash",
-                &format!(
-                    "{}...",
-                    &self.password_hash[0..5]
-                        .iter()
-                        .map(|b| b.to_string())
-                        .collect::<String>()
+                    self.password_phc.chars().take(10).collect::<String>()
                 ),
             )
             .finish()
     }
 }
 
-impl From<UserNewFields> for UserForInsert {
-    fn from(fields: UserNewFields) -> Self {
-        let password_salt = service::crypto::salt(USER_SALT_LEN);
-        let password_salted = format!("{}{}", fields.password, password_salt);
-        let password_hash = sha2::Sha384::digest(password_salted.as_bytes()).to_vec();
+impl TryFrom<UserNewFields> for UserForInsert {
+    type Error = ErrorUser;
+
+    fn try_from(fields: UserNewFields) -> Result<Self, Self::Error> {
+        let password_phc =
+            password::hash(&fields.password).map_err(|e| ErrorUser::PasswordHash(e.to_string()))?;
 
-        UserForInsert {
+        Ok(UserForInsert {
             display_name: fields.display_name,
             email: fields.email,
-            password_salt,
-            password_hash,
-        }
+            password_phc,
+        })
     }
 }
 
@@ -110,6 +127,8 @@ pub struct UserPublic {
     pub id: i32,
     pub display_name: String,
     pub email: String,
+    pub email_verified_at: Option<SystemTime>,
+    pub role: Role,
     pub created_at: SystemTime,
     pub updated_at: SystemTime,
 }
@@ -120,11 +139,21 @@ impl From<User> for UserPublic {
             id: user.id,
             display_name: user.display_name,
             email: user.email,
+            email_verified_at: user.email_verified_at,
+            role: user.role,
             created_at: user.created_at,
             updated_at: user.updated_at,
         }
     }
 }
+
+impl From<&User> for Claims {
+    fn from(user: &User) -> Self {
+        Claims::new(user.id as u64, &user.display_name, &user.email)
+            .with_email_verified(user.email_verified_at.is_some())
+            .with_role(user.role)
+    }
+}
 // endregion
 
 // region: -- Account Controller
@@ -139,6 +168,18 @@ pub enum ErrorUser {
     #[error("Display name already exists")]
     DisplayNameAlreadyExists,
 
+    #[error("Display name must be at least {0} characters long")]
+    DisplayNameTooShort(usize),
+
+    #[error("Display name must be at most {0} characters long")]
+    DisplayNameTooLong(usize),
+
+    #[error("Display name may only contain letters, numbers, spaces, '_', '-' and '.'")]
+    DisplayNameInvalidCharacters,
+
+    #[error("Display name contains a word that is not allowed")]
+    DisplayNameNotAllowed,
+
     #[error("Email already exists")]
     EmailAlreadyExists,
 
@@ -148,22 +189,53 @@ pub enum ErrorUser {
     #[error("Password or email is invalid")]
     InvalidCredentials,
 
+    #[error("Current password is incorrect")]
+    WrongPassword,
+
+    #[error("Two-factor code is invalid")]
+    WrongCode,
+
+    #[error("Sign in again to confirm it's you")]
+    ReauthRequired,
+
+    #[error("Token is invalid or has expired")]
+    InvalidToken,
+
+    #[error("Email is already verified")]
+    EmailAlreadyVerified,
+
     #[error(transparent)]
     Password(#[from] ErrorPassword),
+
+    #[error("{0}")]
+    PasswordHash(String),
 }
 
 #[derive(Debug, thiserror::Error, PartialEq, Clone, Serialize)]
 pub enum ErrorPassword {
-    #[error("Password must be at least 12 characters long")]
-    TooShort,
+    #[error("Password must be at least {0} characters long")]
+    TooShort(usize),
+
+    #[error("Password must be at most {0} characters long")]
+    TooLong(usize),
+
+    #[error("Password must not contain your email address")]
+    ContainsEmail,
+
+    #[error("Password must not contain your display name")]
+    ContainsDisplayName,
+
+    #[error("Password is too common, it appears in lists of breached passwords")]
+    Common,
 }
 
-pub async fn create(mut conn: DbConn, fields: UserNewFields) -> Result<User, ErrorUser> {
+pub async fn create(mut conn: DbConn, mut fields: UserNewFields) -> Result<User, ErrorUser> {
     trace!("User Create:\n{fields:#?}");
-    valid_password(&fields.password)?;
+    fields.display_name = display_name::normalize(&fields.display_name)?;
+    valid_password(&fields.password, &fields.email, &fields.display_name)?;
     valid_email(&fields.email)?;
 
-    let user_insert: UserForInsert = fields.into();
+    let user_insert = UserForInsert::try_from(fields)?;
     trace!("User Insert:\n{user_insert:#?}");
 
     diesel::insert_into(users::table)
@@ -172,32 +244,130 @@ pub async fn create(mut conn: DbConn, fields: UserNewFields) -> Result<User, Err
         .map_err(create_db_error_map)
 }
 
+/// Create a user that signs in through an identity provider and has no password yet
+pub(crate) fn create_without_password(
+    conn: &mut PgConnection,
+    display_name: &str,
+    email: &str,
+    email_verified: bool,
+) -> Result<User, ErrorUser> {
+    let display_name = display_name::normalize(display_name)?;
+    valid_email(email)?;
+
+    diesel::insert_into(users::table)
+        .values((
+            users::display_name.eq(display_name),
+            users::email.eq(email),
+            users::email_verified_at.eq(email_verified.then(SystemTime::now)),
+        ))
+        .get_result::<User>(conn)
+        .map_err(create_db_error_map)
+}
+
 pub async fn login(
     mut conn: DbConn,
     email: impl AsRef<str>,
     password: impl AsRef<str>,
 ) -> Result<Claims, MainError> {
-    let user: User = users::table
+    let password = password.as_ref();
+    let user: User = match users::table
         .filter(users::email.eq(email.as_ref()))
         .get_result::<User>(&mut conn)
-        .map_err(|_| MainError::LoginFail)?;
+    {
+        Ok(user) => user,
+        Err(_) => {
+            password::verify_dummy(password);
+            return Err(MainError::LoginFail);
+        }
+    };
+
+    match user.verify_password(password) {
+        Verification::Valid => {}
+        Verification::ValidNeedsRehash => {
+            // The password was right, so a failed upgrade should not fail the login
+            if let Err(e) = rehash_password(&mut conn, user.id, password) {
+                warn!(
+                    "⚠️  Failed to upgrade password hash for user {}: {e}",
+                    user.id
+                );
+            }
+        }
+        Verification::Invalid => return Err(MainError::LoginFail),
+    }
 
-    let password_salted = format!("{}{}", password.as_ref(), user.password_salt);
-    let password_hash = sha2::Sha384::digest(password_salted.as_bytes()).to_vec();
+    Ok(Claims::from(&user))
+}
 
-    if password_hash == user.password_hash {
-        Ok(Claims::new(user.id as u64, user.display_name, user.email))
-    } else {
-        Err(MainError::LoginFail)
-    }
+/// Replace whatever hash a user has with a fresh Argon2id hash and drop the legacy columns
+fn rehash_password(conn: &mut DbConn, user_id: i32, password: &str) -> Result<(), ErrorUser> {
+    let password_phc =
+        password::hash(password).map_err(|e| ErrorUser::PasswordHash(e.to_string()))?;
+
+    diesel::update(users::table.filter(users::id.eq(user_id)))
+        .set((
+            users::password_phc.eq(password_phc),
+            users::password_salt.eq(None::<String>),
+            users::password_hash.eq(None::<Vec<u8>>),
+        ))
+        .execute(conn)
+        .map_err(create_db_error_map)?;
+
+    trace!("Upgraded password hash for user {user_id}");
+    Ok(())
 }
 
-fn valid_password(password: &str) -> Result<(), ErrorPassword> {
-    if password.len() >= 12 {
-        Ok(())
-    } else {
-        Err(ErrorPassword::TooShort)
+/// Change a user's password, proving they know the current one
+pub async fn change_password(
+    mut conn: DbConn,
+    user_id: i32,
+    current_password: &str,
+    new_password: &str,
+) -> Result<User, ErrorUser> {
+    let user = users::table
+        .find(user_id)
+        .get_result::<User>(&mut conn)
+        .map_err(|_| ErrorUser::NotFound)?;
+
+    if !user.verify_password(current_password).is_valid() {
+        return Err(ErrorUser::WrongPassword);
     }
+
+    valid_password(new_password, &user.email, &user.display_name)?;
+    let password_phc =
+        password::hash(new_password).map_err(|e| ErrorUser::PasswordHash(e.to_string()))?;
+
+    set_password_phc(&mut conn, user.id, password_phc).map_err(create_db_error_map)
+}
+
+/// Store a new Argon2id hash, drop any legacy hash and bump `updated_at`
+pub(crate) fn set_password_phc(
+    conn: &mut PgConnection,
+    user_id: i32,
+    password_phc: String,
+) -> Result<User, diesel::result::Error> {
+    diesel::update(users::table.find(user_id))
+        .set((
+            users::password_phc.eq(password_phc),
+            users::password_salt.eq(None::<String>),
+            users::password_hash.eq(None::<Vec<u8>>),
+            users::updated_at.eq(SystemTime::now()),
+        ))
+        .get_result::<User>(conn)
+}
+
+/// Check a new password for the user with `email` and `display_name` against the password policy
+pub(crate) fn valid_password(
+    password: &str,
+    email: &str,
+    display_name: &str,
+) -> Result<(), ErrorPassword> {
+    password_policy::check(
+        password,
+        &PasswordOwner {
+            email,
+            display_name,
+        },
+    )
 }
 
 fn valid_email(email: &str) -> Result<(), ErrorUser> {
@@ -215,12 +385,29 @@ pub async fn update_email(
 ) -> Result<User, ErrorUser> {
     valid_email(new_email)?;
     use crate::schema::users::dsl as users_dsl;
+    // A new address has to be verified again
     diesel::update(users_dsl::users.filter(users_dsl::id.eq(user_id)))
-        .set(users_dsl::email.eq(new_email))
+        .set((
+            users_dsl::email.eq(new_email),
+            users_dsl::email_verified_at.eq(None::<SystemTime>),
+        ))
         .get_result::<User>(&mut conn)
         .map_err(create_db_error_map)
 }
 
+/// Give a user a new role. It reaches their access tokens the next time they are refreshed.
+pub async fn set_role(mut conn: DbConn, user_id: i32, role: Role) -> Result<User, ErrorUser> {
+    diesel::update(users::table.find(user_id))
+        .set((
+            users::role.eq(role),
+            users::updated_at.eq(SystemTime::now()),
+        ))
+        .get_result::<User>(&mut conn)
+        .optional()
+        .map_err(create_db_error_map)?
+        .ok_or(ErrorUser::NotFound)
+}
+
 pub async fn get_by_id(mut conn: DbConn, user_id: i32) -> Result<User, ErrorUser> {
     use crate::schema::users::dsl as users_dsl;
     users_dsl::users
@@ -229,6 +416,13 @@ pub async fn get_by_id(mut conn: DbConn, user_id: i32) -> Result<User, ErrorUser
         .map_err(|_| ErrorUser::NotFound)
 }
 
+pub async fn get_by_email(mut conn: DbConn, email: &str) -> Result<User, ErrorUser> {
+    users::table
+        .filter(users::email.eq(email))
+        .get_result::<User>(&mut conn)
+        .map_err(|_| ErrorUser::NotFound)
+}
+
 fn create_db_error_map(error: diesel::result::Error) -> ErrorUser {
     match error {
         DatabaseError(DatabaseErrorKind::UniqueViolation, info) => match info.constraint_name() {
@@ -248,7 +442,7 @@ fn create_db_error_map(error: diesel::result::Error) -> ErrorUser {
 mod tests {
     use test_case::test_case;
 
-    use crate::model::user::{valid_password, ErrorPassword, USER_SALT_LEN};
+    use crate::model::user::{valid_password, ErrorPassword};
 
     use super::{UserForInsert, UserNewFields};
 
@@ -260,17 +454,19 @@ mod tests {
             password: "password".into(),
         };
 
-        let user_insert: UserForInsert = fields.into();
+        let user_insert = UserForInsert::try_from(fields).unwrap();
 
         assert_eq!(user_insert.display_name, "john89");
         assert_eq!(user_insert.email, "john89@contoso.com");
-        assert_eq!(user_insert.password_salt.len(), USER_SALT_LEN);
-        assert_ne!(user_insert.password_hash, Vec::<u8>::new());
+        assert!(user_insert.password_phc.starts_with("$argon2id$"));
     }
 
     #[test]
     fn password_too_short() {
-        assert_eq!(valid_password("1234567890"), Err(ErrorPassword::TooShort));
+        assert_eq!(
+            valid_password("1234567890", "john89@contoso.com", "john89"),
+            Err(ErrorPassword::TooShort(12))
+        );
     }
 
     #[test_case("john@contoso.com")]
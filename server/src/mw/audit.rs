@@ -0,0 +1,54 @@
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use serde_json::json;
+
+use crate::{
+    db::DbPool,
+    model::audit::AuditKind,
+    service::audit::AuditContext,
+    web::{ctx::Ctx, error::MainError},
+};
+
+/// Middleware to record every request made with an impersonation token in the audit log,
+/// whatever it does and whether or not it succeeds. Layer it inside `ctx_resolver`.
+pub async fn audit_impersonation(
+    State(db_pool): State<DbPool>,
+    audit: AuditContext,
+    ctx: Result<Ctx, MainError>,
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    let Ok(Ctx {
+        account_id,
+        impersonator: Some(_),
+        ..
+    }) = ctx
+    else {
+        return next.run(req).await;
+    };
+
+    let method = req.method().to_string();
+    let path = req.uri().path().to_string();
+    let res = next.run(req).await;
+
+    // Errors only get their real status from the response mapper further out
+    let status = res
+        .extensions()
+        .get::<MainError>()
+        .map(|e| e.client_response().0)
+        .unwrap_or(res.status());
+    audit
+        .record(
+            &db_pool,
+            AuditKind::ImpersonatedRequest,
+            Some(account_id as i32),
+            json!({ "method": method, "path": path, "status": status.as_u16() }),
+        )
+        .await;
+
+    res
+}
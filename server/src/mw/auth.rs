@@ -1,19 +1,33 @@
 use axum::{
     body::Body,
     extract::{FromRequestParts, Request, State},
-    http::request::Parts,
+    http::{header::AUTHORIZATION, request::Parts, HeaderMap},
     middleware::Next,
     response::Response,
 };
-use tower_cookies::{Cookie, Cookies};
+use tower_cookies::Cookies;
 use tracing::{debug, trace};
 
 use crate::{
-    service,
-    web::{ctx::Ctx, error::MainError, AUTH_HEADER},
+    db::{get_db_conn, DbPool},
+    model::{
+        access_token::{self, Scope, Scopes},
+        role::Role,
+        session::{self, ErrorSession},
+    },
+    service::{jwt::JwtController, revocation::RevocationController},
+    web::{
+        self,
+        ctx::{Ctx, TokenSource},
+        error::MainError,
+        AUTH_HEADER,
+    },
 };
 
-/// Middleware to require authentication for a route
+/// Middleware to require authentication for a route.
+///
+/// Only a user's own session passes, personal access tokens need a route that opts in to them
+/// with [`require_scope`].
 pub async fn require_auth(
     ctx: Result<Ctx, MainError>,
     req: Request<Body>,
@@ -25,33 +39,132 @@ pub async fn require_auth(
     }
 
     // TODO: Is that really always the error?
-    ctx?;
+    if ctx?.scopes != Scopes::All {
+        return Err(MainError::SessionRequired);
+    }
+
+    Ok(next.run(req).await)
+}
+
+/// Middleware to require authentication for a route that personal access tokens with `scope`
+/// may also use, e.g. `middleware::from_fn_with_state(Scope::LobbyRead, require_scope)`
+pub async fn require_scope(
+    State(scope): State<Scope>,
+    ctx: Result<Ctx, MainError>,
+    req: Request<Body>,
+    next: Next,
+) -> Result<Response, MainError> {
+    match &ctx {
+        Ok(ctx) => debug!("🔐  ✅ Require Scope {scope}: {ctx:?}"),
+        Err(e) => debug!("🔐  ❌ Require Scope {scope}: {e:?}"),
+    }
+
+    if !ctx?.scopes.allows(scope) {
+        return Err(MainError::MissingScope(scope));
+    }
+
+    Ok(next.run(req).await)
+}
+
+/// Middleware to require a user's own session with at least `role`, e.g.
+/// `middleware::from_fn_with_state(Role::Admin, require_role)`
+pub async fn require_role(
+    State(role): State<Role>,
+    ctx: Result<Ctx, MainError>,
+    req: Request<Body>,
+    next: Next,
+) -> Result<Response, MainError> {
+    match &ctx {
+        Ok(ctx) => debug!("🔐  ✅ Require Role {role}: {ctx:?}"),
+        Err(e) => debug!("🔐  ❌ Require Role {role}: {e:?}"),
+    }
+
+    let ctx = ctx?;
+    if ctx.scopes != Scopes::All {
+        return Err(MainError::SessionRequired);
+    }
+    if !ctx.role.has(role) {
+        return Err(MainError::MissingRole(role));
+    }
 
     Ok(next.run(req).await)
 }
 
+/// Middleware to keep impersonation tokens away from routes that change how a user signs in or
+/// what happens to their account, e.g. changing the password or deleting the account.
+/// Layer it inside [`require_auth`] or [`require_role`].
+pub async fn refuse_impersonation(
+    ctx: Result<Ctx, MainError>,
+    req: Request<Body>,
+    next: Next,
+) -> Result<Response, MainError> {
+    if let Some(impersonator) = ctx?.impersonator {
+        debug!("🔐  ❌ Refuse Impersonation by {impersonator}");
+        return Err(MainError::ImpersonationForbidden);
+    }
+
+    Ok(next.run(req).await)
+}
+
+/// Resolve the `Ctx` for a request from its access token.
+///
+/// An `Authorization` header takes precedence over the auth cookie: when one is sent, the cookie
+/// is ignored, and a header that isn't a valid bearer token fails without falling back to it.
+/// Bearer tokens starting with `pat_` are personal access tokens rather than JWTs. A JWT for a
+/// session that has since been revoked, e.g. signed out from another device, is rejected.
+/// Requests outside `/api`, e.g. for static assets, skip that session lookup.
 pub async fn ctx_resolver(
-    State(ctl_jwt): State<service::jwt::JwtController>,
+    State(ctl_jwt): State<JwtController>,
+    State(ctl_revocation): State<RevocationController>,
+    State(db_pool): State<DbPool>,
     cookies: Cookies,
     mut req: Request<Body>,
     next: Next,
 ) -> Result<Response, MainError> {
-    let auth_token = cookies.get(AUTH_HEADER).map(|c| c.value().to_string());
+    let resolve = |token: &str, source: TokenSource| {
+        let claims = ctl_jwt
+            .verify(token)
+            .map_err(|e| MainError::AuthFailToken(e.to_string()))?;
+        if ctl_revocation.is_revoked(&claims.jti) {
+            return Err(MainError::AuthFailTokenRevoked);
+        }
+        Ok(Ctx::from(&claims).with_token_source(source))
+    };
 
-    // Compute Result<Ctx, MainError>
-    let ctx_result = auth_token
-        .ok_or(MainError::AuthFailNoAuthTokenCookie)
-        .and_then(|token| {
-            ctl_jwt
-                .verify(&token)
-                .map_err(|e| MainError::AuthFailToken(e.to_string()))
-        })
-        .map(|claims| Ctx::new(claims.sub));
+    let api = req.uri().path().starts_with("/api");
+    let live_session = |ctx| async {
+        if api {
+            check_session(&db_pool, ctx).await
+        } else {
+            Ok(ctx)
+        }
+    };
 
-    // Remove the cookie if something went wrong other than NoAuthToken
-    if let Err(MainError::AuthFailNoAuthTokenCookie) = ctx_result {
-        cookies.remove(Cookie::from(AUTH_HEADER));
-    }
+    // Compute Result<Ctx, MainError>
+    let ctx_result = match bearer_token(req.headers()) {
+        Some(Ok(token)) if token.starts_with(access_token::TOKEN_PREFIX) => {
+            resolve_access_token(&db_pool, token).await
+        }
+        Some(token) => match token.and_then(|token| resolve(token, TokenSource::Bearer)) {
+            Ok(ctx) => live_session(ctx).await,
+            Err(e) => Err(e),
+        },
+        None => match cookies.get(AUTH_HEADER) {
+            Some(cookie) => {
+                let ctx_result = match resolve(cookie.value(), TokenSource::Cookie) {
+                    Ok(ctx) => live_session(ctx).await,
+                    Err(e) => Err(e),
+                };
+                // A cookie that doesn't work never will, so stop the browser sending it. Errors
+                // reaching the database say nothing about the cookie, so keep it for those.
+                if ctx_result.as_ref().is_err_and(is_dead_token) {
+                    cookies.remove(web::auth_cookie_removal());
+                }
+                ctx_result
+            }
+            None => Err(MainError::AuthFailNoAuthTokenCookie),
+        },
+    };
 
     match &ctx_result {
         Ok(ctx) => trace!("⚡️  ✅ Ctx Resolver : {ctx:?}"),
@@ -64,6 +177,55 @@ pub async fn ctx_resolver(
     Ok(next.run(req).await)
 }
 
+/// Make sure the session a token was issued for is still live
+async fn check_session(db_pool: &DbPool, ctx: Ctx) -> Result<Ctx, MainError> {
+    if let Some(session_id) = ctx.session_id {
+        let conn = get_db_conn(db_pool)?;
+        session::touch(conn, session_id)
+            .await
+            .map_err(|e| match e {
+                // Gone along with its user, as good as revoked
+                ErrorSession::NotFound => ErrorSession::Revoked,
+                e => e,
+            })?;
+    }
+    Ok(ctx)
+}
+
+/// Whether `e` means the token itself is no good, as opposed to not being able to check it
+fn is_dead_token(e: &MainError) -> bool {
+    matches!(
+        e,
+        MainError::AuthFailToken(_)
+            | MainError::AuthFailTokenRevoked
+            | MainError::Session(
+                ErrorSession::Revoked | ErrorSession::NotFound | ErrorSession::Expired
+            )
+    )
+}
+
+async fn resolve_access_token(db_pool: &DbPool, token: &str) -> Result<Ctx, MainError> {
+    let conn = get_db_conn(db_pool)?;
+    let token = access_token::authenticate(conn, token).await?;
+    Ok(Ctx::from(&token))
+}
+
+/// The token from an `Authorization: Bearer <token>` header, `None` when there is no header
+fn bearer_token(headers: &HeaderMap) -> Option<Result<&str, MainError>> {
+    let value = headers.get(AUTHORIZATION)?;
+
+    let token = value
+        .to_str()
+        .ok()
+        .and_then(|value| value.split_once(' '))
+        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+        .map(|(_, token)| token.trim())
+        .filter(|token| !token.is_empty())
+        .ok_or(MainError::AuthFailBadAuthorizationHeader);
+
+    Some(token)
+}
+
 #[async_trait::async_trait]
 impl<S: Send + Sync> FromRequestParts<S> for Ctx {
     type Rejection = MainError;
@@ -84,3 +246,62 @@ impl<S: Send + Sync> FromRequestParts<S> for Ctx {
         res
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{header::AUTHORIZATION, HeaderMap, HeaderValue};
+
+    use super::{bearer_token, is_dead_token};
+    use crate::{model::session::ErrorSession, web::error::MainError};
+
+    fn headers(authorization: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_static(authorization));
+        headers
+    }
+
+    #[test]
+    fn bearer_token_from_header() {
+        assert!(bearer_token(&HeaderMap::new()).is_none());
+        assert_eq!(
+            bearer_token(&headers("Bearer abc.def.ghi"))
+                .unwrap()
+                .unwrap(),
+            "abc.def.ghi"
+        );
+        assert_eq!(
+            bearer_token(&headers("bearer abc.def.ghi"))
+                .unwrap()
+                .unwrap(),
+            "abc.def.ghi"
+        );
+    }
+
+    #[test]
+    fn other_schemes_are_rejected() {
+        for authorization in ["Basic Ym9iOnNlY3JldA==", "Bearer ", "abc.def.ghi"] {
+            assert!(matches!(
+                bearer_token(&headers(authorization)),
+                Some(Err(MainError::AuthFailBadAuthorizationHeader))
+            ));
+        }
+    }
+
+    #[test]
+    fn only_bad_tokens_clear_the_cookie() {
+        assert!(is_dead_token(&MainError::AuthFailToken("expired".into())));
+        assert!(is_dead_token(&MainError::AuthFailTokenRevoked));
+        assert!(is_dead_token(&MainError::Session(ErrorSession::Revoked)));
+        assert!(is_dead_token(&MainError::Session(ErrorSession::NotFound)));
+
+        assert!(!is_dead_token(&MainError::Session(ErrorSession::Db(
+            "connection reset".into()
+        ))));
+        assert!(!is_dead_token(&MainError::Internal("timed out".into())));
+    }
+
+    #[test]
+    fn expired_sessions_clear_the_cookie() {
+        assert!(is_dead_token(&MainError::Session(ErrorSession::Expired)));
+    }
+}
@@ -0,0 +1,209 @@
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{
+        header::{AUTHORIZATION, COOKIE},
+        HeaderMap, Method,
+    },
+    middleware::Next,
+    response::Response,
+};
+use subtle::ConstantTimeEq;
+use tower_cookies::Cookies;
+use tracing::debug;
+
+use crate::{
+    service::crypto,
+    web::{self, error::MainError, AUTH_HEADER, CSRF_COOKIE, CSRF_HEADER, REFRESH_COOKIE},
+};
+
+/// Bytes of randomness in a CSRF token
+const CSRF_TOKEN_BYTES: usize = 32;
+
+/// Middleware protecting cookie authenticated requests from cross site request forgery with a
+/// double submit token.
+///
+/// Every browser is handed a `csrf-token` cookie the client can read, and requests that change
+/// state must echo it back in an `X-CSRF-Token` header, which another site can't read or set.
+/// Requests with a bearer token are exempt, browsers never attach those on their own.
+///
+/// The check goes by which cookies the browser sent rather than whether they resolve to a
+/// session, so expired auth cookies and the refresh cookie (`/token/refresh`) are covered too.
+pub async fn csrf_protect(
+    cookies: Cookies,
+    req: Request<Body>,
+    next: Next,
+) -> Result<Response, MainError> {
+    if !is_safe(req.method()) && sends_session_cookie(req.headers()) {
+        let cookie = cookies.get(CSRF_COOKIE);
+        let cookie = cookie.as_ref().map(|cookie| cookie.value());
+        if !tokens_match(cookie, header_token(req.headers())) {
+            debug!("🛡️  ❌ CSRF token missing or wrong");
+            return Err(MainError::CsrfTokenMismatch);
+        }
+    }
+
+    let res = next.run(req).await;
+
+    // Handlers may have rotated the token, only fill in a missing one
+    if cookies.get(CSRF_COOKIE).is_none() {
+        cookies.add(new_csrf_cookie());
+    }
+
+    Ok(res)
+}
+
+/// A fresh CSRF cookie, e.g. to rotate the token when a session starts
+pub fn new_csrf_cookie() -> tower_cookies::Cookie<'static> {
+    web::csrf_cookie(crypto::token(CSRF_TOKEN_BYTES))
+}
+
+/// Methods that must not change state, so need no token
+fn is_safe(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+/// Whether the browser attached a cookie that authenticates the request on its own. An
+/// `Authorization` header takes precedence over the cookies, so those requests never use them.
+fn sends_session_cookie(headers: &HeaderMap) -> bool {
+    if headers.contains_key(AUTHORIZATION) {
+        return false;
+    }
+
+    // Read the header itself, the auth cookie may already be queued for removal
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.split_once('='))
+        .any(|(name, _)| matches!(name.trim(), AUTH_HEADER | REFRESH_COOKIE))
+}
+
+fn header_token(headers: &HeaderMap) -> Option<&str> {
+    headers.get(CSRF_HEADER)?.to_str().ok()
+}
+
+fn tokens_match(cookie: Option<&str>, header: Option<&str>) -> bool {
+    match (cookie, header) {
+        (Some(cookie), Some(header)) if !cookie.is_empty() => {
+            cookie.as_bytes().ct_eq(header.as_bytes()).into()
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header, Method, Request, StatusCode},
+        middleware,
+        response::Response,
+        routing::post,
+        Router,
+    };
+    use tower::ServiceExt;
+    use tower_cookies::CookieManagerLayer;
+
+    use super::{csrf_protect, is_safe, tokens_match};
+    use crate::web::{
+        error::{ErrorClient, MainError},
+        CSRF_HEADER,
+    };
+
+    const TOKEN: &str = "csrf-value";
+
+    async fn send(cookies: &str, headers: &[(&str, &str)]) -> Response {
+        let app = Router::new()
+            .route("/api/lobby", post(|| async { "created" }))
+            .layer(middleware::from_fn(csrf_protect))
+            .layer(CookieManagerLayer::new());
+
+        let mut req = Request::builder()
+            .method(Method::POST)
+            .uri("/api/lobby")
+            .header(header::COOKIE, cookies);
+        for (name, value) in headers {
+            req = req.header(*name, *value);
+        }
+
+        app.oneshot(req.body(Body::empty()).unwrap()).await.unwrap()
+    }
+
+    fn assert_refused(res: &Response) {
+        let err = res.extensions().get::<MainError>();
+        assert!(matches!(err, Some(MainError::CsrfTokenMismatch)), "{err:?}");
+        let (status, client) = err.unwrap().client_response();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert!(matches!(client, ErrorClient::CsrfFailed));
+    }
+
+    #[test]
+    fn only_reads_are_safe() {
+        assert!(is_safe(&Method::GET));
+        assert!(is_safe(&Method::HEAD));
+        assert!(is_safe(&Method::OPTIONS));
+        for method in [Method::POST, Method::PUT, Method::PATCH, Method::DELETE] {
+            assert!(!is_safe(&method), "{method}");
+        }
+    }
+
+    #[test]
+    fn header_must_echo_cookie() {
+        assert!(tokens_match(Some("abc"), Some("abc")));
+        assert!(!tokens_match(Some("abc"), Some("abd")));
+        assert!(!tokens_match(Some("abc"), Some("ab")));
+        assert!(!tokens_match(Some("abc"), None));
+        assert!(!tokens_match(None, Some("abc")));
+        assert!(!tokens_match(Some(""), Some("")));
+    }
+
+    #[tokio::test]
+    async fn cookie_post_without_header_is_refused() {
+        let res = send("auth-token=jwt; csrf-token=csrf-value", &[]).await;
+        assert_refused(&res);
+    }
+
+    #[tokio::test]
+    async fn cookie_post_with_wrong_header_is_refused() {
+        let res = send(
+            "auth-token=jwt; csrf-token=csrf-value",
+            &[(CSRF_HEADER, "other")],
+        )
+        .await;
+        assert_refused(&res);
+    }
+
+    #[tokio::test]
+    async fn cookie_post_with_matching_header_passes() {
+        let res = send(
+            "auth-token=jwt; csrf-token=csrf-value",
+            &[(CSRF_HEADER, TOKEN)],
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn refresh_cookie_alone_is_checked() {
+        let res = send("refresh-token=opaque; csrf-token=csrf-value", &[]).await;
+        assert_refused(&res);
+    }
+
+    #[tokio::test]
+    async fn bearer_post_is_exempt() {
+        let res = send(
+            "auth-token=jwt",
+            &[(header::AUTHORIZATION.as_str(), "Bearer jwt")],
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn post_without_session_cookie_passes() {
+        let res = send("csrf-token=csrf-value", &[]).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+}
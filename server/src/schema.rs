@@ -1,6 +1,187 @@
 // @generated automatically by Diesel CLI.
 
+pub mod sql_types {
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "lobby_visibility"))]
+    pub struct LobbyVisibility;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "user_role"))]
+    pub struct UserRole;
+}
+
+diesel::table! {
+    audit_events (id) {
+        id -> Int8,
+        #[max_length = 64]
+        kind -> Varchar,
+        actor_id -> Nullable<Int4>,
+        target_id -> Nullable<Int4>,
+        #[max_length = 45]
+        ip -> Nullable<Varchar>,
+        #[max_length = 512]
+        user_agent -> Nullable<Varchar>,
+        request_id -> Uuid,
+        detail -> Jsonb,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    email_verification_tokens (id) {
+        id -> Int4,
+        user_id -> Int4,
+        #[max_length = 255]
+        email -> Varchar,
+        token_hash -> Bytea,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    friendships (user_id, friend_id) {
+        user_id -> Int4,
+        friend_id -> Int4,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::LobbyVisibility;
+
+    lobbies (id) {
+        id -> Int4,
+        #[max_length = 255]
+        name -> Varchar,
+        visibility -> LobbyVisibility,
+        created_at -> Timestamp,
+        owner_id -> Int4,
+        max_players -> Int4,
+        #[max_length = 32]
+        invite_code -> Nullable<Varchar>,
+    }
+}
+
+diesel::table! {
+    lobby_members (lobby_id, user_id) {
+        lobby_id -> Int4,
+        user_id -> Int4,
+        joined_at -> Timestamp,
+    }
+}
+
 diesel::table! {
+    login_throttles (key) {
+        #[max_length = 320]
+        key -> Varchar,
+        failures -> Int4,
+        last_failure_at -> Timestamp,
+        blocked_until -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    password_reset_tokens (id) {
+        id -> Int4,
+        user_id -> Int4,
+        token_hash -> Bytea,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    personal_access_tokens (id) {
+        id -> Uuid,
+        user_id -> Int4,
+        #[max_length = 100]
+        name -> Varchar,
+        #[max_length = 255]
+        scopes -> Varchar,
+        token_hash -> Bytea,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        last_used_at -> Nullable<Timestamp>,
+        revoked_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    recovery_codes (id) {
+        id -> Int4,
+        user_id -> Int4,
+        code_hash -> Bytea,
+        created_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    refresh_tokens (id) {
+        id -> Int4,
+        session_id -> Uuid,
+        token_hash -> Bytea,
+        created_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    revoked_tokens (jti) {
+        jti -> Uuid,
+        expires_at -> Timestamp,
+        revoked_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    sessions (id) {
+        id -> Uuid,
+        user_id -> Int4,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        revoked_at -> Nullable<Timestamp>,
+        #[max_length = 512]
+        user_agent -> Nullable<Varchar>,
+        #[max_length = 45]
+        ip -> Nullable<Varchar>,
+        last_seen_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    user_identities (id) {
+        id -> Int4,
+        user_id -> Int4,
+        #[max_length = 50]
+        provider -> Varchar,
+        #[max_length = 255]
+        subject -> Varchar,
+        #[max_length = 255]
+        email -> Nullable<Varchar>,
+        created_at -> Timestamp,
+        last_login_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    user_totp (user_id) {
+        user_id -> Int4,
+        secret -> Bytea,
+        created_at -> Timestamp,
+        confirmed_at -> Nullable<Timestamp>,
+        last_used_step -> Nullable<Int8>,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::UserRole;
+
     users (id) {
         id -> Int4,
         #[max_length = 255]
@@ -8,9 +189,44 @@ diesel::table! {
         #[max_length = 255]
         email -> Varchar,
         #[max_length = 255]
-        password_salt -> Varchar,
-        password_hash -> Bytea,
+        password_salt -> Nullable<Varchar>,
+        password_hash -> Nullable<Bytea>,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        #[max_length = 255]
+        password_phc -> Nullable<Varchar>,
+        email_verified_at -> Nullable<Timestamp>,
+        role -> UserRole,
+        deleted_at -> Nullable<Timestamp>,
     }
 }
+
+diesel::joinable!(email_verification_tokens -> users (user_id));
+diesel::joinable!(lobbies -> users (owner_id));
+diesel::joinable!(lobby_members -> lobbies (lobby_id));
+diesel::joinable!(lobby_members -> users (user_id));
+diesel::joinable!(password_reset_tokens -> users (user_id));
+diesel::joinable!(personal_access_tokens -> users (user_id));
+diesel::joinable!(recovery_codes -> users (user_id));
+diesel::joinable!(refresh_tokens -> sessions (session_id));
+diesel::joinable!(sessions -> users (user_id));
+diesel::joinable!(user_identities -> users (user_id));
+diesel::joinable!(user_totp -> users (user_id));
+
+diesel::allow_tables_to_appear_in_same_query!(
+    audit_events,
+    email_verification_tokens,
+    friendships,
+    lobbies,
+    lobby_members,
+    login_throttles,
+    password_reset_tokens,
+    personal_access_tokens,
+    recovery_codes,
+    refresh_tokens,
+    revoked_tokens,
+    sessions,
+    user_identities,
+    user_totp,
+    users,
+);
@@ -0,0 +1,55 @@
+use serde_json::Value;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::db::DbPool;
+use crate::model::{
+    audit::{self, AuditEventNew, AuditKind},
+    session::SessionClient,
+};
+
+/// Who is making a request and from where, stamped on every audit event it records
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AuditContext {
+    pub actor_id: Option<i32>,
+    pub client: SessionClient,
+    pub request_id: Uuid,
+}
+
+impl AuditContext {
+    /// For requests where the actor is only known part way through, like logging in
+    pub fn with_actor(&self, actor_id: i32) -> Self {
+        Self {
+            actor_id: Some(actor_id),
+            ..self.clone()
+        }
+    }
+
+    /// Record that `kind` happened to `target_id`'s account. A failure to write the event is
+    /// logged rather than failing the request that caused it.
+    pub async fn record(
+        &self,
+        db_pool: &DbPool,
+        kind: AuditKind,
+        target_id: Option<i32>,
+        detail: Value,
+    ) {
+        let event = AuditEventNew {
+            kind,
+            actor_id: self.actor_id,
+            target_id,
+            ip: self.client.ip.map(|ip| ip.to_string()),
+            user_agent: self.client.user_agent.clone(),
+            request_id: self.request_id,
+            detail,
+        };
+
+        let recorded = match db_pool.get() {
+            Ok(conn) => audit::record(conn, event).await.map(|_| ()),
+            Err(e) => Err(audit::ErrorAudit::Db(e.to_string())),
+        };
+        if let Err(e) = recorded {
+            warn!("⚠️  Failed to record {kind} audit event: {e}");
+        }
+    }
+}
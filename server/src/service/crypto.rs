@@ -1,13 +1,27 @@
-use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand::{distributions::Alphanumeric, thread_rng, Rng, RngCore};
+use sha2::Digest;
 
 pub fn salt(n: usize) -> String {
     let mut rng = thread_rng();
     (0..n).map(|_| rng.sample(Alphanumeric) as char).collect()
 }
 
+/// A url safe token made from `n_bytes` of randomness
+pub fn token(n_bytes: usize) -> String {
+    let mut bytes = vec![0u8; n_bytes];
+    thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Hash of a random token for storage. Tokens carry their own entropy, so a fast hash is enough.
+pub fn token_hash(token: &str) -> Vec<u8> {
+    sha2::Sha256::digest(token.as_bytes()).to_vec()
+}
+
 #[cfg(test)]
 mod tests {
-    use super::salt;
+    use super::{salt, token, token_hash};
 
     #[test]
     fn generate_salt() {
@@ -21,4 +35,19 @@ mod tests {
         let salt2: String = salt(12);
         assert_ne!(salt1, salt2, "Two salts are the same");
     }
+
+    #[test]
+    fn token_is_url_safe() {
+        let token = token(32);
+        assert_eq!(token.len(), 43);
+        assert!(token
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+    }
+
+    #[test]
+    fn token_hash_is_stable() {
+        assert_eq!(token_hash("abc"), token_hash("abc"));
+        assert_ne!(token_hash("abc"), token_hash("abd"));
+    }
 }
@@ -0,0 +1,236 @@
+use std::collections::HashSet;
+
+use once_cell::sync::Lazy;
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+use crate::dictionary;
+use crate::model::user::ErrorUser;
+
+pub const MIN_CHARS: usize = 3;
+pub const MAX_CHARS: usize = 32;
+/// Banned words shorter than this only match a whole word, so that "ass" doesn't rule out "Cassandra"
+const MIN_SUBSTRING_CHARS: usize = 5;
+/// Banned words that fold down to less than this are too short to match anything meaningful
+const MIN_BANNED_CHARS: usize = 3;
+
+static BANNED: Lazy<BannedWords> = Lazy::new(|| BannedWords::new(dictionary::banned()));
+
+/// The banned list folded the same way names are. Words without doubled letters are also
+/// checked against the name with its repeated letters collapsed, so that "fuuuck" is caught
+/// without "boob" turning into "bob".
+struct BannedWords {
+    exact: Matcher,
+    collapsed: Matcher,
+}
+
+#[derive(Default)]
+struct Matcher {
+    substrings: Vec<String>,
+    words: HashSet<String>,
+}
+
+impl Matcher {
+    fn insert(&mut self, folded: String) {
+        let chars = folded.chars().count();
+        if chars >= MIN_SUBSTRING_CHARS {
+            self.substrings.push(folded);
+        } else if chars >= MIN_BANNED_CHARS {
+            self.words.insert(folded);
+        }
+    }
+
+    /// `words` are the folded words of a name and `squashed` all of them run together
+    fn matches(&self, words: &[String], squashed: &str) -> bool {
+        self.words.contains(squashed)
+            || words.iter().any(|word| self.words.contains(word))
+            || self
+                .substrings
+                .iter()
+                .any(|banned| squashed.contains(banned))
+    }
+}
+
+impl BannedWords {
+    fn new<'a>(banned: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut words = Self {
+            exact: Matcher::default(),
+            collapsed: Matcher::default(),
+        };
+        for word in banned {
+            let folded = fold(word);
+            if collapse_repeats(&folded) == folded {
+                words.collapsed.insert(folded.clone());
+            }
+            words.exact.insert(folded);
+        }
+        words
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        let words: Vec<String> = name.split(is_separator).map(fold).collect();
+        let collapsed: Vec<String> = words.iter().map(|w| collapse_repeats(w)).collect();
+        self.exact.matches(&words, &words.concat())
+            || self.collapsed.matches(&collapsed, &collapsed.concat())
+    }
+}
+
+/// Normalize a display name and check it against the rules, returning the form to store.
+///
+/// Names are NFKC normalized, trimmed and have runs of whitespace collapsed to one space. They
+/// must be [`MIN_CHARS`] to [`MAX_CHARS`] characters of letters, numbers, spaces, `_`, `-` and
+/// `.`, and must not contain a banned word, even spelled with lookalike letters or leetspeak.
+pub fn normalize(display_name: &str) -> Result<String, ErrorUser> {
+    let name = display_name
+        .nfkc()
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let chars = name.chars().count();
+    if chars < MIN_CHARS {
+        return Err(ErrorUser::DisplayNameTooShort(MIN_CHARS));
+    }
+    if chars > MAX_CHARS {
+        return Err(ErrorUser::DisplayNameTooLong(MAX_CHARS));
+    }
+    if !name.chars().all(|c| c.is_alphanumeric() || is_separator(c)) {
+        return Err(ErrorUser::DisplayNameInvalidCharacters);
+    }
+    if BANNED.matches(&name) {
+        return Err(ErrorUser::DisplayNameNotAllowed);
+    }
+    Ok(name)
+}
+
+fn is_separator(c: char) -> bool {
+    matches!(c, ' ' | '_' | '-' | '.')
+}
+
+/// Reduce text to the lowercase ASCII letters and digits it looks like: accents are dropped,
+/// lookalike letters from other scripts and leetspeak are mapped back, and anything else goes
+fn fold(text: &str) -> String {
+    text.nfkd()
+        .filter(|c| !is_combining_mark(*c))
+        .flat_map(char::to_lowercase)
+        .filter_map(|c| {
+            let c = unconfuse(c);
+            c.is_ascii_alphanumeric().then_some(c)
+        })
+        .collect()
+}
+
+fn unconfuse(c: char) -> char {
+    match c {
+        // Cyrillic and Greek letters that look like Latin ones
+        'а' | 'α' => 'a',
+        'в' | 'β' => 'b',
+        'с' | 'ϲ' => 'c',
+        'ԁ' => 'd',
+        'е' | 'ё' | 'ε' => 'e',
+        'һ' | 'н' | 'η' => 'h',
+        'к' | 'κ' => 'k',
+        'м' | 'μ' => 'm',
+        'п' | 'π' => 'n',
+        'о' | 'ο' | 'σ' => 'o',
+        'р' | 'ρ' => 'p',
+        'ԛ' => 'q',
+        'г' => 'r',
+        'ѕ' => 's',
+        'т' | 'τ' => 't',
+        'υ' | 'ц' => 'u',
+        'ν' => 'v',
+        'ш' | 'ω' => 'w',
+        'х' | 'χ' => 'x',
+        'у' | 'γ' => 'y',
+        'ј' => 'j',
+        // "1", "l", "|" and "!" all stand in for each other, so they fold to the same letter
+        'і' | 'ι' | 'l' | '1' | '|' | '!' => 'i',
+        // Leetspeak
+        '0' => 'o',
+        '3' => 'e',
+        '4' | '@' => 'a',
+        '5' | '$' => 's',
+        '7' | '+' => 't',
+        '8' => 'b',
+        '9' => 'g',
+        c => c,
+    }
+}
+
+fn collapse_repeats(text: &str) -> String {
+    let mut collapsed = String::with_capacity(text.len());
+    for c in text.chars() {
+        if !collapsed.ends_with(c) {
+            collapsed.push(c);
+        }
+    }
+    collapsed
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::{fold, normalize};
+    use crate::model::user::ErrorUser;
+
+    #[test_case("  Bob   the\tBuilder ", "Bob the Builder" ; "whitespace")]
+    #[test_case("Ｂｏｂ", "Bob" ; "fullwidth")]
+    #[test_case("Zoë", "Zoë" ; "accents")]
+    #[test_case("kyle_r-89.dev", "kyle_r-89.dev" ; "separators")]
+    fn normalizes(name: &str, expected: &str) {
+        assert_eq!(normalize(name), Ok(expected.to_string()));
+    }
+
+    #[test]
+    fn length_counts_characters() {
+        assert_eq!(normalize("al"), Err(ErrorUser::DisplayNameTooShort(3)));
+        assert_eq!(normalize(" a  "), Err(ErrorUser::DisplayNameTooShort(3)));
+        assert_eq!(normalize("Zoë"), Ok("Zoë".to_string()));
+        assert_eq!(normalize(&"é".repeat(32)), Ok("é".repeat(32)));
+        assert_eq!(
+            normalize(&"a".repeat(33)),
+            Err(ErrorUser::DisplayNameTooLong(32))
+        );
+    }
+
+    #[test_case("bob!" ; "punctuation")]
+    #[test_case("<script>" ; "markup")]
+    #[test_case("bob🔥" ; "emoji")]
+    #[test_case("bob\u{200b}smith" ; "zero width space")]
+    fn rejects_characters(name: &str) {
+        assert_eq!(
+            normalize(name),
+            Err(ErrorUser::DisplayNameInvalidCharacters)
+        );
+    }
+
+    #[test_case("Fuck")]
+    #[test_case("xX_shithead_Xx" ; "inside a word")]
+    #[test_case("f.u.c.k" ; "split up")]
+    #[test_case("SH1THEAD" ; "leetspeak")]
+    #[test_case("bu11sh1t" ; "leetspeak ones")]
+    #[test_case("fuuuuck" ; "repeated letters")]
+    #[test_case("ѕһіt" ; "cyrillic lookalikes")]
+    #[test_case("shït" ; "accents")]
+    #[test_case("big ass" ; "short word")]
+    #[test_case("Ass" ; "short word alone")]
+    fn rejects_banned_words(name: &str) {
+        assert_eq!(normalize(name), Err(ErrorUser::DisplayNameNotAllowed));
+    }
+
+    #[test_case("Cassandra")]
+    #[test_case("Thomas Hobbes")]
+    #[test_case("Scunthorpe United")]
+    #[test_case("Sussex")]
+    #[test_case("Bob the Builder")]
+    fn allows_innocent_names(name: &str) {
+        assert_eq!(normalize(name), Ok(name.to_string()));
+    }
+
+    #[test]
+    fn folds_lookalikes() {
+        assert_eq!(fold("Ｐ4ѕѕ w0rd!"), "passwordi");
+    }
+}
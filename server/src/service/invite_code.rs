@@ -0,0 +1,72 @@
+use std::collections::HashSet;
+
+use once_cell::sync::Lazy;
+use rand::{seq::SliceRandom, thread_rng};
+
+use crate::dictionary;
+
+/// Words in a code. 592 words to pick from gives about 207 million codes.
+pub const WORDS: usize = 3;
+const SEPARATOR: char = '-';
+
+static WORD_LIST: Lazy<Vec<&'static str>> = Lazy::new(dictionary::safe_words_4);
+static WORD_SET: Lazy<HashSet<&'static str>> = Lazy::new(|| WORD_LIST.iter().copied().collect());
+
+/// A new random code such as `acid-able-ache`, easy to read out to someone
+pub fn generate() -> String {
+    let mut rng = thread_rng();
+    (0..WORDS)
+        .filter_map(|_| WORD_LIST.choose(&mut rng).copied())
+        .collect::<Vec<_>>()
+        .join(&SEPARATOR.to_string())
+}
+
+/// The form a code is stored in, from however someone typed it: any case, with hyphens or
+/// spaces between the words. `None` when it can't be a code at all.
+pub fn normalize(code: &str) -> Option<String> {
+    let code = code.to_lowercase();
+    let words: Vec<&str> = code
+        .split(|c: char| c == SEPARATOR || c.is_whitespace())
+        .filter(|word| !word.is_empty())
+        .collect();
+
+    (words.len() == WORDS && words.iter().all(|word| WORD_SET.contains(word)))
+        .then(|| words.join(&SEPARATOR.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::{generate, normalize, WORD_LIST};
+    use crate::dictionary;
+
+    #[test]
+    fn banned_words_are_left_out() {
+        let banned: Vec<String> = dictionary::banned().map(str::to_lowercase).collect();
+        assert_eq!(WORD_LIST.len(), 592);
+        assert!(WORD_LIST.iter().all(|word| word.len() == 4));
+        assert!(!WORD_LIST
+            .iter()
+            .any(|word| banned.contains(&word.to_string())));
+    }
+
+    #[test]
+    fn generated_codes_are_three_known_words() {
+        for _ in 0..100 {
+            let code = generate();
+            assert_eq!(code.split('-').count(), 3);
+            assert_eq!(normalize(&code), Some(code));
+        }
+    }
+
+    #[test_case("acid-able-ache", Some("acid-able-ache") ; "as generated")]
+    #[test_case(" Acid Able  ACHE ", Some("acid-able-ache") ; "spoken")]
+    #[test_case("acid-able", None ; "too short")]
+    #[test_case("acid-able-ache-ajar", None ; "too long")]
+    #[test_case("acid-able-zzzz", None ; "unknown word")]
+    #[test_case("acid-able-tart", None ; "banned word")]
+    fn normalizes(code: &str, expected: Option<&str>) {
+        assert_eq!(normalize(code).as_deref(), expected);
+    }
+}
@@ -1,23 +1,53 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use jsonwebtoken::{encode, DecodingKey, EncodingKey, Header, Validation};
 use rsa::{
-    pkcs1::{EncodeRsaPrivateKey, EncodeRsaPublicKey},
-    pkcs8::LineEnding,
+    pkcs1::{DecodeRsaPrivateKey, DecodeRsaPublicKey, EncodeRsaPrivateKey, EncodeRsaPublicKey},
+    pkcs8::{DecodePrivateKey, DecodePublicKey, LineEnding},
+    traits::PublicKeyParts,
     RsaPrivateKey, RsaPublicKey,
 };
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::Digest;
+use tracing::{info, warn};
+use uuid::Uuid;
 
 use super::time;
+use crate::model::role::Role;
 
 const EXPIRATION_WITHIN_SEC: u64 = 60 * 5;
 const ONE_HOUR_SEC: u64 = 60 * 60;
+/// Impersonation tokens can't be refreshed and don't last long
+const IMPERSONATION_SEC: u64 = 60 * 15;
+const MFA_PENDING_SEC: u64 = 60 * 5;
+/// Audience of MFA pending tokens, so they are never accepted where full claims are expected
+const MFA_PENDING_AUDIENCE: &str = "mfa-pending";
+const OIDC_PENDING_SEC: u64 = 60 * 10;
+/// Audience of the cookie that carries an OpenID Connect login from start to callback
+const OIDC_PENDING_AUDIENCE: &str = "oidc-pending";
 const RSA_BITS: usize = 2048;
+const ALGORITHM: jsonwebtoken::Algorithm = jsonwebtoken::Algorithm::RS384;
+
+/// PEM encoded private key used to sign new tokens
+const ENV_SIGNING_KEY: &str = "JWT_SIGNING_KEY";
+/// Path to a PEM file holding the signing key, used when `JWT_SIGNING_KEY` is not set
+const ENV_SIGNING_KEY_FILE: &str = "JWT_SIGNING_KEY_FILE";
+/// One or more PEM encoded keys (public or private) that are still accepted but no longer sign
+const ENV_VERIFY_KEYS: &str = "JWT_VERIFY_KEYS";
+/// Comma separated paths to PEM files, each holding keys that are accepted but no longer sign
+const ENV_VERIFY_KEY_FILES: &str = "JWT_VERIFY_KEY_FILES";
 
 #[derive(Debug, thiserror::Error)]
 pub enum ErrorJwt {
     #[error("Failed to generate JWT service: {0}")]
     Generate(#[from] rsa::errors::Error),
+
+    #[error("Failed to read JWT key file {0}: {1}")]
+    KeyFile(String, String),
+
+    #[error("Failed to parse JWT key: {0}")]
+    KeyParse(String),
 }
 
 #[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
@@ -27,6 +57,24 @@ pub struct Claims {
     pub sub: u64,
     pub display_name: String,
     pub email: String,
+    #[serde(default)]
+    pub email_verified: bool,
+    #[serde(default)]
+    pub role: Role,
+    /// Unique token id, so a single token can be revoked
+    pub jti: Uuid,
+    /// The session this token was issued for, absent for tokens not backed by a refresh token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<Uuid>,
+    /// The admin acting as `sub`, present on impersonation tokens (RFC 8693 `act`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<Actor>,
+}
+
+/// Who is really behind an impersonation token
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Copy)]
+pub struct Actor {
+    pub sub: u64,
 }
 
 impl Claims {
@@ -41,25 +89,192 @@ impl Claims {
             sub,
             display_name: display_name.into(),
             email: email.into(),
+            email_verified: false,
+            role: Role::default(),
+            jti: Uuid::new_v4(),
+            sid: None,
+            act: None,
         }
     }
+
+    pub fn with_email_verified(mut self, email_verified: bool) -> Self {
+        self.email_verified = email_verified;
+        self
+    }
+
+    pub fn with_role(mut self, role: Role) -> Self {
+        self.role = role;
+        self
+    }
+
+    pub fn with_session(mut self, sid: Uuid) -> Self {
+        self.sid = Some(sid);
+        self
+    }
+
+    /// Let the admin `actor` act as this user for a short while, without a session
+    pub fn impersonated_by(mut self, actor: u64) -> Self {
+        self.act = Some(Actor { sub: actor });
+        self.sid = None;
+        self.exp = self.iat + IMPERSONATION_SEC;
+        self
+    }
 }
 
-pub struct Jwt {
+/// Proof that a user got their password right, exchanged for full `Claims` once they pass
+/// their second factor
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct MfaPendingClaims {
+    pub iat: u64,
+    pub exp: u64,
+    pub sub: u64,
+    pub aud: String,
+    pub jti: Uuid,
+    /// The login was started by a client that wants the access token in the response body
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub token_in_body: bool,
+}
+
+impl MfaPendingClaims {
+    pub fn new(sub: u64) -> Self {
+        let now = time::now_unix().unwrap();
+        Self {
+            iat: now,
+            exp: now + MFA_PENDING_SEC,
+            sub,
+            aud: MFA_PENDING_AUDIENCE.to_string(),
+            jti: Uuid::new_v4(),
+            token_in_body: false,
+        }
+    }
+
+    pub fn with_token_in_body(mut self, token_in_body: bool) -> Self {
+        self.token_in_body = token_in_body;
+        self
+    }
+}
+
+/// An OpenID Connect login on its way through the provider, kept in a cookie until the
+/// provider redirects back
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct OidcPendingClaims {
+    pub iat: u64,
+    pub exp: u64,
+    pub aud: String,
+    pub provider: String,
+    pub state: String,
+    pub nonce: String,
+    /// PKCE secret, proves the callback comes from the browser that started the login
+    pub code_verifier: String,
+    /// The signed in user linking the provider to their account, absent when logging in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link_sub: Option<u64>,
+}
+
+impl OidcPendingClaims {
+    pub fn new(
+        provider: impl Into<String>,
+        state: impl Into<String>,
+        nonce: impl Into<String>,
+        code_verifier: impl Into<String>,
+    ) -> Self {
+        let now = time::now_unix().unwrap();
+        Self {
+            iat: now,
+            exp: now + OIDC_PENDING_SEC,
+            aud: OIDC_PENDING_AUDIENCE.to_string(),
+            provider: provider.into(),
+            state: state.into(),
+            nonce: nonce.into(),
+            code_verifier: code_verifier.into(),
+            link_sub: None,
+        }
+    }
+
+    pub fn with_link_sub(mut self, link_sub: Option<u64>) -> Self {
+        self.link_sub = link_sub;
+        self
+    }
+}
+
+// region: JSON Web Key Set
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct Jwk {
+    pub kty: String,
+    #[serde(rename = "use")]
+    pub use_: String,
+    pub alg: String,
+    pub kid: String,
+    pub n: String,
+    pub e: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct Jwks {
+    pub keys: Vec<Jwk>,
+}
+// endregion
+
+/// A public key tokens can be verified with, identified by its RFC 7638 thumbprint
+struct VerifyingKey {
+    kid: String,
     public_key: RsaPublicKey,
     decoding_key: DecodingKey,
+}
+
+impl VerifyingKey {
+    fn new(public_key: RsaPublicKey) -> Self {
+        let public_pem = public_key
+            .to_pkcs1_pem(LineEnding::LF)
+            .expect("Failed to serialize public key as PEM");
+
+        Self {
+            kid: thumbprint(&public_key),
+            decoding_key: DecodingKey::from_rsa_pem(public_pem.as_bytes())
+                .expect("Failed to create decoding key from public key"),
+            public_key,
+        }
+    }
+
+    fn jwk(&self) -> Jwk {
+        Jwk {
+            kty: "RSA".into(),
+            use_: "sig".into(),
+            alg: format!("{ALGORITHM:?}"),
+            kid: self.kid.clone(),
+            n: URL_SAFE_NO_PAD.encode(self.public_key.n().to_bytes_be()),
+            e: URL_SAFE_NO_PAD.encode(self.public_key.e().to_bytes_be()),
+        }
+    }
+}
 
+/// RFC 7638 JWK thumbprint: SHA-256 over the required members in lexicographic order
+fn thumbprint(public_key: &RsaPublicKey) -> String {
+    let e = URL_SAFE_NO_PAD.encode(public_key.e().to_bytes_be());
+    let n = URL_SAFE_NO_PAD.encode(public_key.n().to_bytes_be());
+    let canonical = format!(r#"{{"e":"{e}","kty":"RSA","n":"{n}"}}"#);
+    URL_SAFE_NO_PAD.encode(sha2::Sha256::digest(canonical.as_bytes()))
+}
+
+pub struct Jwt {
     private_key: RsaPrivateKey,
     encoding_key: EncodingKey,
+    signing_kid: String,
+
+    /// Every key a token may be signed with, including the current signing key
+    verifying_keys: HashMap<String, VerifyingKey>,
 
     validation: Validation,
+    mfa_pending_validation: Validation,
+    oidc_pending_validation: Validation,
 }
 
 impl std::fmt::Debug for Jwt {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Jwt")
-            .field("public_key", &self.public_key)
             .field("private_key", &self.private_key)
+            .field("signing_kid", &self.signing_kid)
+            .field("verifying_kids", &self.verifying_keys.keys())
             .field("validation", &self.validation)
             .finish()
     }
@@ -74,40 +289,169 @@ impl Jwt {
     }
 
     pub fn new(private_key: RsaPrivateKey) -> Self {
+        Self::with_verify_keys(private_key, vec![])
+    }
+
+    /// Sign with `private_key` while still accepting tokens signed by any of `verify_keys`
+    pub fn with_verify_keys(private_key: RsaPrivateKey, verify_keys: Vec<RsaPublicKey>) -> Self {
         let private_pem = private_key
             .to_pkcs1_pem(LineEnding::LF)
             .expect("Failed to serialize private key as PEM");
 
-        let public_key = RsaPublicKey::from(&private_key);
-        let public_pem = public_key
-            .to_pkcs1_pem(LineEnding::LF)
-            .expect("Failed to serialize public key as PEM");
+        let signing_key = VerifyingKey::new(RsaPublicKey::from(&private_key));
+        let signing_kid = signing_key.kid.clone();
 
-        let mut validation = Validation::new(jsonwebtoken::Algorithm::RS384);
+        let verifying_keys = std::iter::once(signing_key)
+            .chain(verify_keys.into_iter().map(VerifyingKey::new))
+            .map(|key| (key.kid.clone(), key))
+            .collect();
+
+        let mut validation = Validation::new(ALGORITHM);
         validation.validate_exp = true;
         validation.reject_tokens_expiring_in_less_than = EXPIRATION_WITHIN_SEC;
 
+        // These only live a few minutes, the margin above would reject them outright
+        let mut mfa_pending_validation = Validation::new(ALGORITHM);
+        mfa_pending_validation.set_audience(&[MFA_PENDING_AUDIENCE]);
+        let mut oidc_pending_validation = Validation::new(ALGORITHM);
+        oidc_pending_validation.set_audience(&[OIDC_PENDING_AUDIENCE]);
+
         Self {
-            public_key,
-            decoding_key: DecodingKey::from_rsa_pem(public_pem.as_bytes())
-                .expect("Failed to create decoding key from public key"),
-            private_key,
             encoding_key: EncodingKey::from_rsa_pem(private_pem.as_bytes())
                 .expect("Failed to create encoding key from private key"),
+            private_key,
+            signing_kid,
+            verifying_keys,
             validation,
+            mfa_pending_validation,
+            oidc_pending_validation,
         }
     }
 
+    /// Load keys from the environment. Returns `None` when no signing key is configured.
+    pub fn from_env() -> Result<Option<Self>, ErrorJwt> {
+        let signing_pem = match (
+            std::env::var(ENV_SIGNING_KEY).ok(),
+            std::env::var(ENV_SIGNING_KEY_FILE).ok(),
+        ) {
+            (Some(pem), _) => pem,
+            (None, Some(path)) => read_key_file(&path)?,
+            (None, None) => return Ok(None),
+        };
+        let private_key = parse_private_key(&signing_pem)?;
+
+        let mut verify_keys = vec![];
+        if let Ok(pems) = std::env::var(ENV_VERIFY_KEYS) {
+            verify_keys.extend(parse_public_keys(&pems)?);
+        }
+        if let Ok(paths) = std::env::var(ENV_VERIFY_KEY_FILES) {
+            for path in paths.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+                verify_keys.extend(parse_public_keys(&read_key_file(path)?)?);
+            }
+        }
+
+        Ok(Some(Self::with_verify_keys(private_key, verify_keys)))
+    }
+
+    /// The key id placed in the header of every token this instance signs
+    pub fn kid(&self) -> &str {
+        &self.signing_kid
+    }
+
     pub fn sign(&self, claims: &Claims) -> Result<String, String> {
-        let header = Header::new(jsonwebtoken::Algorithm::RS384);
-        let token = encode(&header, &claims, &self.encoding_key).map_err(|e| e.to_string())?;
-        Ok(token)
+        self.encode(claims)
     }
 
     pub fn verify(&self, token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
-        let result = jsonwebtoken::decode::<Claims>(token, &self.decoding_key, &self.validation);
+        self.decode(token, &self.validation)
+    }
+
+    pub fn sign_mfa_pending(&self, claims: &MfaPendingClaims) -> Result<String, String> {
+        self.encode(claims)
+    }
+
+    pub fn verify_mfa_pending(
+        &self,
+        token: &str,
+    ) -> Result<MfaPendingClaims, jsonwebtoken::errors::Error> {
+        self.decode(token, &self.mfa_pending_validation)
+    }
+
+    pub fn sign_oidc_pending(&self, claims: &OidcPendingClaims) -> Result<String, String> {
+        self.encode(claims)
+    }
+
+    pub fn verify_oidc_pending(
+        &self,
+        token: &str,
+    ) -> Result<OidcPendingClaims, jsonwebtoken::errors::Error> {
+        self.decode(token, &self.oidc_pending_validation)
+    }
+
+    fn encode(&self, claims: &impl Serialize) -> Result<String, String> {
+        let mut header = Header::new(ALGORITHM);
+        header.kid = Some(self.signing_kid.clone());
+        let token = encode(&header, claims, &self.encoding_key).map_err(|e| e.to_string())?;
+        Ok(token)
+    }
+
+    fn decode<T: DeserializeOwned>(
+        &self,
+        token: &str,
+        validation: &Validation,
+    ) -> Result<T, jsonwebtoken::errors::Error> {
+        let header = jsonwebtoken::decode_header(token)?;
+
+        // Tokens signed before key ids were introduced can only have come from the signing key
+        let kid = header.kid.as_deref().unwrap_or(&self.signing_kid);
+        let key = self
+            .verifying_keys
+            .get(kid)
+            .ok_or(jsonwebtoken::errors::ErrorKind::InvalidKeyFormat)?;
+
+        let result = jsonwebtoken::decode::<T>(token, &key.decoding_key, validation);
         Ok(result?.claims)
     }
+
+    /// The public half of every accepted key, signing key first
+    pub fn jwks(&self) -> Jwks {
+        let mut keys: Vec<Jwk> = self.verifying_keys.values().map(|k| k.jwk()).collect();
+        keys.sort_by_key(|k| k.kid != self.signing_kid);
+        Jwks { keys }
+    }
+}
+
+fn read_key_file(path: &str) -> Result<String, ErrorJwt> {
+    std::fs::read_to_string(path).map_err(|e| ErrorJwt::KeyFile(path.to_string(), e.to_string()))
+}
+
+fn parse_private_key(pem: &str) -> Result<RsaPrivateKey, ErrorJwt> {
+    let pem = pem.trim();
+    RsaPrivateKey::from_pkcs8_pem(pem)
+        .or_else(|_| RsaPrivateKey::from_pkcs1_pem(pem))
+        .map_err(|e| ErrorJwt::KeyParse(e.to_string()))
+}
+
+/// Parse every PEM block in `pems`. Private keys are reduced to their public half.
+fn parse_public_keys(pems: &str) -> Result<Vec<RsaPublicKey>, ErrorJwt> {
+    split_pem_blocks(pems)
+        .map(|pem| {
+            if pem.contains("PRIVATE KEY-----") {
+                parse_private_key(&pem).map(|k| RsaPublicKey::from(&k))
+            } else {
+                RsaPublicKey::from_public_key_pem(&pem)
+                    .or_else(|_| RsaPublicKey::from_pkcs1_pem(&pem))
+                    .map_err(|e| ErrorJwt::KeyParse(e.to_string()))
+            }
+        })
+        .collect()
+}
+
+fn split_pem_blocks(pems: &str) -> impl Iterator<Item = String> + '_ {
+    pems.split("-----BEGIN ")
+        .map(str::trim)
+        .filter(|block| !block.is_empty())
+        .map(|block| format!("-----BEGIN {block}"))
 }
 
 #[derive(Debug, Clone)]
@@ -117,9 +461,18 @@ pub struct JwtController {
 
 impl JwtController {
     pub(crate) fn new() -> Result<Self, ErrorJwt> {
-        Ok(Self {
-            jwt: Arc::new(Jwt::generate()?),
-        })
+        let jwt = match Jwt::from_env()? {
+            Some(jwt) => {
+                info!("🔑 Loaded JWT signing key {}", jwt.kid());
+                jwt
+            }
+            None => {
+                warn!("⚠️  {ENV_SIGNING_KEY} not set, generating an ephemeral JWT signing key. Tokens will not survive a restart!");
+                Jwt::generate()?
+            }
+        };
+
+        Ok(Self { jwt: Arc::new(jwt) })
     }
 
     pub fn sign(&self, claims: &Claims) -> Result<String, String> {
@@ -129,27 +482,70 @@ impl JwtController {
     pub fn verify(&self, token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
         self.jwt.verify(token)
     }
+
+    pub fn sign_mfa_pending(&self, claims: &MfaPendingClaims) -> Result<String, String> {
+        self.jwt.sign_mfa_pending(claims)
+    }
+
+    pub fn verify_mfa_pending(
+        &self,
+        token: &str,
+    ) -> Result<MfaPendingClaims, jsonwebtoken::errors::Error> {
+        self.jwt.verify_mfa_pending(token)
+    }
+
+    pub fn sign_oidc_pending(&self, claims: &OidcPendingClaims) -> Result<String, String> {
+        self.jwt.sign_oidc_pending(claims)
+    }
+
+    pub fn verify_oidc_pending(
+        &self,
+        token: &str,
+    ) -> Result<OidcPendingClaims, jsonwebtoken::errors::Error> {
+        self.jwt.verify_oidc_pending(token)
+    }
+
+    pub fn jwks(&self) -> Jwks {
+        self.jwt.jwks()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use std::time::{SystemTime, UNIX_EPOCH};
 
-    use crate::service::jwt::Claims;
+    use crate::{model::role::Role, service::jwt::Claims};
+
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use uuid::Uuid;
 
-    use super::Jwt;
+    use super::{
+        parse_private_key, parse_public_keys, Actor, Jwt, MfaPendingClaims, OidcPendingClaims,
+        IMPERSONATION_SEC,
+    };
 
     use jsonwebtoken::errors::ErrorKind;
     use lazy_static::lazy_static;
+    use rsa::{
+        pkcs1::EncodeRsaPublicKey,
+        pkcs8::{EncodePrivateKey, EncodePublicKey, LineEnding},
+        RsaPrivateKey, RsaPublicKey,
+    };
 
     lazy_static! {
         /// A Lazy static instance to use the same key across all tests
         static ref JWT: Jwt = Jwt::generate().unwrap();
+        /// A second key to test rotation
+        static ref OLD_KEY: RsaPrivateKey = RsaPrivateKey::new(&mut rand::thread_rng(), 2048).unwrap();
+    }
+
+    fn claims() -> Claims {
+        Claims::new(1, "Someone".to_string(), "someone@contoso.com".to_string())
     }
 
     #[test]
     fn round_trip_a_token() {
-        let claims = Claims::new(1, "Someone".to_string(), "someone@contoso.com".to_string());
+        let claims = claims();
 
         let token = JWT.sign(&claims).expect("Failed to sign token");
         let verified_claims = JWT.verify(&token).expect("Failed to verify token");
@@ -167,6 +563,11 @@ mod tests {
             sub: 1,
             display_name: "Someone".to_string(),
             email: "someone@contoso.com".to_string(),
+            email_verified: false,
+            role: Role::default(),
+            jti: uuid::Uuid::new_v4(),
+            sid: None,
+            act: None,
         };
 
         let token = JWT.sign(&claims).expect("Failed to sign token");
@@ -175,4 +576,109 @@ mod tests {
             .expect_err("Token should be invalid and expiring soon!");
         assert_eq!(result.kind(), &ErrorKind::ExpiredSignature);
     }
+
+    #[test]
+    fn impersonation_token_names_its_actor() {
+        let session = Uuid::new_v4();
+        let impersonated = claims().with_session(session).impersonated_by(7);
+        assert_eq!(impersonated.act, Some(Actor { sub: 7 }));
+        assert_eq!(impersonated.sid, None);
+        assert_eq!(impersonated.exp - impersonated.iat, IMPERSONATION_SEC);
+
+        let token = JWT.sign(&impersonated).unwrap();
+        let verified = JWT.verify(&token).unwrap();
+        assert_eq!(verified.act, Some(Actor { sub: 7 }));
+        assert_eq!(verified.sub, 1);
+
+        // Regular tokens leave the claim out entirely
+        let token = JWT.sign(&claims()).unwrap();
+        let payload = token.split('.').nth(1).unwrap();
+        let payload = String::from_utf8(URL_SAFE_NO_PAD.decode(payload).unwrap()).unwrap();
+        assert!(!payload.contains("\"act\""));
+    }
+
+    #[test]
+    fn mfa_pending_token_is_not_a_login() {
+        let pending = MfaPendingClaims::new(1);
+        let token = JWT.sign_mfa_pending(&pending).unwrap();
+        assert_eq!(JWT.verify_mfa_pending(&token).unwrap(), pending);
+        assert!(JWT.verify(&token).is_err());
+
+        let full = JWT.sign(&claims()).unwrap();
+        assert!(JWT.verify_mfa_pending(&full).is_err());
+    }
+
+    #[test]
+    fn oidc_pending_token_is_not_a_login() {
+        let pending = OidcPendingClaims::new("mock", "state", "nonce", "verifier");
+        let token = JWT.sign_oidc_pending(&pending).unwrap();
+        assert_eq!(JWT.verify_oidc_pending(&token).unwrap(), pending);
+        assert!(JWT.verify(&token).is_err());
+        assert!(JWT.verify_mfa_pending(&token).is_err());
+
+        let mfa_pending = JWT.sign_mfa_pending(&MfaPendingClaims::new(1)).unwrap();
+        assert!(JWT.verify_oidc_pending(&mfa_pending).is_err());
+    }
+
+    #[test]
+    fn token_header_has_kid() {
+        let token = JWT.sign(&claims()).unwrap();
+        let header = jsonwebtoken::decode_header(&token).unwrap();
+        assert_eq!(header.kid.as_deref(), Some(JWT.kid()));
+    }
+
+    #[test]
+    fn rotated_key_still_verifies() {
+        let claims = claims();
+        let old = Jwt::new(OLD_KEY.clone());
+        let token = old.sign(&claims).unwrap();
+
+        let rotated =
+            Jwt::with_verify_keys(JWT.private_key.clone(), vec![RsaPublicKey::from(&*OLD_KEY)]);
+        assert_eq!(rotated.verify(&token).unwrap(), claims);
+
+        // New tokens are signed with the new key
+        let token = rotated.sign(&claims).unwrap();
+        let header = jsonwebtoken::decode_header(&token).unwrap();
+        assert_eq!(header.kid.as_deref(), Some(JWT.kid()));
+    }
+
+    #[test]
+    fn unknown_key_is_rejected() {
+        let old = Jwt::new(OLD_KEY.clone());
+        let token = old.sign(&claims()).unwrap();
+
+        assert!(JWT.verify(&token).is_err());
+    }
+
+    #[test]
+    fn jwks_lists_signing_key_first() {
+        let rotated =
+            Jwt::with_verify_keys(JWT.private_key.clone(), vec![RsaPublicKey::from(&*OLD_KEY)]);
+        let jwks = rotated.jwks();
+
+        assert_eq!(jwks.keys.len(), 2);
+        assert_eq!(jwks.keys[0].kid, JWT.kid());
+        assert_eq!(jwks.keys[0].alg, "RS384");
+        assert_eq!(jwks.keys[0].e, "AQAB");
+    }
+
+    #[test]
+    fn parse_pkcs8_private_key() {
+        let pem = OLD_KEY.to_pkcs8_pem(LineEnding::LF).unwrap();
+        assert_eq!(parse_private_key(&pem).unwrap(), *OLD_KEY);
+    }
+
+    #[test]
+    fn parse_several_public_keys() {
+        let public = RsaPublicKey::from(&*OLD_KEY);
+        let pems = format!(
+            "{}\n{}",
+            public.to_public_key_pem(LineEnding::LF).unwrap(),
+            public.to_pkcs1_pem(LineEnding::LF).unwrap()
+        );
+
+        let keys = parse_public_keys(&pems).unwrap();
+        assert_eq!(keys, vec![public.clone(), public]);
+    }
 }
@@ -1,4 +1,14 @@
+pub mod audit;
 pub mod crypto;
 pub mod db;
+pub mod display_name;
+pub mod invite_code;
 pub mod jwt;
+pub mod mailer;
+pub mod oidc;
+pub mod password;
+pub mod password_policy;
+pub mod revocation;
+pub mod throttle;
 pub mod time;
+pub mod totp;
@@ -0,0 +1,462 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{
+    jwk::{Jwk, JwkSet},
+    Algorithm, DecodingKey, Validation,
+};
+use serde::{Deserialize, Serialize};
+use sha2::Digest;
+use subtle::ConstantTimeEq;
+use tokio::sync::RwLock;
+use tracing::{debug, info};
+
+use crate::service::{crypto, jwt::OidcPendingClaims};
+
+/// Comma separated names of the identity providers to offer, e.g. `google,microsoft`. Each one
+/// is configured with `OIDC_<NAME>_ISSUER`, `OIDC_<NAME>_CLIENT_ID` and, for confidential
+/// clients, `OIDC_<NAME>_CLIENT_SECRET`.
+const ENV_PROVIDERS: &str = "OIDC_PROVIDERS";
+
+const SCOPES: &str = "openid email profile";
+const STATE_BYTES: usize = 16;
+const NONCE_BYTES: usize = 16;
+const CODE_VERIFIER_BYTES: usize = 32;
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Algorithms an ID token may be signed with. Anything symmetric would let the public client
+/// id double as a key, so only asymmetric ones are accepted.
+const ID_TOKEN_ALGORITHMS: [Algorithm; 9] = [
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::ES384,
+    Algorithm::EdDSA,
+];
+
+#[derive(Debug, thiserror::Error, PartialEq, Clone, Serialize)]
+pub enum ErrorOidc {
+    #[error("Invalid OpenID Connect config: {0}")]
+    Config(String),
+
+    #[error("Unknown identity provider '{0}'")]
+    UnknownProvider(String),
+
+    #[error("Identity provider request failed: {0}")]
+    Http(String),
+
+    #[error("Identity provider metadata is invalid: {0}")]
+    Discovery(String),
+
+    #[error("Login state does not match, start the login again")]
+    StateMismatch,
+
+    #[error("Identity provider refused the login: {0}")]
+    Provider(String),
+
+    #[error("ID token is invalid: {0}")]
+    IdToken(String),
+}
+
+impl From<reqwest::Error> for ErrorOidc {
+    fn from(e: reqwest::Error) -> Self {
+        ErrorOidc::Http(e.to_string())
+    }
+}
+
+/// A registered client at an identity provider
+#[derive(Debug, Clone)]
+pub struct OidcProvider {
+    /// Our name for the provider, used in routes and stored with every linked identity
+    pub name: String,
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub redirect_uri: String,
+}
+
+impl OidcProvider {
+    /// Read a provider's client from the environment, with its callback under `app_url`
+    fn from_env(name: &str, app_url: &str) -> Result<Self, ErrorOidc> {
+        let prefix = format!("OIDC_{}", name.to_uppercase());
+        let var = |key: &str| {
+            std::env::var(format!("{prefix}_{key}"))
+                .ok()
+                .filter(|value| !value.is_empty())
+        };
+        let required = |key: &str| {
+            var(key).ok_or_else(|| ErrorOidc::Config(format!("{prefix}_{key} not set")))
+        };
+
+        Ok(Self {
+            name: name.to_string(),
+            issuer: required("ISSUER")?,
+            client_id: required("CLIENT_ID")?,
+            client_secret: var("CLIENT_SECRET"),
+            redirect_uri: format!("{app_url}/api/oidc/{name}/callback"),
+        })
+    }
+}
+
+/// Who the identity provider says signed in
+#[derive(Debug, Clone, PartialEq)]
+pub struct OidcProfile {
+    pub provider: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub name: Option<String>,
+}
+
+/// The parts of the provider's discovery document we use
+#[derive(Debug, Clone, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Debug)]
+struct Provider {
+    config: OidcProvider,
+    /// Fetched on first use
+    metadata: RwLock<Option<ProviderMetadata>>,
+    /// Fetched on first use and again whenever a token names a key we haven't seen
+    jwks: RwLock<JwkSet>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenErrorResponse {
+    error: String,
+    error_description: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct IdTokenClaims {
+    sub: String,
+    nonce: Option<String>,
+    azp: Option<String>,
+    email: Option<String>,
+    /// A bool, though some providers send it as a string
+    email_verified: Option<serde_json::Value>,
+    name: Option<String>,
+    preferred_username: Option<String>,
+}
+
+/// Runs OpenID Connect logins: the authorization code flow with PKCE, checking state and nonce
+/// and validating the ID token against the provider's published keys
+#[derive(Debug, Clone)]
+pub struct OidcController {
+    providers: Arc<HashMap<String, Provider>>,
+    http: reqwest::Client,
+}
+
+impl OidcController {
+    pub fn new(providers: Vec<OidcProvider>) -> Result<Self, ErrorOidc> {
+        let http = reqwest::Client::builder()
+            .timeout(HTTP_TIMEOUT)
+            // Endpoints come from the issuer's own metadata, nothing should bounce elsewhere
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .map_err(|e| ErrorOidc::Config(e.to_string()))?;
+
+        let providers = providers
+            .into_iter()
+            .map(|mut config| {
+                config.issuer = config.issuer.trim_end_matches('/').to_string();
+                let provider = Provider {
+                    config,
+                    metadata: RwLock::default(),
+                    jwks: RwLock::new(JwkSet { keys: vec![] }),
+                };
+                (provider.config.name.clone(), provider)
+            })
+            .collect();
+
+        Ok(Self {
+            providers: Arc::new(providers),
+            http,
+        })
+    }
+
+    /// The providers named in the environment, none when it isn't set
+    pub fn from_env(app_url: &str) -> Result<Self, ErrorOidc> {
+        let names = std::env::var(ENV_PROVIDERS).unwrap_or_default();
+        let providers = names
+            .split(',')
+            .map(|name| name.trim().to_lowercase())
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                // The name becomes part of environment variable names and routes
+                if !name.chars().all(|c| c.is_ascii_alphanumeric()) {
+                    return Err(ErrorOidc::Config(format!(
+                        "{ENV_PROVIDERS}: '{name}' must be letters and digits"
+                    )));
+                }
+                OidcProvider::from_env(&name, app_url)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if !providers.is_empty() {
+            let names: Vec<&str> = providers.iter().map(|p| p.name.as_str()).collect();
+            info!("🪪 OpenID Connect login with {}", names.join(", "));
+        }
+        Self::new(providers)
+    }
+
+    /// Names of the configured providers, sorted
+    pub fn provider_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.providers.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Start a login, returning where to send the browser and what to remember until it comes
+    /// back. `link_sub` is the signed in user when linking a provider to their account.
+    pub async fn authorize(
+        &self,
+        provider: &str,
+        link_sub: Option<u64>,
+    ) -> Result<(String, OidcPendingClaims), ErrorOidc> {
+        let provider = self.provider(provider)?;
+        let metadata = self.metadata(provider).await?;
+
+        let pending = OidcPendingClaims::new(
+            &provider.config.name,
+            crypto::token(STATE_BYTES),
+            crypto::token(NONCE_BYTES),
+            crypto::token(CODE_VERIFIER_BYTES),
+        )
+        .with_link_sub(link_sub);
+
+        let url = reqwest::Url::parse_with_params(
+            &metadata.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", &provider.config.client_id),
+                ("redirect_uri", &provider.config.redirect_uri),
+                ("scope", SCOPES),
+                ("state", &pending.state),
+                ("nonce", &pending.nonce),
+                ("code_challenge", &pkce_challenge(&pending.code_verifier)),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .map_err(|e| ErrorOidc::Discovery(e.to_string()))?;
+
+        Ok((url.into(), pending))
+    }
+
+    /// Finish a login the provider redirected back with `state` and `code`
+    pub async fn callback(
+        &self,
+        pending: &OidcPendingClaims,
+        state: &str,
+        code: &str,
+    ) -> Result<OidcProfile, ErrorOidc> {
+        if !bool::from(state.as_bytes().ct_eq(pending.state.as_bytes())) {
+            return Err(ErrorOidc::StateMismatch);
+        }
+
+        let provider = self.provider(&pending.provider)?;
+        let metadata = self.metadata(provider).await?;
+        let id_token = self
+            .exchange_code(provider, &metadata, code, &pending.code_verifier)
+            .await?;
+
+        let header = jsonwebtoken::decode_header(&id_token)
+            .map_err(|e| ErrorOidc::IdToken(e.to_string()))?;
+        if !ID_TOKEN_ALGORITHMS.contains(&header.alg) {
+            return Err(ErrorOidc::IdToken(format!(
+                "{:?} is not an accepted algorithm",
+                header.alg
+            )));
+        }
+        let key = self
+            .decoding_key(provider, &metadata, header.kid.as_deref())
+            .await?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&metadata.issuer]);
+        validation.set_audience(&[&provider.config.client_id]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        let claims = jsonwebtoken::decode::<IdTokenClaims>(&id_token, &key, &validation)
+            .map_err(|e| ErrorOidc::IdToken(e.to_string()))?
+            .claims;
+
+        // Ties the token to the login we started, so a token issued for another one can't be
+        // replayed here
+        let nonce_matches = claims
+            .nonce
+            .as_deref()
+            .is_some_and(|nonce| bool::from(nonce.as_bytes().ct_eq(pending.nonce.as_bytes())));
+        if !nonce_matches {
+            return Err(ErrorOidc::IdToken("nonce does not match".to_string()));
+        }
+        if claims
+            .azp
+            .as_deref()
+            .is_some_and(|azp| azp != provider.config.client_id)
+        {
+            return Err(ErrorOidc::IdToken(
+                "issued to another client (azp)".to_string(),
+            ));
+        }
+
+        debug!("🪪 {} signed in as {}", provider.config.name, claims.sub);
+        Ok(OidcProfile {
+            provider: provider.config.name.clone(),
+            subject: claims.sub,
+            email: claims.email,
+            email_verified: match claims.email_verified {
+                Some(serde_json::Value::Bool(verified)) => verified,
+                Some(serde_json::Value::String(verified)) => verified == "true",
+                _ => false,
+            },
+            name: claims.name.or(claims.preferred_username),
+        })
+    }
+
+    fn provider(&self, name: &str) -> Result<&Provider, ErrorOidc> {
+        self.providers
+            .get(name)
+            .ok_or_else(|| ErrorOidc::UnknownProvider(name.to_string()))
+    }
+
+    async fn metadata(&self, provider: &Provider) -> Result<ProviderMetadata, ErrorOidc> {
+        if let Some(metadata) = provider.metadata.read().await.as_ref() {
+            return Ok(metadata.clone());
+        }
+
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            provider.config.issuer
+        );
+        let metadata: ProviderMetadata = self
+            .http
+            .get(&url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        // The document has to be about the issuer we asked, or its keys prove nothing
+        if metadata.issuer.trim_end_matches('/') != provider.config.issuer {
+            return Err(ErrorOidc::Discovery(format!(
+                "issuer is {}, expected {}",
+                metadata.issuer, provider.config.issuer
+            )));
+        }
+
+        debug!("🪪 Discovered {}", provider.config.issuer);
+        *provider.metadata.write().await = Some(metadata.clone());
+        Ok(metadata)
+    }
+
+    async fn exchange_code(
+        &self,
+        provider: &Provider,
+        metadata: &ProviderMetadata,
+        code: &str,
+        code_verifier: &str,
+    ) -> Result<String, ErrorOidc> {
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &provider.config.redirect_uri),
+            ("client_id", &provider.config.client_id),
+            ("code_verifier", code_verifier),
+        ];
+        if let Some(secret) = &provider.config.client_secret {
+            form.push(("client_secret", secret));
+        }
+
+        let response = self
+            .http
+            .post(&metadata.token_endpoint)
+            .form(&form)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let reason = match response.json::<TokenErrorResponse>().await {
+                Ok(TokenErrorResponse {
+                    error,
+                    error_description: Some(description),
+                }) => format!("{error}: {description}"),
+                Ok(TokenErrorResponse { error, .. }) => error,
+                Err(_) => status.to_string(),
+            };
+            return Err(ErrorOidc::Provider(reason));
+        }
+
+        let tokens: TokenResponse = response.json().await?;
+        Ok(tokens.id_token)
+    }
+
+    async fn decoding_key(
+        &self,
+        provider: &Provider,
+        metadata: &ProviderMetadata,
+        kid: Option<&str>,
+    ) -> Result<DecodingKey, ErrorOidc> {
+        if let Some(jwk) = find_key(&*provider.jwks.read().await, kid) {
+            return DecodingKey::from_jwk(&jwk).map_err(|e| ErrorOidc::IdToken(e.to_string()));
+        }
+
+        // A key we haven't seen, the provider may have rotated
+        let jwks: JwkSet = self
+            .http
+            .get(&metadata.jwks_uri)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let jwk = find_key(&jwks, kid);
+        *provider.jwks.write().await = jwks;
+
+        let jwk = jwk.ok_or_else(|| ErrorOidc::IdToken("signed with an unknown key".into()))?;
+        DecodingKey::from_jwk(&jwk).map_err(|e| ErrorOidc::IdToken(e.to_string()))
+    }
+}
+
+/// The key a token names, or the only key when it names none
+fn find_key(jwks: &JwkSet, kid: Option<&str>) -> Option<Jwk> {
+    match kid {
+        Some(kid) => jwks.find(kid).cloned(),
+        None if jwks.keys.len() == 1 => jwks.keys.first().cloned(),
+        None => None,
+    }
+}
+
+/// The S256 PKCE challenge sent in place of the verifier
+pub fn pkce_challenge(code_verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(sha2::Sha256::digest(code_verifier.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::pkce_challenge;
+
+    #[test]
+    fn pkce_challenge_is_unpadded_base64url_sha256() {
+        // echo -n <verifier> | sha256sum, then base64url without padding
+        assert_eq!(
+            pkce_challenge("dBjftJeZ4CVP-mJ92K9qGnsq0SGjPxXRw8bF-1Xe5ia6jN9kTAY"),
+            "X-BFLznVFpdwF1XVn-zSHO-n1dCJw99VCFiee4Z3TVk"
+        );
+    }
+}
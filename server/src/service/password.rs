@@ -0,0 +1,263 @@
+use argon2::{
+    password_hash::{rand_core::OsRng, SaltString},
+    Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version,
+};
+use once_cell::sync::Lazy;
+use sha2::Digest;
+use subtle::ConstantTimeEq;
+use tracing::warn;
+
+// OWASP recommended minimums for Argon2id
+const DEFAULT_MEMORY_KIB: u32 = 19 * 1024;
+const DEFAULT_ITERATIONS: u32 = 2;
+const DEFAULT_PARALLELISM: u32 = 1;
+
+const ENV_MEMORY_KIB: &str = "PASSWORD_ARGON2_MEMORY_KIB";
+const ENV_ITERATIONS: &str = "PASSWORD_ARGON2_ITERATIONS";
+const ENV_PARALLELISM: &str = "PASSWORD_ARGON2_PARALLELISM";
+
+static HASHER: Lazy<Hasher> = Lazy::new(|| {
+    Hasher::new(HashConfig::from_env()).unwrap_or_else(|e| {
+        warn!("⚠️  {e}, falling back to default Argon2 parameters");
+        Hasher::new(HashConfig::default()).expect("Default Argon2 parameters are valid")
+    })
+});
+
+#[derive(Debug, thiserror::Error, PartialEq, Clone)]
+pub enum ErrorHash {
+    #[error("Invalid Argon2 parameters: {0}")]
+    Params(String),
+
+    #[error("Failed to hash password: {0}")]
+    Hash(String),
+}
+
+/// The cost parameters used for newly hashed passwords
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HashConfig {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for HashConfig {
+    fn default() -> Self {
+        Self {
+            memory_kib: DEFAULT_MEMORY_KIB,
+            iterations: DEFAULT_ITERATIONS,
+            parallelism: DEFAULT_PARALLELISM,
+        }
+    }
+}
+
+impl HashConfig {
+    /// Read the cost parameters from the environment, using the defaults for anything unset
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        Self {
+            memory_kib: env_u32(ENV_MEMORY_KIB).unwrap_or(default.memory_kib),
+            iterations: env_u32(ENV_ITERATIONS).unwrap_or(default.iterations),
+            parallelism: env_u32(ENV_PARALLELISM).unwrap_or(default.parallelism),
+        }
+    }
+}
+
+fn env_u32(name: &str) -> Option<u32> {
+    let value = std::env::var(name).ok()?;
+    match value.parse() {
+        Ok(v) => Some(v),
+        Err(_) => {
+            warn!("⚠️  Ignoring {name}={value}, expected a positive integer");
+            None
+        }
+    }
+}
+
+/// Outcome of checking a password against a stored hash
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Verification {
+    Invalid,
+    Valid,
+    /// The password matched, but the stored hash should be replaced with [`hash`]
+    ValidNeedsRehash,
+}
+
+impl Verification {
+    pub fn is_valid(&self) -> bool {
+        !matches!(self, Verification::Invalid)
+    }
+}
+
+pub struct Hasher {
+    argon2: Argon2<'static>,
+}
+
+impl Hasher {
+    pub fn new(config: HashConfig) -> Result<Self, ErrorHash> {
+        let params = Params::new(
+            config.memory_kib,
+            config.iterations,
+            config.parallelism,
+            None,
+        )
+        .map_err(|e| ErrorHash::Params(e.to_string()))?;
+
+        Ok(Self {
+            argon2: Argon2::new(Algorithm::Argon2id, Version::V0x13, params),
+        })
+    }
+
+    /// Hash a password into a PHC string, e.g. `$argon2id$v=19$m=19456,t=2,p=1$<salt>$<hash>`
+    pub fn hash(&self, password: &str) -> Result<String, ErrorHash> {
+        let salt = SaltString::generate(&mut OsRng);
+        self.argon2
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(|e| ErrorHash::Hash(e.to_string()))
+    }
+
+    /// Verify a password against a PHC string, flagging hashes made with outdated parameters
+    pub fn verify(&self, password: &str, phc: &str) -> Verification {
+        let Ok(hash) = PasswordHash::new(phc) else {
+            warn!("⚠️  Stored password hash is not a valid PHC string");
+            return Verification::Invalid;
+        };
+
+        if self
+            .argon2
+            .verify_password(password.as_bytes(), &hash)
+            .is_err()
+        {
+            return Verification::Invalid;
+        }
+
+        if self.is_current(&hash) {
+            Verification::Valid
+        } else {
+            Verification::ValidNeedsRehash
+        }
+    }
+
+    fn is_current(&self, hash: &PasswordHash) -> bool {
+        let current = self.argon2.params();
+        hash.algorithm == Algorithm::Argon2id.ident()
+            && hash.version == Some(Version::V0x13.into())
+            && Params::try_from(hash).is_ok_and(|p| {
+                p.m_cost() == current.m_cost()
+                    && p.t_cost() == current.t_cost()
+                    && p.p_cost() == current.p_cost()
+            })
+    }
+}
+
+/// Hash a password with the configured Argon2id parameters
+pub fn hash(password: &str) -> Result<String, ErrorHash> {
+    HASHER.hash(password)
+}
+
+/// Verify a password against a PHC string produced by [`hash`]
+pub fn verify(password: &str, phc: &str) -> Verification {
+    HASHER.verify(password, phc)
+}
+
+/// Burn roughly the same time as a real verification, so unknown accounts can't be
+/// distinguished from wrong passwords by response time.
+pub fn verify_dummy(password: &str) {
+    static DUMMY: Lazy<String> =
+        Lazy::new(|| hash("dummy password").expect("Failed to hash dummy password"));
+    let _ = verify(password, &DUMMY);
+}
+
+/// Verify a password stored with the original single round of SHA-384 over `password + salt`.
+/// Only used to upgrade rows created before Argon2id.
+pub fn verify_legacy_sha384(password: &str, salt: &str, expected: &[u8]) -> Verification {
+    let password_salted = format!("{password}{salt}");
+    let actual = sha2::Sha384::digest(password_salted.as_bytes());
+
+    if bool::from(actual.as_slice().ct_eq(expected)) {
+        Verification::ValidNeedsRehash
+    } else {
+        Verification::Invalid
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sha2::Digest;
+
+    use super::{verify_legacy_sha384, HashConfig, Hasher, Verification};
+
+    fn cheap_config() -> HashConfig {
+        HashConfig {
+            memory_kib: 1024,
+            iterations: 1,
+            parallelism: 1,
+        }
+    }
+
+    #[test]
+    fn hash_is_phc_argon2id() {
+        let hasher = Hasher::new(cheap_config()).unwrap();
+        let phc = hasher.hash("correct horse battery staple").unwrap();
+        assert!(phc.starts_with("$argon2id$v=19$m=1024,t=1,p=1$"), "{phc}");
+    }
+
+    #[test]
+    fn verify_round_trip() {
+        let hasher = Hasher::new(cheap_config()).unwrap();
+        let phc = hasher.hash("correct horse battery staple").unwrap();
+
+        assert_eq!(
+            hasher.verify("correct horse battery staple", &phc),
+            Verification::Valid
+        );
+        assert_eq!(hasher.verify("wrong password", &phc), Verification::Invalid);
+    }
+
+    #[test]
+    fn same_password_hashes_differently() {
+        let hasher = Hasher::new(cheap_config()).unwrap();
+        let a = hasher.hash("correct horse battery staple").unwrap();
+        let b = hasher.hash("correct horse battery staple").unwrap();
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn changed_params_need_rehash() {
+        let old = Hasher::new(cheap_config()).unwrap();
+        let phc = old.hash("correct horse battery staple").unwrap();
+
+        let new = Hasher::new(HashConfig {
+            iterations: 2,
+            ..cheap_config()
+        })
+        .unwrap();
+        assert_eq!(
+            new.verify("correct horse battery staple", &phc),
+            Verification::ValidNeedsRehash
+        );
+    }
+
+    #[test]
+    fn garbage_phc_is_invalid() {
+        let hasher = Hasher::new(cheap_config()).unwrap();
+        assert_eq!(
+            hasher.verify("anything", "not-a-hash"),
+            Verification::Invalid
+        );
+    }
+
+    #[test]
+    fn legacy_sha384_always_needs_rehash() {
+        let expected = sha2::Sha384::digest("password1234somesalt".as_bytes()).to_vec();
+
+        assert_eq!(
+            verify_legacy_sha384("password1234", "somesalt", &expected),
+            Verification::ValidNeedsRehash
+        );
+        assert_eq!(
+            verify_legacy_sha384("password4321", "somesalt", &expected),
+            Verification::Invalid
+        );
+    }
+}
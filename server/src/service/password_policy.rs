@@ -0,0 +1,274 @@
+use std::collections::HashSet;
+
+use once_cell::sync::Lazy;
+use tracing::warn;
+
+use crate::model::user::ErrorPassword;
+
+const DEFAULT_MIN_CHARS: usize = 12;
+const DEFAULT_MAX_CHARS: usize = 128;
+/// Names and email addresses shorter than this are too likely to turn up by chance
+const MIN_PERSONAL_INFO_CHARS: usize = 3;
+
+const ENV_MIN_CHARS: &str = "PASSWORD_MIN_CHARS";
+const ENV_MAX_CHARS: &str = "PASSWORD_MAX_CHARS";
+
+const COMMON_PASSWORDS_RAW: &str = include_str!(concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/words",
+    "/common_passwords.txt"
+));
+
+static COMMON_PASSWORDS: Lazy<HashSet<&'static str>> =
+    Lazy::new(|| COMMON_PASSWORDS_RAW.lines().map(str::trim).collect());
+
+static POLICY: Lazy<PasswordPolicy> = Lazy::new(PasswordPolicy::from_env);
+
+/// Who a password is for, so it can't simply be their own name
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PasswordOwner<'a> {
+    pub email: &'a str,
+    pub display_name: &'a str,
+}
+
+/// One check a new password has to pass
+pub trait PasswordRule: Send + Sync {
+    fn check(&self, password: &str, owner: &PasswordOwner) -> Result<(), ErrorPassword>;
+}
+
+/// Length in Unicode characters, not bytes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Length {
+    pub min: usize,
+    pub max: usize,
+}
+
+impl PasswordRule for Length {
+    fn check(&self, password: &str, _owner: &PasswordOwner) -> Result<(), ErrorPassword> {
+        let chars = password.chars().count();
+        if chars < self.min {
+            return Err(ErrorPassword::TooShort(self.min));
+        }
+        if chars > self.max {
+            return Err(ErrorPassword::TooLong(self.max));
+        }
+        Ok(())
+    }
+}
+
+/// The password must not contain the owner's email address or display name
+#[derive(Debug, Clone, Copy)]
+pub struct NoPersonalInfo;
+
+impl PasswordRule for NoPersonalInfo {
+    fn check(&self, password: &str, owner: &PasswordOwner) -> Result<(), ErrorPassword> {
+        let password = password.to_lowercase();
+        let contains = |info: &str| {
+            let info = info.trim().to_lowercase();
+            info.chars().count() >= MIN_PERSONAL_INFO_CHARS && password.contains(&info)
+        };
+
+        let local_part = owner.email.split('@').next().unwrap_or_default();
+        if contains(owner.email) || contains(local_part) {
+            return Err(ErrorPassword::ContainsEmail);
+        }
+        if contains(owner.display_name) {
+            return Err(ErrorPassword::ContainsDisplayName);
+        }
+        Ok(())
+    }
+}
+
+/// The password must not be on the embedded list of common and breached passwords
+#[derive(Debug, Clone, Copy)]
+pub struct NotCommon;
+
+impl PasswordRule for NotCommon {
+    fn check(&self, password: &str, _owner: &PasswordOwner) -> Result<(), ErrorPassword> {
+        match COMMON_PASSWORDS.contains(password.to_lowercase().as_str()) {
+            true => Err(ErrorPassword::Common),
+            false => Ok(()),
+        }
+    }
+}
+
+/// The rules every new password is checked against, in order. The first one broken is reported.
+pub struct PasswordPolicy {
+    rules: Vec<Box<dyn PasswordRule>>,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self::new(Length {
+            min: DEFAULT_MIN_CHARS,
+            max: DEFAULT_MAX_CHARS,
+        })
+    }
+}
+
+impl PasswordPolicy {
+    /// The standard rules with the given length limits
+    pub fn new(length: Length) -> Self {
+        Self::empty()
+            .with_rule(length)
+            .with_rule(NoPersonalInfo)
+            .with_rule(NotCommon)
+    }
+
+    /// A policy that accepts anything, to build up with [`PasswordPolicy::with_rule`]
+    pub fn empty() -> Self {
+        Self { rules: Vec::new() }
+    }
+
+    pub fn with_rule(mut self, rule: impl PasswordRule + 'static) -> Self {
+        self.rules.push(Box::new(rule));
+        self
+    }
+
+    /// Read the length limits from the environment, using the defaults for anything unset
+    pub fn from_env() -> Self {
+        let min = env_usize(ENV_MIN_CHARS).unwrap_or(DEFAULT_MIN_CHARS);
+        let max = env_usize(ENV_MAX_CHARS).unwrap_or(DEFAULT_MAX_CHARS);
+        if min == 0 || min > max {
+            warn!("⚠️  Ignoring {ENV_MIN_CHARS}={min} and {ENV_MAX_CHARS}={max}, using defaults");
+            return Self::default();
+        }
+        Self::new(Length { min, max })
+    }
+
+    pub fn check(&self, password: &str, owner: &PasswordOwner) -> Result<(), ErrorPassword> {
+        self.rules
+            .iter()
+            .try_for_each(|rule| rule.check(password, owner))
+    }
+}
+
+/// Check a new password against the policy configured for this server
+pub fn check(password: &str, owner: &PasswordOwner) -> Result<(), ErrorPassword> {
+    POLICY.check(password, owner)
+}
+
+fn env_usize(name: &str) -> Option<usize> {
+    let value = std::env::var(name).ok()?;
+    match value.parse() {
+        Ok(v) => Some(v),
+        Err(_) => {
+            warn!("⚠️  Ignoring {name}={value}, expected a positive integer");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::{
+        Length, PasswordOwner, PasswordPolicy, PasswordRule, COMMON_PASSWORDS, DEFAULT_MIN_CHARS,
+    };
+    use crate::model::user::ErrorPassword;
+
+    const BOB: PasswordOwner = PasswordOwner {
+        email: "bob.smith@contoso.com",
+        display_name: "Bob the Builder",
+    };
+
+    #[test]
+    fn length_counts_characters_not_bytes() {
+        let policy = PasswordPolicy::default();
+        // Twice as many bytes as characters
+        assert_eq!(
+            policy.check("ééééééééééé", &BOB),
+            Err(ErrorPassword::TooShort(12))
+        );
+        assert_eq!(policy.check("éééééééééééé", &BOB), Ok(()));
+        // 11 characters, 44 bytes
+        assert_eq!(
+            policy.check("🔐🔐🔐🔐🔐🔐🔐🔐🔐🔐🔐", &BOB),
+            Err(ErrorPassword::TooShort(12))
+        );
+    }
+
+    #[test]
+    fn length_has_a_maximum() {
+        let policy = PasswordPolicy::new(Length { min: 4, max: 16 });
+        assert_eq!(policy.check(&"x7".repeat(8), &BOB), Ok(()));
+        assert_eq!(
+            policy.check(&"x7".repeat(9), &BOB),
+            Err(ErrorPassword::TooLong(16))
+        );
+    }
+
+    #[test_case("bob.smith@contoso.com!!" ; "whole address")]
+    #[test_case("my name is BOB.SMITH ok" ; "local part in any case")]
+    fn rejects_email(password: &str) {
+        assert_eq!(
+            PasswordPolicy::default().check(password, &BOB),
+            Err(ErrorPassword::ContainsEmail)
+        );
+    }
+
+    #[test]
+    fn rejects_display_name() {
+        assert_eq!(
+            PasswordPolicy::default().check("i am Bob The Builder!", &BOB),
+            Err(ErrorPassword::ContainsDisplayName)
+        );
+    }
+
+    #[test]
+    fn short_names_are_not_personal_info() {
+        let owner = PasswordOwner {
+            email: "al@contoso.com",
+            display_name: "al",
+        };
+        assert_eq!(
+            PasswordPolicy::default().check("a calm walrus naps", &owner),
+            Ok(())
+        );
+    }
+
+    #[test_case("password1234")]
+    #[test_case("Password1234" ; "in any case")]
+    #[test_case("qwertyuiopasdfghjkl")]
+    #[test_case("correct horse battery staple")]
+    fn rejects_common_passwords(password: &str) {
+        assert_eq!(
+            PasswordPolicy::default().check(password, &BOB),
+            Err(ErrorPassword::Common)
+        );
+    }
+
+    #[test]
+    fn common_passwords_could_pass_the_length_check() {
+        for password in COMMON_PASSWORDS.iter() {
+            assert!(password.chars().count() >= DEFAULT_MIN_CHARS, "{password}");
+            assert_eq!(*password, password.to_lowercase(), "{password}");
+        }
+    }
+
+    #[test]
+    fn accepts_a_good_password() {
+        assert_eq!(
+            PasswordPolicy::default().check("violet-harbor-tuesday-42", &BOB),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn rules_are_pluggable() {
+        struct NoSpaces;
+        impl PasswordRule for NoSpaces {
+            fn check(&self, password: &str, _: &PasswordOwner) -> Result<(), ErrorPassword> {
+                match password.contains(' ') {
+                    true => Err(ErrorPassword::Common),
+                    false => Ok(()),
+                }
+            }
+        }
+
+        let policy = PasswordPolicy::empty().with_rule(NoSpaces);
+        assert_eq!(policy.check("x", &BOB), Ok(()));
+        assert_eq!(policy.check("a b", &BOB), Err(ErrorPassword::Common));
+    }
+}
@@ -0,0 +1,181 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use diesel::prelude::*;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::{
+    db::{DbConn, DbPool},
+    schema::revoked_tokens,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ErrorRevocation {
+    #[error("Revocation list lock poisoned")]
+    Lock,
+
+    #[error("{0}")]
+    Db(String),
+}
+
+#[derive(Debug, Queryable, Selectable, Insertable)]
+#[diesel(table_name = crate::schema::revoked_tokens)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+struct RevokedToken {
+    jti: Uuid,
+    expires_at: SystemTime,
+}
+
+/// Revoked token ids, each kept only until the token would have expired anyway
+#[derive(Debug, Default)]
+pub struct RevocationList {
+    revoked: HashMap<Uuid, SystemTime>,
+}
+
+impl RevocationList {
+    pub fn insert(&mut self, jti: Uuid, expires_at: SystemTime) {
+        self.revoked.insert(jti, expires_at);
+    }
+
+    pub fn contains(&self, jti: &Uuid) -> bool {
+        self.revoked
+            .get(jti)
+            .is_some_and(|expires_at| *expires_at > SystemTime::now())
+    }
+
+    /// Drop every entry for a token that has expired by `now`
+    pub fn purge(&mut self, now: SystemTime) {
+        self.revoked.retain(|_, expires_at| *expires_at > now);
+    }
+
+    pub fn len(&self) -> usize {
+        self.revoked.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.revoked.is_empty()
+    }
+}
+
+/// Checks and records revoked access tokens. Lookups are served from memory, every revocation
+/// is written through to Postgres so it survives restarts and reaches other instances on sync.
+#[derive(Debug, Clone)]
+pub struct RevocationController {
+    list: Arc<RwLock<RevocationList>>,
+    db_pool: DbPool,
+}
+
+impl RevocationController {
+    pub async fn new(db_pool: DbPool) -> Result<Self, ErrorRevocation> {
+        let ctl = Self {
+            list: Arc::default(),
+            db_pool,
+        };
+        ctl.sync().await?;
+        Ok(ctl)
+    }
+
+    /// Revoke a token until its `exp` (seconds since the Unix epoch)
+    pub async fn revoke(&self, jti: Uuid, exp: u64) -> Result<(), ErrorRevocation> {
+        let expires_at = UNIX_EPOCH + Duration::from_secs(exp);
+        self.list
+            .write()
+            .map_err(|_| ErrorRevocation::Lock)?
+            .insert(jti, expires_at);
+
+        let mut conn = self.conn()?;
+        diesel::insert_into(revoked_tokens::table)
+            .values(RevokedToken { jti, expires_at })
+            .on_conflict_do_nothing()
+            .execute(&mut conn)
+            .map_err(|e| ErrorRevocation::Db(e.to_string()))?;
+
+        debug!("🚫 Revoked token {jti}");
+        Ok(())
+    }
+
+    pub fn is_revoked(&self, jti: &Uuid) -> bool {
+        match self.list.read() {
+            Ok(list) => list.contains(jti),
+            // Fail closed: if we can't tell, treat the token as revoked
+            Err(_) => true,
+        }
+    }
+
+    /// Purge expired revocations and load any recorded by other instances
+    pub async fn sync(&self) -> Result<(), ErrorRevocation> {
+        let now = SystemTime::now();
+        let mut conn = self.conn()?;
+
+        diesel::delete(revoked_tokens::table.filter(revoked_tokens::expires_at.le(now)))
+            .execute(&mut conn)
+            .map_err(|e| ErrorRevocation::Db(e.to_string()))?;
+
+        let stored: Vec<RevokedToken> = revoked_tokens::table
+            .select(RevokedToken::as_select())
+            .load(&mut conn)
+            .map_err(|e| ErrorRevocation::Db(e.to_string()))?;
+
+        let mut list = self.list.write().map_err(|_| ErrorRevocation::Lock)?;
+        list.purge(now);
+        for RevokedToken { jti, expires_at } in stored {
+            list.insert(jti, expires_at);
+        }
+
+        debug!("🚫 Revocation list synced: {} entries", list.len());
+        Ok(())
+    }
+
+    /// Keep syncing with Postgres in the background
+    pub fn spawn_sync(self, every: Duration) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(every);
+            loop {
+                interval.tick().await;
+                if let Err(e) = self.sync().await {
+                    warn!("⚠️  Failed to sync revocation list: {e}");
+                }
+            }
+        })
+    }
+
+    fn conn(&self) -> Result<DbConn, ErrorRevocation> {
+        self.db_pool
+            .get()
+            .map_err(|e| ErrorRevocation::Db(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use uuid::Uuid;
+
+    use super::RevocationList;
+
+    #[test]
+    fn revoked_token_is_contained() {
+        let mut list = RevocationList::default();
+        let jti = Uuid::new_v4();
+        list.insert(jti, SystemTime::now() + Duration::from_secs(60));
+
+        assert!(list.contains(&jti));
+        assert!(!list.contains(&Uuid::new_v4()));
+    }
+
+    #[test]
+    fn expired_entries_are_ignored_and_purged() {
+        let mut list = RevocationList::default();
+        let jti = Uuid::new_v4();
+        list.insert(jti, SystemTime::now() - Duration::from_secs(1));
+
+        assert!(!list.contains(&jti));
+        list.purge(SystemTime::now());
+        assert!(list.is_empty());
+    }
+}
@@ -0,0 +1,562 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    net::IpAddr,
+    str::FromStr,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use diesel::prelude::*;
+use serde::Serialize;
+use tracing::{debug, info, warn};
+
+use crate::{
+    db::{DbConn, DbPool},
+    schema::login_throttles,
+};
+
+/// Where failed attempts are kept: `memory` (the default) or `postgres` to survive restarts
+const ENV_STORE: &str = "LOGIN_THROTTLE_STORE";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ErrorThrottle {
+    #[error("Throttle lock poisoned")]
+    Lock,
+
+    #[error("{0}")]
+    Db(String),
+
+    #[error("{ENV_STORE}={0}, expected memory or postgres")]
+    Config(String),
+
+    #[error(
+        "Invalid throttle key '{0}', expected email:<address>, ip:<address>, invite-user:<id>, invite-ip:<address>, verify-email:<address> or verify-ip:<address>"
+    )]
+    InvalidKey(String),
+}
+
+/// What failed attempts are counted against
+#[derive(Debug, Clone, PartialEq)]
+pub enum ThrottleKey {
+    Email(String),
+    Ip(IpAddr),
+    /// A user guessing lobby invite codes, counted apart from their logins
+    InviteUser(i32),
+    InviteIp(IpAddr),
+    /// Verification emails resent to an address, counted apart from its logins
+    VerifyEmail(String),
+    VerifyIp(IpAddr),
+}
+
+impl fmt::Display for ThrottleKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ThrottleKey::Email(email) => write!(f, "email:{}", email.to_lowercase()),
+            ThrottleKey::Ip(ip) => write!(f, "ip:{ip}"),
+            ThrottleKey::InviteUser(user_id) => write!(f, "invite-user:{user_id}"),
+            ThrottleKey::InviteIp(ip) => write!(f, "invite-ip:{ip}"),
+            ThrottleKey::VerifyEmail(email) => write!(f, "verify-email:{}", email.to_lowercase()),
+            ThrottleKey::VerifyIp(ip) => write!(f, "verify-ip:{ip}"),
+        }
+    }
+}
+
+impl FromStr for ThrottleKey {
+    type Err = ErrorThrottle;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("email", email)) if !email.is_empty() => Ok(ThrottleKey::Email(email.into())),
+            Some(("ip", ip)) => ip
+                .parse()
+                .map(ThrottleKey::Ip)
+                .map_err(|_| ErrorThrottle::InvalidKey(s.to_string())),
+            Some(("invite-user", user_id)) => user_id
+                .parse()
+                .map(ThrottleKey::InviteUser)
+                .map_err(|_| ErrorThrottle::InvalidKey(s.to_string())),
+            Some(("invite-ip", ip)) => ip
+                .parse()
+                .map(ThrottleKey::InviteIp)
+                .map_err(|_| ErrorThrottle::InvalidKey(s.to_string())),
+            Some(("verify-email", email)) if !email.is_empty() => {
+                Ok(ThrottleKey::VerifyEmail(email.into()))
+            }
+            Some(("verify-ip", ip)) => ip
+                .parse()
+                .map(ThrottleKey::VerifyIp)
+                .map_err(|_| ErrorThrottle::InvalidKey(s.to_string())),
+            _ => Err(ErrorThrottle::InvalidKey(s.to_string())),
+        }
+    }
+}
+
+/// How quickly repeated failures are slowed down and then locked out
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ThrottlePolicy {
+    /// Failures allowed before any delay
+    pub free_attempts: u32,
+    /// Delay after the first failure past the free ones, doubling with each failure after
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// Failures after which the key is locked out instead
+    pub lockout_after: u32,
+    pub lockout: Duration,
+    /// A key with no failures for this long starts over
+    pub forget_after: Duration,
+}
+
+impl Default for ThrottlePolicy {
+    fn default() -> Self {
+        Self {
+            free_attempts: 5,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60 * 5),
+            lockout_after: 10,
+            lockout: Duration::from_secs(60 * 15),
+            forget_after: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+impl ThrottlePolicy {
+    /// How long a key is blocked for after its `failures`th failure
+    pub fn delay(&self, failures: u32) -> Option<Duration> {
+        if failures >= self.lockout_after {
+            return Some(self.lockout);
+        }
+        let over = failures.checked_sub(self.free_attempts)?.checked_sub(1)?;
+        let delay = self.base_delay.saturating_mul(2u32.saturating_pow(over));
+        Some(delay.min(self.max_delay))
+    }
+
+    pub fn is_lockout(&self, failures: u32) -> bool {
+        failures >= self.lockout_after
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Queryable, Selectable, Insertable, AsChangeset)]
+#[diesel(table_name = crate::schema::login_throttles)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(treat_none_as_null = true)]
+struct StoredAttempts {
+    key: String,
+    failures: i32,
+    last_failure_at: SystemTime,
+    blocked_until: Option<SystemTime>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Attempts {
+    pub failures: u32,
+    pub last_failure_at: SystemTime,
+    pub blocked_until: Option<SystemTime>,
+}
+
+/// A key that is currently blocked, as shown to admins
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Lockout {
+    pub key: String,
+    pub failures: u32,
+    pub last_failure_at: SystemTime,
+    pub blocked_until: SystemTime,
+    /// Blocked for the full lockout rather than a backoff delay
+    pub locked_out: bool,
+}
+
+/// Failed attempts per key
+#[derive(Debug, Default)]
+pub struct ThrottleList {
+    attempts: HashMap<String, Attempts>,
+}
+
+impl ThrottleList {
+    /// How much longer a key is blocked for, if it is
+    pub fn blocked_for(&self, key: &str, now: SystemTime) -> Option<Duration> {
+        self.attempts
+            .get(key)
+            .and_then(|a| a.blocked_until)
+            .and_then(|until| until.duration_since(now).ok())
+            .filter(|remaining| !remaining.is_zero())
+    }
+
+    pub fn record_failure(
+        &mut self,
+        key: String,
+        now: SystemTime,
+        policy: &ThrottlePolicy,
+    ) -> Attempts {
+        let attempts = self.attempts.entry(key).or_insert(Attempts {
+            failures: 0,
+            last_failure_at: now,
+            blocked_until: None,
+        });
+
+        if is_forgotten(attempts, now, policy) {
+            attempts.failures = 0;
+        }
+        attempts.failures += 1;
+        attempts.last_failure_at = now;
+        attempts.blocked_until = policy.delay(attempts.failures).map(|delay| now + delay);
+        *attempts
+    }
+
+    pub fn insert(&mut self, key: String, attempts: Attempts) {
+        self.attempts.insert(key, attempts);
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        self.attempts.remove(key);
+    }
+
+    /// Drop every key that has been quiet long enough to start over
+    pub fn purge(&mut self, now: SystemTime, policy: &ThrottlePolicy) {
+        self.attempts.retain(|_, a| !is_forgotten(a, now, policy));
+    }
+
+    pub fn blocked(&self, now: SystemTime) -> impl Iterator<Item = (&String, &Attempts)> {
+        self.attempts
+            .iter()
+            .filter(move |(_, a)| a.blocked_until.is_some_and(|until| until > now))
+    }
+
+    pub fn len(&self) -> usize {
+        self.attempts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.attempts.is_empty()
+    }
+}
+
+fn is_forgotten(attempts: &Attempts, now: SystemTime, policy: &ThrottlePolicy) -> bool {
+    let blocked = attempts.blocked_until.is_some_and(|until| until > now);
+    let quiet = now
+        .duration_since(attempts.last_failure_at)
+        .is_ok_and(|since| since >= policy.forget_after);
+    quiet && !blocked
+}
+
+/// Slows down and then locks out repeated failed logins and invite code guesses. Checks are
+/// served from memory, and with the Postgres store every change is written through so counters
+/// survive a restart.
+#[derive(Debug, Clone)]
+pub struct ThrottleController {
+    list: Arc<RwLock<ThrottleList>>,
+    policy: ThrottlePolicy,
+    db_pool: Option<DbPool>,
+}
+
+impl ThrottleController {
+    /// Keep counters in Postgres as well as memory when `db_pool` is given
+    pub async fn new(
+        policy: ThrottlePolicy,
+        db_pool: Option<DbPool>,
+    ) -> Result<Self, ErrorThrottle> {
+        let ctl = Self {
+            list: Arc::default(),
+            policy,
+            db_pool,
+        };
+        ctl.load()?;
+        Ok(ctl)
+    }
+
+    pub async fn from_env(db_pool: DbPool) -> Result<Self, ErrorThrottle> {
+        let db_pool = match std::env::var(ENV_STORE).as_deref() {
+            Ok("postgres") => {
+                info!("🚦 Login throttling persisted to Postgres");
+                Some(db_pool)
+            }
+            Ok("memory") | Err(_) => None,
+            Ok(other) => return Err(ErrorThrottle::Config(other.to_string())),
+        };
+        Self::new(ThrottlePolicy::default(), db_pool).await
+    }
+
+    /// `Err` with how long to wait if any of the keys is blocked
+    pub fn check(&self, keys: &[ThrottleKey]) -> Result<(), Duration> {
+        let now = SystemTime::now();
+        let Ok(list) = self.list.read() else {
+            // Fail closed: if we can't tell, make them wait
+            return Err(self.policy.base_delay);
+        };
+
+        match keys
+            .iter()
+            .filter_map(|key| list.blocked_for(&key.to_string(), now))
+            .max()
+        {
+            Some(remaining) => Err(remaining),
+            None => Ok(()),
+        }
+    }
+
+    /// Count a failed attempt against every key
+    pub async fn record_failure(&self, keys: &[ThrottleKey]) -> Result<(), ErrorThrottle> {
+        let now = SystemTime::now();
+        let recorded: Vec<(String, Attempts)> = {
+            let mut list = self.list.write().map_err(|_| ErrorThrottle::Lock)?;
+            keys.iter()
+                .map(|key| {
+                    let key = key.to_string();
+                    let attempts = list.record_failure(key.clone(), now, &self.policy);
+                    (key, attempts)
+                })
+                .collect()
+        };
+
+        for (key, attempts) in &recorded {
+            if self.policy.is_lockout(attempts.failures) {
+                warn!("🚦 Locked out {key} after {} failures", attempts.failures);
+            }
+        }
+
+        if let Some(mut conn) = self.conn()? {
+            for (key, attempts) in recorded {
+                let stored = StoredAttempts {
+                    key,
+                    failures: attempts.failures as i32,
+                    last_failure_at: attempts.last_failure_at,
+                    blocked_until: attempts.blocked_until,
+                };
+                diesel::insert_into(login_throttles::table)
+                    .values(&stored)
+                    .on_conflict(login_throttles::key)
+                    .do_update()
+                    .set(&stored)
+                    .execute(&mut conn)
+                    .map_err(|e| ErrorThrottle::Db(e.to_string()))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Forget every failure against a key, e.g. after a successful login or by an admin
+    pub async fn clear(&self, key: &ThrottleKey) -> Result<(), ErrorThrottle> {
+        let key = key.to_string();
+        self.list
+            .write()
+            .map_err(|_| ErrorThrottle::Lock)?
+            .remove(&key);
+
+        if let Some(mut conn) = self.conn()? {
+            diesel::delete(login_throttles::table.find(&key))
+                .execute(&mut conn)
+                .map_err(|e| ErrorThrottle::Db(e.to_string()))?;
+        }
+        debug!("🚦 Cleared {key}");
+        Ok(())
+    }
+
+    /// Every key that is blocked right now, longest block first
+    pub fn lockouts(&self) -> Result<Vec<Lockout>, ErrorThrottle> {
+        let now = SystemTime::now();
+        let list = self.list.read().map_err(|_| ErrorThrottle::Lock)?;
+
+        let mut lockouts: Vec<Lockout> = list
+            .blocked(now)
+            .filter_map(|(key, a)| {
+                Some(Lockout {
+                    key: key.clone(),
+                    failures: a.failures,
+                    last_failure_at: a.last_failure_at,
+                    blocked_until: a.blocked_until?,
+                    locked_out: self.policy.is_lockout(a.failures),
+                })
+            })
+            .collect();
+        lockouts.sort_by_key(|l| std::cmp::Reverse(l.blocked_until));
+        Ok(lockouts)
+    }
+
+    /// Drop keys that have been quiet long enough, from memory and Postgres
+    pub async fn purge(&self) -> Result<(), ErrorThrottle> {
+        let now = SystemTime::now();
+        self.list
+            .write()
+            .map_err(|_| ErrorThrottle::Lock)?
+            .purge(now, &self.policy);
+
+        if let Some(mut conn) = self.conn()? {
+            let quiet_since = now - self.policy.forget_after;
+            diesel::delete(
+                login_throttles::table
+                    .filter(login_throttles::last_failure_at.le(quiet_since))
+                    .filter(
+                        login_throttles::blocked_until
+                            .is_null()
+                            .or(login_throttles::blocked_until.le(now)),
+                    ),
+            )
+            .execute(&mut conn)
+            .map_err(|e| ErrorThrottle::Db(e.to_string()))?;
+        }
+        Ok(())
+    }
+
+    /// Keep purging in the background
+    pub fn spawn_purge(self, every: Duration) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(every);
+            loop {
+                interval.tick().await;
+                if let Err(e) = self.purge().await {
+                    warn!("⚠️  Failed to purge login throttles: {e}");
+                }
+            }
+        })
+    }
+
+    fn load(&self) -> Result<(), ErrorThrottle> {
+        let Some(mut conn) = self.conn()? else {
+            return Ok(());
+        };
+
+        let stored: Vec<StoredAttempts> = login_throttles::table
+            .select(StoredAttempts::as_select())
+            .load(&mut conn)
+            .map_err(|e| ErrorThrottle::Db(e.to_string()))?;
+
+        let mut list = self.list.write().map_err(|_| ErrorThrottle::Lock)?;
+        for s in stored {
+            list.insert(
+                s.key,
+                Attempts {
+                    failures: s.failures as u32,
+                    last_failure_at: s.last_failure_at,
+                    blocked_until: s.blocked_until,
+                },
+            );
+        }
+        list.purge(SystemTime::now(), &self.policy);
+
+        debug!("🚦 Login throttles loaded: {} keys", list.len());
+        Ok(())
+    }
+
+    fn conn(&self) -> Result<Option<DbConn>, ErrorThrottle> {
+        self.db_pool
+            .as_ref()
+            .map(|pool| pool.get().map_err(|e| ErrorThrottle::Db(e.to_string())))
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::{ThrottleKey, ThrottleList, ThrottlePolicy};
+
+    const SEC: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn key_round_trip() {
+        for key in [
+            ThrottleKey::Email("bob@contoso.com".into()),
+            ThrottleKey::Ip("10.0.0.1".parse().unwrap()),
+            ThrottleKey::Ip("::1".parse().unwrap()),
+            ThrottleKey::InviteUser(42),
+            ThrottleKey::InviteIp("::1".parse().unwrap()),
+            ThrottleKey::VerifyEmail("bob@contoso.com".into()),
+            ThrottleKey::VerifyIp("10.0.0.1".parse().unwrap()),
+        ] {
+            assert_eq!(key.to_string().parse::<ThrottleKey>().unwrap(), key);
+        }
+        for bad in [
+            "bob@contoso.com",
+            "email:",
+            "ip:not-an-ip",
+            "user:bob",
+            "invite-user:bob",
+            "verify-email:",
+        ] {
+            assert!(bad.parse::<ThrottleKey>().is_err());
+        }
+    }
+
+    #[test]
+    fn delay_backs_off_exponentially_then_locks_out() {
+        let policy = ThrottlePolicy::default();
+        let delays: Vec<Option<Duration>> = (1..=11).map(|n| policy.delay(n)).collect();
+        assert_eq!(
+            delays,
+            vec![
+                None,
+                None,
+                None,
+                None,
+                None,
+                Some(SEC),
+                Some(2 * SEC),
+                Some(4 * SEC),
+                Some(8 * SEC),
+                Some(policy.lockout),
+                Some(policy.lockout),
+            ]
+        );
+    }
+
+    #[test]
+    fn delay_is_capped() {
+        let policy = ThrottlePolicy {
+            lockout_after: u32::MAX,
+            ..ThrottlePolicy::default()
+        };
+        assert_eq!(policy.delay(100), Some(policy.max_delay));
+    }
+
+    #[test]
+    fn failures_block_until_delay_passes() {
+        let policy = ThrottlePolicy::default();
+        let mut list = ThrottleList::default();
+        let now = SystemTime::now();
+
+        for _ in 0..policy.free_attempts {
+            list.record_failure("email:bob".into(), now, &policy);
+        }
+        assert_eq!(list.blocked_for("email:bob", now), None);
+
+        list.record_failure("email:bob".into(), now, &policy);
+        assert_eq!(list.blocked_for("email:bob", now), Some(SEC));
+        assert_eq!(list.blocked_for("email:bob", now + SEC), None);
+        assert_eq!(list.blocked_for("ip:127.0.0.1", now), None);
+    }
+
+    #[test]
+    fn quiet_keys_start_over() {
+        let policy = ThrottlePolicy::default();
+        let mut list = ThrottleList::default();
+        let now = SystemTime::now();
+
+        for _ in 0..=policy.free_attempts {
+            list.record_failure("email:bob".into(), now, &policy);
+        }
+        let later = now + policy.forget_after;
+        let attempts = list.record_failure("email:bob".into(), later, &policy);
+        assert_eq!(attempts.failures, 1);
+
+        list.purge(later + policy.forget_after, &policy);
+        assert!(list.is_empty());
+    }
+
+    #[test]
+    fn lockout_is_not_purged_early() {
+        let policy = ThrottlePolicy {
+            forget_after: Duration::from_secs(60),
+            ..ThrottlePolicy::default()
+        };
+        let mut list = ThrottleList::default();
+        let now = SystemTime::now();
+
+        for _ in 0..policy.lockout_after {
+            list.record_failure("email:bob".into(), now, &policy);
+        }
+        list.purge(now + policy.forget_after, &policy);
+        assert!(list
+            .blocked_for("email:bob", now + policy.forget_after)
+            .is_some());
+    }
+}
@@ -0,0 +1,230 @@
+//! Time-based one-time passwords (RFC 6238), as used by authenticator apps
+
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use rand::{thread_rng, RngCore};
+use subtle::ConstantTimeEq;
+
+/// RFC 4226 recommends at least 128 bits, authenticator apps expect 160
+const SECRET_BYTES: usize = 20;
+const DEFAULT_DIGITS: u32 = 6;
+const DEFAULT_STEP_SEC: u64 = 30;
+/// Accept a code from one step either side of now, to allow for clock drift
+const SKEW_STEPS: u64 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Algorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl Algorithm {
+    fn name(&self) -> &'static str {
+        match self {
+            Algorithm::Sha1 => "SHA1",
+            Algorithm::Sha256 => "SHA256",
+            Algorithm::Sha512 => "SHA512",
+        }
+    }
+
+    fn hmac(&self, key: &[u8], message: &[u8]) -> Vec<u8> {
+        fn mac<M: Mac + hmac::digest::KeyInit>(key: &[u8], message: &[u8]) -> Vec<u8> {
+            let mut mac = <M as Mac>::new_from_slice(key).expect("HMAC takes keys of any size");
+            mac.update(message);
+            mac.finalize().into_bytes().to_vec()
+        }
+
+        match self {
+            Algorithm::Sha1 => mac::<Hmac<sha1::Sha1>>(key, message),
+            Algorithm::Sha256 => mac::<Hmac<sha2::Sha256>>(key, message),
+            Algorithm::Sha512 => mac::<Hmac<sha2::Sha512>>(key, message),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub struct Totp {
+    secret: Vec<u8>,
+    digits: u32,
+    step: u64,
+    algorithm: Algorithm,
+}
+
+impl std::fmt::Debug for Totp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Totp")
+            .field("digits", &self.digits)
+            .field("step", &self.step)
+            .field("algorithm", &self.algorithm)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Totp {
+    /// The settings every authenticator app supports: SHA-1, 6 digits, 30 second steps
+    pub fn new(secret: Vec<u8>) -> Self {
+        Self {
+            secret,
+            digits: DEFAULT_DIGITS,
+            step: DEFAULT_STEP_SEC,
+            algorithm: Algorithm::Sha1,
+        }
+    }
+
+    pub fn generate() -> Self {
+        let mut secret = vec![0u8; SECRET_BYTES];
+        thread_rng().fill_bytes(&mut secret);
+        Self::new(secret)
+    }
+
+    pub fn with_digits(mut self, digits: u32) -> Self {
+        self.digits = digits;
+        self
+    }
+
+    pub fn with_algorithm(mut self, algorithm: Algorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    pub fn secret(&self) -> &[u8] {
+        &self.secret
+    }
+
+    /// The secret as users type it into an authenticator app
+    pub fn secret_base32(&self) -> String {
+        BASE32_NOPAD.encode(&self.secret)
+    }
+
+    /// The `otpauth://` URI authenticator apps read from a QR code
+    pub fn uri(&self, issuer: &str, account: &str) -> String {
+        let label = urlencoding::encode(&format!("{issuer}:{account}")).into_owned();
+        format!(
+            "otpauth://totp/{label}?secret={}&issuer={}&algorithm={}&digits={}&period={}",
+            self.secret_base32(),
+            urlencoding::encode(issuer),
+            self.algorithm.name(),
+            self.digits,
+            self.step,
+        )
+    }
+
+    /// The time step a Unix timestamp falls in
+    pub fn step_at(&self, unix_time: u64) -> u64 {
+        unix_time / self.step
+    }
+
+    /// The code for a time step (RFC 4226 HOTP with the step as the counter)
+    pub fn code_for_step(&self, step: u64) -> String {
+        let hash = self.algorithm.hmac(&self.secret, &step.to_be_bytes());
+
+        // Dynamic truncation
+        let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+        let binary = u32::from_be_bytes([
+            hash[offset] & 0x7f,
+            hash[offset + 1],
+            hash[offset + 2],
+            hash[offset + 3],
+        ]);
+
+        let code = binary as u64 % 10u64.pow(self.digits);
+        format!("{code:0width$}", width = self.digits as usize)
+    }
+
+    pub fn code_at(&self, unix_time: u64) -> String {
+        self.code_for_step(self.step_at(unix_time))
+    }
+
+    /// Check a code against the steps around `unix_time`, returning the step it matched.
+    /// Every candidate is compared so the time taken doesn't depend on which one matched.
+    pub fn verify(&self, code: &str, unix_time: u64) -> Option<u64> {
+        let code = code.trim();
+        let now = self.step_at(unix_time);
+
+        let mut matched = None;
+        for step in now.saturating_sub(SKEW_STEPS)..=now + SKEW_STEPS {
+            let expected = self.code_for_step(step);
+            if bool::from(expected.as_bytes().ct_eq(code.as_bytes())) {
+                matched = Some(step);
+            }
+        }
+        matched
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::{Algorithm, Totp};
+
+    // RFC 6238 Appendix B. Each algorithm has its own seed, the ASCII digits repeated to
+    // the hash length.
+    const SEED_SHA1: &[u8] = b"12345678901234567890";
+    const SEED_SHA256: &[u8] = b"12345678901234567890123456789012";
+    const SEED_SHA512: &[u8] = b"1234567890123456789012345678901234567890123456789012345678901234";
+
+    #[test_case(59, Algorithm::Sha1, "94287082")]
+    #[test_case(59, Algorithm::Sha256, "46119246")]
+    #[test_case(59, Algorithm::Sha512, "90693936")]
+    #[test_case(1111111109, Algorithm::Sha1, "07081804")]
+    #[test_case(1111111109, Algorithm::Sha256, "68084774")]
+    #[test_case(1111111109, Algorithm::Sha512, "25091201")]
+    #[test_case(1111111111, Algorithm::Sha1, "14050471")]
+    #[test_case(1111111111, Algorithm::Sha256, "67062674")]
+    #[test_case(1111111111, Algorithm::Sha512, "99943326")]
+    #[test_case(1234567890, Algorithm::Sha1, "89005924")]
+    #[test_case(1234567890, Algorithm::Sha256, "91819424")]
+    #[test_case(1234567890, Algorithm::Sha512, "93441116")]
+    #[test_case(2000000000, Algorithm::Sha1, "69279037")]
+    #[test_case(2000000000, Algorithm::Sha256, "90698825")]
+    #[test_case(2000000000, Algorithm::Sha512, "38618901")]
+    #[test_case(20000000000, Algorithm::Sha1, "65353130")]
+    #[test_case(20000000000, Algorithm::Sha256, "77737706")]
+    #[test_case(20000000000, Algorithm::Sha512, "47863826")]
+    fn rfc6238_test_vectors(unix_time: u64, algorithm: Algorithm, expected: &str) {
+        let seed = match algorithm {
+            Algorithm::Sha1 => SEED_SHA1,
+            Algorithm::Sha256 => SEED_SHA256,
+            Algorithm::Sha512 => SEED_SHA512,
+        };
+        let totp = Totp::new(seed.to_vec())
+            .with_digits(8)
+            .with_algorithm(algorithm);
+
+        assert_eq!(totp.code_at(unix_time), expected);
+    }
+
+    #[test]
+    fn verify_allows_one_step_of_drift() {
+        let totp = Totp::new(SEED_SHA1.to_vec());
+        let code = totp.code_at(1111111111);
+        let step = totp.step_at(1111111111);
+
+        assert_eq!(totp.verify(&code, 1111111111), Some(step));
+        assert_eq!(totp.verify(&code, 1111111111 + 30), Some(step));
+        assert_eq!(totp.verify(&code, 1111111111 - 30), Some(step));
+        assert_eq!(totp.verify(&code, 1111111111 + 90), None);
+        assert_eq!(totp.verify("000000", 1111111111), None);
+    }
+
+    #[test]
+    fn six_digit_codes_keep_leading_zeros() {
+        // The 8 digit code at this time is 07081804
+        let totp = Totp::new(SEED_SHA1.to_vec());
+        assert_eq!(totp.code_at(1111111109), "081804");
+    }
+
+    #[test]
+    fn uri_for_authenticator_apps() {
+        let totp = Totp::new(SEED_SHA1.to_vec());
+        assert_eq!(totp.secret_base32(), "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+        assert_eq!(
+            totp.uri("RustWebApp", "bob@contoso.com"),
+            "otpauth://totp/RustWebApp%3Abob%40contoso.com\
+            ?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&issuer=RustWebApp\
+            &algorithm=SHA1&digits=6&period=30"
+        );
+    }
+}
@@ -1,34 +1,109 @@
 use axum::{
     body::Body,
-    http::{Request, Response, Uri},
+    http::{header, HeaderValue, Request, Response, Uri},
     response::IntoResponse,
     Json,
 };
 use error::MainError;
 use serde_json::json;
+use tower_cookies::{cookie::SameSite, Cookie};
 use tracing::info;
-use uuid::Uuid;
+
+use crate::model::session::SESSION_LIFETIME;
+use crate::web::request_id::RequestId;
 
 pub mod app_state;
+pub mod client_ip;
+pub mod config;
 pub mod ctx;
 pub mod error;
+pub mod request_id;
 pub mod routes;
+pub mod user_agent;
 
 pub const AUTH_HEADER: &str = "auth-token";
+pub const REFRESH_COOKIE: &str = "refresh-token";
+/// The refresh cookie is only ever sent to the token endpoints
+pub const REFRESH_COOKIE_PATH: &str = "/api/token";
+pub const OIDC_COOKIE: &str = "oidc-pending";
+/// The OpenID Connect cookie is only ever sent back to the provider callbacks
+pub const OIDC_COOKIE_PATH: &str = "/api/oidc";
+pub const CSRF_COOKIE: &str = "csrf-token";
+/// Header the client echoes the CSRF cookie back in
+pub const CSRF_HEADER: &str = "x-csrf-token";
+/// Header every response carries its request id in
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
 
-pub async fn main_request_mapper(req: Request<Body>) -> Request<Body> {
-    let path = req.uri().path();
-    let method = req.method();
+pub fn auth_cookie(token: String) -> Cookie<'static> {
+    let mut auth_cookie = Cookie::new(AUTH_HEADER, token);
+    auth_cookie.set_secure(true);
+    auth_cookie.set_same_site(SameSite::Lax);
+    // Set path so that the cookie is sent with every request, not just /api requests
+    auth_cookie.set_path("/");
+    auth_cookie
+}
 
-    info!("🏁 {method} {path}");
+pub fn refresh_cookie(token: String) -> Cookie<'static> {
+    Cookie::build((REFRESH_COOKIE, token))
+        .secure(true)
+        .http_only(true)
+        .same_site(SameSite::Strict)
+        .path(REFRESH_COOKIE_PATH)
+        .max_age(SESSION_LIFETIME.try_into().unwrap_or_default())
+        .build()
+}
 
-    req
+/// Carries an OpenID Connect login across the trip to the provider. Lax, so it comes back with
+/// the provider's top level redirect to the callback.
+pub fn oidc_cookie(token: String) -> Cookie<'static> {
+    Cookie::build((OIDC_COOKIE, token))
+        .secure(true)
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .path(OIDC_COOKIE_PATH)
+        .build()
+}
+
+/// Double submit CSRF token. Readable by the client, which sends it back in [`CSRF_HEADER`].
+pub fn csrf_cookie(token: String) -> Cookie<'static> {
+    Cookie::build((CSRF_COOKIE, token))
+        .secure(true)
+        .same_site(SameSite::Strict)
+        .path("/")
+        .build()
 }
 
-pub async fn main_response_mapper(uri: Uri, res: Response<Body>) -> Response<Body> {
-    // Uuid to correlate client and server logs
-    let uuid = Uuid::new_v4();
+/// A cookie that, once passed to `Cookies::remove`, clears the auth cookie
+pub fn auth_cookie_removal() -> Cookie<'static> {
+    Cookie::build(AUTH_HEADER).path("/").build()
+}
 
+/// A cookie that, once passed to `Cookies::remove`, clears the refresh cookie
+pub fn refresh_cookie_removal() -> Cookie<'static> {
+    Cookie::build(REFRESH_COOKIE)
+        .path(REFRESH_COOKIE_PATH)
+        .build()
+}
+
+/// A cookie that, once passed to `Cookies::remove`, clears the OpenID Connect cookie
+pub fn oidc_cookie_removal() -> Cookie<'static> {
+    Cookie::build(OIDC_COOKIE).path(OIDC_COOKIE_PATH).build()
+}
+
+pub async fn main_request_mapper(mut req: Request<Body>) -> Request<Body> {
+    // Uuid to correlate client and server logs and audit events
+    let request_id = RequestId::new();
+    info!("🏁 {} {} {}", req.method(), req.uri().path(), request_id.0);
+
+    req.extensions_mut().insert(request_id);
+    req
+}
+
+pub async fn main_response_mapper(
+    uri: Uri,
+    RequestId(uuid): RequestId,
+    res: Response<Body>,
+) -> Response<Body> {
     // Service error is for detailed server logging
     let service_error = res.extensions().get::<MainError>();
 
@@ -54,7 +129,17 @@ pub async fn main_response_mapper(uri: Uri, res: Response<Body>) -> Response<Bod
         info!("❌ Service: {e}");
     }
 
-    let res = client_error.unwrap_or(res);
+    let retry_after = service_error.and_then(|e| e.retry_after());
+    let mut res = client_error.unwrap_or(res);
+
+    if let Ok(request_id) = HeaderValue::from_str(&uuid.to_string()) {
+        res.headers_mut().insert(REQUEST_ID_HEADER, request_id);
+    }
+    if let Some(retry_after) = retry_after {
+        res.headers_mut()
+            .insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+    }
+
     let emoji = if res.status().is_success() {
         "✅"
     } else {
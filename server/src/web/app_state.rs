@@ -1,20 +1,44 @@
+use std::sync::Arc;
+
 use axum::extract::FromRef;
 
-use crate::{db::DbPool, model::lobby::LobbyController, service::jwt::JwtController};
+use crate::{
+    db::DbPool,
+    model::lobby::LobbyController,
+    service::{
+        jwt::JwtController,
+        mailer::{self, Mailer},
+        oidc::OidcController,
+        revocation::RevocationController,
+        throttle::ThrottleController,
+    },
+    web::config::Config,
+};
 
 #[derive(Clone)]
 pub struct AppState {
     pub db_pool: DbPool,
     pub ctl_lobby: LobbyController,
     pub ctl_jwt: JwtController,
+    pub ctl_revocation: RevocationController,
+    pub ctl_throttle: ThrottleController,
+    pub ctl_oidc: OidcController,
+    pub mailer: Arc<dyn Mailer>,
+    pub config: Config,
 }
 
 impl AppState {
     pub async fn new(db_pool: DbPool) -> anyhow::Result<Self> {
+        let config = Config::from_env();
         Ok(Self {
-            db_pool,
-            ctl_lobby: LobbyController::new().await?,
+            ctl_lobby: LobbyController::new(db_pool.clone()),
             ctl_jwt: JwtController::new()?,
+            ctl_revocation: RevocationController::new(db_pool.clone()).await?,
+            ctl_throttle: ThrottleController::from_env(db_pool.clone()).await?,
+            ctl_oidc: OidcController::from_env(&config.app_url)?,
+            mailer: mailer::from_env()?,
+            config,
+            db_pool,
         })
     }
 }
@@ -37,4 +61,34 @@ impl FromRef<AppState> for JwtController {
         app_state.ctl_jwt.clone()
     }
 }
+
+impl FromRef<AppState> for RevocationController {
+    fn from_ref(app_state: &AppState) -> RevocationController {
+        app_state.ctl_revocation.clone()
+    }
+}
+
+impl FromRef<AppState> for ThrottleController {
+    fn from_ref(app_state: &AppState) -> ThrottleController {
+        app_state.ctl_throttle.clone()
+    }
+}
+
+impl FromRef<AppState> for OidcController {
+    fn from_ref(app_state: &AppState) -> OidcController {
+        app_state.ctl_oidc.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<dyn Mailer> {
+    fn from_ref(app_state: &AppState) -> Arc<dyn Mailer> {
+        app_state.mailer.clone()
+    }
+}
+
+impl FromRef<AppState> for Config {
+    fn from_ref(app_state: &AppState) -> Config {
+        app_state.config.clone()
+    }
+}
 // endregion
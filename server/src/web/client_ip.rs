@@ -0,0 +1,47 @@
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+};
+
+use axum::{
+    extract::{ConnectInfo, FromRef, FromRequestParts},
+    http::request::Parts,
+};
+
+use crate::web::config::Config;
+
+/// The address of the client that sent a request, if it could be told.
+///
+/// Behind a reverse proxy every connection comes from the proxy, so set `CLIENT_IP_HEADER` to
+/// the header it puts the real address in (e.g. `Fly-Client-IP`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClientIp(pub Option<IpAddr>);
+
+#[async_trait::async_trait]
+impl<S> FromRequestParts<S> for ClientIp
+where
+    Config: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Infallible> {
+        let config = Config::from_ref(state);
+
+        let ip = match &config.client_ip_header {
+            Some(header) => parts
+                .headers
+                .get(header)
+                .and_then(|value| value.to_str().ok())
+                // X-Forwarded-For style headers list the client first
+                .and_then(|value| value.split(',').next())
+                .and_then(|ip| ip.trim().parse().ok()),
+            None => parts
+                .extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip()),
+        };
+
+        Ok(ClientIp(ip))
+    }
+}
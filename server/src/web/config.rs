@@ -0,0 +1,76 @@
+/// Base url of the client, used to build links in emails
+const ENV_APP_URL: &str = "APP_URL";
+/// Comma separated actions that need a verified email address: `login`, `lobby`
+const ENV_REQUIRE_VERIFIED_EMAIL: &str = "REQUIRE_VERIFIED_EMAIL";
+/// Header a trusted reverse proxy puts the client address in, e.g. `Fly-Client-IP`
+const ENV_CLIENT_IP_HEADER: &str = "CLIENT_IP_HEADER";
+
+const DEFAULT_APP_URL: &str = "http://localhost:5173";
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub app_url: String,
+    pub require_verified_email: RequireVerifiedEmail,
+    pub client_ip_header: Option<String>,
+}
+
+/// Which actions are blocked until a user has verified their email address
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RequireVerifiedEmail {
+    pub login: bool,
+    pub lobby: bool,
+}
+
+impl RequireVerifiedEmail {
+    fn parse(raw: &str) -> Self {
+        let actions: Vec<&str> = raw.split(',').map(str::trim).collect();
+        Self {
+            login: actions.contains(&"login"),
+            lobby: actions.contains(&"lobby"),
+        }
+    }
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        Self {
+            app_url: std::env::var(ENV_APP_URL)
+                .unwrap_or(DEFAULT_APP_URL.to_string())
+                .trim_end_matches('/')
+                .to_string(),
+            require_verified_email: std::env::var(ENV_REQUIRE_VERIFIED_EMAIL)
+                .map(|raw| RequireVerifiedEmail::parse(&raw))
+                .unwrap_or_default(),
+            client_ip_header: std::env::var(ENV_CLIENT_IP_HEADER)
+                .ok()
+                .filter(|header| !header.is_empty()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RequireVerifiedEmail;
+
+    #[test]
+    fn parse_require_verified_email() {
+        assert_eq!(
+            RequireVerifiedEmail::parse("login, lobby"),
+            RequireVerifiedEmail {
+                login: true,
+                lobby: true
+            }
+        );
+        assert_eq!(
+            RequireVerifiedEmail::parse("lobby"),
+            RequireVerifiedEmail {
+                login: false,
+                lobby: true
+            }
+        );
+        assert_eq!(
+            RequireVerifiedEmail::parse(""),
+            RequireVerifiedEmail::default()
+        );
+    }
+}
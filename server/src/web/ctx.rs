@@ -1,10 +1,99 @@
+use uuid::Uuid;
+
+use crate::{
+    model::{
+        access_token::{AccessToken, Scopes},
+        lobby::LobbyActor,
+        role::Role,
+    },
+    service::jwt::Claims,
+};
+
+/// Where the access token for a request came from
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum TokenSource {
+    #[default]
+    Cookie,
+    /// An `Authorization: Bearer` header
+    Bearer,
+}
+
 #[derive(Debug, Clone)]
 pub struct Ctx {
     pub account_id: u64,
+    pub role: Role,
+    pub token_source: TokenSource,
+    /// Id of the access token this request was authenticated with
+    pub jti: Option<Uuid>,
+    /// Expiry of that token, in seconds since the Unix epoch
+    pub exp: Option<u64>,
+    pub session_id: Option<Uuid>,
+    /// What this request may do, limited when it was made with a personal access token
+    pub scopes: Scopes,
+    pub access_token_id: Option<Uuid>,
+    /// The admin acting as this user, when the request is made with an impersonation token
+    pub impersonator: Option<u64>,
 }
 
 impl Ctx {
     pub fn new(account_id: u64) -> Self {
-        Self { account_id }
+        Self {
+            account_id,
+            role: Role::default(),
+            token_source: TokenSource::default(),
+            jti: None,
+            exp: None,
+            session_id: None,
+            scopes: Scopes::All,
+            access_token_id: None,
+            impersonator: None,
+        }
+    }
+
+    pub fn with_token_source(mut self, token_source: TokenSource) -> Self {
+        self.token_source = token_source;
+        self
+    }
+}
+
+impl From<&Claims> for Ctx {
+    fn from(claims: &Claims) -> Self {
+        Self {
+            account_id: claims.sub,
+            role: claims.role,
+            token_source: TokenSource::default(),
+            jti: Some(claims.jti),
+            exp: Some(claims.exp),
+            session_id: claims.sid,
+            scopes: Scopes::All,
+            access_token_id: None,
+            impersonator: claims.act.map(|act| act.sub),
+        }
+    }
+}
+
+impl From<&AccessToken> for Ctx {
+    fn from(token: &AccessToken) -> Self {
+        Self {
+            account_id: token.user_id as u64,
+            // Access tokens never carry more than a regular user's rights
+            role: Role::User,
+            token_source: TokenSource::Bearer,
+            jti: None,
+            exp: None,
+            session_id: None,
+            scopes: Scopes::Only(token.scopes.clone()),
+            access_token_id: Some(token.id),
+            impersonator: None,
+        }
+    }
+}
+
+impl From<&Ctx> for LobbyActor {
+    fn from(ctx: &Ctx) -> Self {
+        Self {
+            user_id: ctx.account_id as i32,
+            role: ctx.role,
+        }
     }
 }
@@ -1,7 +1,20 @@
 use axum::{http::StatusCode, response::IntoResponse};
 use serde::Serialize;
 
-use crate::model::user::{self, ErrorUser};
+use crate::{
+    model::{
+        access_token::{ErrorAccessToken, Scope},
+        audit::ErrorAudit,
+        friendship::ErrorFriendship,
+        identity::ErrorIdentity,
+        lobby::ErrorLobby,
+        mfa::ErrorMfa,
+        role::Role,
+        session::ErrorSession,
+        user::{self, ErrorUser},
+    },
+    service::oidc::ErrorOidc,
+};
 
 #[derive(Debug, Clone, Serialize, thiserror::Error)]
 #[serde(tag = "type", content = "data")]
@@ -16,15 +29,69 @@ pub enum MainError {
     #[error("Bad auth token: {0}")]
     AuthFailToken(String),
 
+    #[error("Auth token has been revoked")]
+    AuthFailTokenRevoked,
+
+    #[error("Authorization header is not a bearer token")]
+    AuthFailBadAuthorizationHeader,
+
+    #[error("Access token is missing scope {0}")]
+    MissingScope(Scope),
+
+    #[error("Route needs a session, access tokens are not accepted")]
+    SessionRequired,
+
+    #[error("Route needs the {0} role")]
+    MissingRole(Role),
+
+    #[error("Route can't be used while impersonating a user")]
+    ImpersonationForbidden,
+
+    #[error("CSRF token missing or does not match")]
+    CsrfTokenMismatch,
+
     #[error("Auth Ctx not in request")]
     AuthFailCtxNotInRequest,
 
+    #[error("No refresh-token cookie found")]
+    AuthFailNoRefreshTokenCookie,
+
+    #[error("Email address is not verified")]
+    EmailNotVerified,
+
+    #[error("Too many failed attempts, retry after {0}s")]
+    TooManyAttempts(u64),
+
     #[error("Internal server error: {0}")]
     Internal(String),
 
     #[error(transparent)]
     User(#[from] user::ErrorUser),
 
+    #[error(transparent)]
+    Session(#[from] ErrorSession),
+
+    #[error(transparent)]
+    Mfa(#[from] ErrorMfa),
+
+    #[error(transparent)]
+    AccessToken(#[from] ErrorAccessToken),
+
+    #[error(transparent)]
+    Oidc(#[from] ErrorOidc),
+
+    #[error(transparent)]
+    Identity(#[from] ErrorIdentity),
+
+    #[error(transparent)]
+    Audit(#[from] ErrorAudit),
+
+    #[error(transparent)]
+    Lobby(#[from] ErrorLobby),
+
+    #[error(transparent)]
+    Friendship(#[from] ErrorFriendship),
+
     #[error("Error: {0}")]
     ClientError(String),
 }
@@ -44,15 +111,128 @@ impl MainError {
             Self::LoginFail => (StatusCode::FORBIDDEN, ErrorClient::LoginFail),
             Self::AuthFailNoAuthTokenCookie
             | Self::AuthFailToken(_)
-            | Self::AuthFailCtxNotInRequest => (StatusCode::FORBIDDEN, ErrorClient::NoAuth),
+            | Self::AuthFailTokenRevoked
+            | Self::AuthFailBadAuthorizationHeader
+            | Self::AuthFailCtxNotInRequest
+            | Self::AuthFailNoRefreshTokenCookie => (StatusCode::FORBIDDEN, ErrorClient::NoAuth),
+            Self::MissingScope(_) | Self::SessionRequired => {
+                (StatusCode::FORBIDDEN, ErrorClient::InsufficientScope)
+            }
+            Self::MissingRole(_) => (StatusCode::FORBIDDEN, ErrorClient::Forbidden),
+            Self::ImpersonationForbidden => {
+                (StatusCode::FORBIDDEN, ErrorClient::ImpersonationForbidden)
+            }
+            Self::CsrfTokenMismatch => (StatusCode::FORBIDDEN, ErrorClient::CsrfFailed),
+            Self::EmailNotVerified => (StatusCode::FORBIDDEN, ErrorClient::EmailNotVerified),
+            Self::TooManyAttempts(_) => {
+                (StatusCode::TOO_MANY_REQUESTS, ErrorClient::TooManyAttempts)
+            }
             Self::Internal(_) => (StatusCode::INTERNAL_SERVER_ERROR, ErrorClient::ServiceError),
             Self::User(e) => e.into(),
+            Self::Audit(ErrorAudit::Db(_)) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, ErrorClient::ServiceError)
+            }
+            Self::Session(ErrorSession::Db(_)) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, ErrorClient::ServiceError)
+            }
+            Self::Session(ErrorSession::NotFound) => (
+                StatusCode::NOT_FOUND,
+                ErrorClient::NotFound(ErrorSession::NotFound.to_string()),
+            ),
+            Self::Session(_) => (StatusCode::FORBIDDEN, ErrorClient::NoAuth),
+            Self::Mfa(ErrorMfa::Db(_)) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, ErrorClient::ServiceError)
+            }
+            Self::Mfa(ErrorMfa::InvalidCode) => {
+                (StatusCode::FORBIDDEN, ErrorClient::InvalidMfaCode)
+            }
+            Self::Mfa(e) => (
+                StatusCode::BAD_REQUEST,
+                ErrorClient::BadRequest(e.to_string()),
+            ),
+            Self::AccessToken(ErrorAccessToken::Db(_)) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, ErrorClient::ServiceError)
+            }
+            Self::AccessToken(ErrorAccessToken::InvalidToken) => {
+                (StatusCode::FORBIDDEN, ErrorClient::NoAuth)
+            }
+            Self::AccessToken(ErrorAccessToken::NotFound) => (
+                StatusCode::NOT_FOUND,
+                ErrorClient::NotFound(ErrorAccessToken::NotFound.to_string()),
+            ),
+            Self::AccessToken(e) => (
+                StatusCode::BAD_REQUEST,
+                ErrorClient::BadRequest(e.to_string()),
+            ),
+            Self::Oidc(ErrorOidc::UnknownProvider(_)) => (
+                StatusCode::NOT_FOUND,
+                ErrorClient::NotFound(self.to_string()),
+            ),
+            Self::Oidc(ErrorOidc::StateMismatch | ErrorOidc::Provider(_)) => (
+                StatusCode::BAD_REQUEST,
+                ErrorClient::BadRequest(self.to_string()),
+            ),
+            // Details of a misbehaving provider are for the logs
+            Self::Oidc(_) => (StatusCode::BAD_GATEWAY, ErrorClient::IdentityProviderError),
+            Self::Identity(ErrorIdentity::Db(_)) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, ErrorClient::ServiceError)
+            }
+            Self::Identity(ErrorIdentity::NotFound) => (
+                StatusCode::NOT_FOUND,
+                ErrorClient::NotFound(self.to_string()),
+            ),
+            Self::Identity(e) => (
+                StatusCode::BAD_REQUEST,
+                ErrorClient::BadRequest(e.to_string()),
+            ),
+            Self::Lobby(ErrorLobby::NotFound | ErrorLobby::InviteNotFound) => (
+                StatusCode::NOT_FOUND,
+                ErrorClient::NotFound(self.to_string()),
+            ),
+            Self::Lobby(ErrorLobby::Forbidden) => (StatusCode::FORBIDDEN, ErrorClient::Forbidden),
+            Self::Lobby(ErrorLobby::InviteRequired) => (
+                StatusCode::FORBIDDEN,
+                ErrorClient::BadRequest(self.to_string()),
+            ),
+            Self::Lobby(ErrorLobby::Full | ErrorLobby::AlreadyMember) => (
+                StatusCode::CONFLICT,
+                ErrorClient::BadRequest(self.to_string()),
+            ),
+            Self::Lobby(
+                ErrorLobby::InvalidName | ErrorLobby::InvalidMaxPlayers | ErrorLobby::NotMember,
+            ) => (
+                StatusCode::BAD_REQUEST,
+                ErrorClient::BadRequest(self.to_string()),
+            ),
+            // Every attempt at a fresh code collided, which only a nearly full code space would do
+            Self::Lobby(ErrorLobby::Db(_) | ErrorLobby::Internal | ErrorLobby::InviteCodeTaken) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, ErrorClient::ServiceError)
+            }
+            Self::Friendship(ErrorFriendship::Db(_)) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, ErrorClient::ServiceError)
+            }
+            Self::Friendship(ErrorFriendship::NotFound) => (
+                StatusCode::NOT_FOUND,
+                ErrorClient::NotFound(self.to_string()),
+            ),
+            Self::Friendship(ErrorFriendship::SelfFriend) => (
+                StatusCode::BAD_REQUEST,
+                ErrorClient::BadRequest(self.to_string()),
+            ),
             Self::ClientError(e) => (
                 StatusCode::BAD_REQUEST,
                 ErrorClient::BadRequest(e.to_string()),
             ),
         }
     }
+
+    /// Seconds the client should wait before trying again, sent as `Retry-After`
+    pub fn retry_after(&self) -> Option<u64> {
+        match self {
+            Self::TooManyAttempts(secs) => Some(*secs),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, strum_macros::AsRefStr, thiserror::Error)]
@@ -63,12 +243,39 @@ pub enum ErrorClient {
     #[error("Authentication required")]
     NoAuth,
 
+    #[error("Access token does not allow this")]
+    InsufficientScope,
+
+    #[error("You do not have permission to do this")]
+    Forbidden,
+
+    #[error("Not allowed while impersonating a user")]
+    ImpersonationForbidden,
+
+    #[error("Request could not be verified, reload the page and try again")]
+    CsrfFailed,
+
+    #[error("Email address must be verified first")]
+    EmailNotVerified,
+
+    #[error("Two-factor code is invalid")]
+    InvalidMfaCode,
+
+    #[error("Too many failed attempts, try again later")]
+    TooManyAttempts,
+
+    #[error("Could not sign in with the identity provider, try again later")]
+    IdentityProviderError,
+
     #[error("Invalid request parameters")]
     InvalidParams,
 
     #[error("{0}")]
     BadRequest(String),
 
+    #[error("{0}")]
+    NotFound(String),
+
     #[error("Internal server error")]
     ServiceError,
 }
@@ -77,9 +284,18 @@ impl From<&ErrorUser> for (StatusCode, ErrorClient) {
     fn from(value: &ErrorUser) -> Self {
         match value {
             ErrorUser::DisplayNameAlreadyExists
+            | ErrorUser::DisplayNameTooShort(_)
+            | ErrorUser::DisplayNameTooLong(_)
+            | ErrorUser::DisplayNameInvalidCharacters
+            | ErrorUser::DisplayNameNotAllowed
             | ErrorUser::EmailAlreadyExists
             | ErrorUser::InvalidEmail
             | ErrorUser::InvalidCredentials
+            | ErrorUser::WrongPassword
+            | ErrorUser::WrongCode
+            | ErrorUser::ReauthRequired
+            | ErrorUser::InvalidToken
+            | ErrorUser::EmailAlreadyVerified
             | ErrorUser::NotFound => (
                 StatusCode::BAD_REQUEST,
                 ErrorClient::BadRequest(value.to_string()),
@@ -88,7 +304,9 @@ impl From<&ErrorUser> for (StatusCode, ErrorClient) {
                 StatusCode::BAD_REQUEST,
                 ErrorClient::BadRequest(e.to_string()),
             ),
-            ErrorUser::Db(_) => (StatusCode::INTERNAL_SERVER_ERROR, ErrorClient::ServiceError),
+            ErrorUser::Db(_) | ErrorUser::PasswordHash(_) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, ErrorClient::ServiceError)
+            }
         }
     }
 }
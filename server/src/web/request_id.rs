@@ -0,0 +1,70 @@
+use std::convert::Infallible;
+
+use axum::{
+    extract::{FromRef, FromRequestParts},
+    http::request::Parts,
+};
+use uuid::Uuid;
+
+use crate::{
+    model::session::SessionClient,
+    service::audit::AuditContext,
+    web::{config::Config, ctx::Ctx, error::MainError},
+};
+
+/// Correlates a request's logs, its error response and the audit events it records. Given to
+/// every request by `main_request_mapper`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RequestId(pub Uuid);
+
+impl RequestId {
+    pub fn new() -> Self {
+        Self(Uuid::new_v4())
+    }
+}
+
+impl Default for RequestId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait::async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for RequestId {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Infallible> {
+        Ok(parts
+            .extensions
+            .get::<RequestId>()
+            .copied()
+            .unwrap_or_default())
+    }
+}
+
+/// The signed in user, if any, and the device and request they are acting through. While an
+/// admin impersonates a user, the admin is the actor.
+#[async_trait::async_trait]
+impl<S> FromRequestParts<S> for AuditContext
+where
+    Config: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Infallible> {
+        let actor_id = parts
+            .extensions
+            .get::<Result<Ctx, MainError>>()
+            .and_then(|ctx| ctx.as_ref().ok())
+            .map(|ctx| ctx.impersonator.unwrap_or(ctx.account_id) as i32);
+        let client = SessionClient::from_request_parts(parts, state).await?;
+        let RequestId(request_id) = RequestId::from_request_parts(parts, state).await?;
+
+        Ok(AuditContext {
+            actor_id,
+            client,
+            request_id,
+        })
+    }
+}
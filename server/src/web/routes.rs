@@ -1,32 +1,197 @@
 use axum::{
     middleware,
-    routing::{get, post},
+    routing::{delete, get, patch, post, put},
     Router,
 };
 
-use crate::web::app_state::AppState;
+use tower_cookies::CookieManagerLayer;
 
+use crate::{
+    model::{access_token::Scope, role::Role},
+    mw::{
+        self,
+        auth::{refuse_impersonation, require_auth, require_role, require_scope},
+    },
+    web::{self, app_state::AppState},
+};
+
+mod access_token;
+mod account;
+mod admin;
+mod friend;
+mod jwks;
 mod lobby;
+mod oidc;
+mod password;
+mod session;
 mod status;
+mod token;
 mod user;
 
+/// Serve the API under `/api` next to `router`, behind every middleware layer
+pub async fn get_app(router: Router, app_state: AppState) -> anyhow::Result<Router> {
+    let api_routes = get_api_routes(&app_state).await?;
+
+    let app = router
+        .nest("/api", api_routes)
+        .layer(middleware::from_fn(mw::csrf::csrf_protect))
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            mw::audit::audit_impersonation,
+        ))
+        .layer(middleware::map_response(web::main_response_mapper))
+        .layer(middleware::from_fn_with_state(
+            app_state,
+            mw::auth::ctx_resolver,
+        ))
+        .layer(middleware::map_request(web::main_request_mapper))
+        .layer(CookieManagerLayer::new());
+    Ok(app)
+}
+
 pub async fn get_api_routes(app_state: &AppState) -> anyhow::Result<axum::Router> {
     let routes_public: Router = Router::new()
         .route("/status", get(status::api_status))
+        .route("/.well-known/jwks.json", get(jwks::get_jwks))
         .route("/login", post(user::login))
+        .route("/login/mfa", post(user::login_mfa))
+        .route("/login/token", post(user::login_token))
         .route("/register", post(user::register))
+        .route("/token/refresh", post(token::refresh))
+        .route("/account/verify", post(account::verify_email))
+        .route("/account/verify/resend", post(account::resend_verification))
+        .route("/password/forgot", post(password::forgot))
+        .route("/password/reset", post(password::reset))
+        .route("/oidc/providers", get(oidc::get_providers))
+        .route("/oidc/:provider/login", get(oidc::login))
+        .route("/oidc/:provider/callback", get(oidc::callback))
+        .with_state(app_state.clone());
+
+    // Personal access tokens can use these when they have the scope
+    let routes_scoped: Router = Router::new()
+        .route(
+            "/lobby",
+            post(lobby::create_lobby).route_layer(middleware::from_fn_with_state(
+                Scope::LobbyWrite,
+                require_scope,
+            )),
+        )
+        .route(
+            "/lobby/:id",
+            get(lobby::get_lobby)
+                .route_layer(middleware::from_fn_with_state(
+                    Scope::LobbyRead,
+                    require_scope,
+                ))
+                .merge(
+                    patch(lobby::patch_lobby)
+                        .delete(lobby::delete_lobby)
+                        .route_layer(middleware::from_fn_with_state(
+                            Scope::LobbyWrite,
+                            require_scope,
+                        )),
+                ),
+        )
+        .route(
+            "/lobby/:id/join",
+            post(lobby::join_lobby).route_layer(middleware::from_fn_with_state(
+                Scope::LobbyWrite,
+                require_scope,
+            )),
+        )
+        .route(
+            "/lobby/:id/leave",
+            post(lobby::leave_lobby).route_layer(middleware::from_fn_with_state(
+                Scope::LobbyWrite,
+                require_scope,
+            )),
+        )
+        .route(
+            "/lobby/:id/invite",
+            post(lobby::rotate_invite).route_layer(middleware::from_fn_with_state(
+                Scope::LobbyWrite,
+                require_scope,
+            )),
+        )
+        .route(
+            "/lobby/join/:code",
+            post(lobby::join_lobby_with_invite).route_layer(middleware::from_fn_with_state(
+                Scope::LobbyWrite,
+                require_scope,
+            )),
+        )
+        .route(
+            "/lobbies",
+            get(lobby::get_lobbies).route_layer(middleware::from_fn_with_state(
+                Scope::LobbyRead,
+                require_scope,
+            )),
+        )
+        .route(
+            "/account/me",
+            get(user::get_account_me).route_layer(middleware::from_fn_with_state(
+                Scope::AccountRead,
+                require_scope,
+            )),
+        )
         .with_state(app_state.clone());
 
     let routes_private: Router = Router::new()
-        .route("/lobby", post(lobby::create_lobby))
-        .route("/lobbies", get(lobby::get_lobbies))
+        .route("/logout", post(user::logout))
+        .route("/account/tokens", get(access_token::list_tokens))
+        .route("/account/audit", get(account::get_audit_log))
+        .route("/account/sessions", get(session::list_sessions))
+        .route("/account/identities", get(oidc::list_identities))
+        .route("/account/friends", get(friend::list_friends))
+        .route(
+            "/account/friends/:id",
+            put(friend::add_friend).delete(friend::remove_friend),
+        )
+        .with_state(app_state.clone())
+        .route_layer(middleware::from_fn(require_auth));
+
+    // Sign in details and the account itself stay out of reach of impersonation tokens
+    let routes_sensitive: Router = Router::new()
         .route(
             "/account/me",
-            get(user::get_account_me).patch(user::patch_account_me),
+            patch(user::patch_account_me).delete(account::delete_account),
         )
+        .route("/account/export", get(account::export_account))
+        .route("/account/password", post(account::change_password))
+        .route("/account/mfa/enroll", post(account::mfa_enroll))
+        .route("/account/mfa/confirm", post(account::mfa_confirm))
+        .route("/account/mfa/disable", post(account::mfa_disable))
+        .route("/account/tokens", post(access_token::create_token))
+        .route("/account/tokens/:id", delete(access_token::revoke_token))
+        .route("/account/sessions", delete(session::revoke_other_sessions))
+        .route("/account/sessions/:id", delete(session::revoke_session))
+        .route("/account/identities/:id", delete(oidc::unlink_identity))
+        .route("/oidc/:provider/link", post(oidc::link))
+        .with_state(app_state.clone())
+        .route_layer(middleware::from_fn(refuse_impersonation))
+        .route_layer(middleware::from_fn(require_auth));
+
+    let routes_moderator: Router = Router::new()
+        .route("/admin/lockouts", get(admin::get_lockouts))
+        .route("/admin/lockouts/:key", delete(admin::clear_lockout))
+        .with_state(app_state.clone())
+        .route_layer(middleware::from_fn_with_state(
+            Role::Moderator,
+            require_role,
+        ));
+
+    let routes_admin: Router = Router::new()
+        .route("/admin/users/:id/role", put(admin::set_user_role))
+        .route("/admin/audit", get(admin::get_audit_events))
+        .route("/admin/impersonate/:id", post(admin::impersonate))
         .with_state(app_state.clone())
-        .route_layer(middleware::from_fn(crate::mw::auth::require_auth));
+        .route_layer(middleware::from_fn_with_state(Role::Admin, require_role));
 
-    let router = routes_public.merge(routes_private);
+    let router = routes_public
+        .merge(routes_scoped)
+        .merge(routes_private)
+        .merge(routes_sensitive)
+        .merge(routes_moderator)
+        .merge(routes_admin);
     Ok(router)
 }
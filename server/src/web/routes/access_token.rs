@@ -0,0 +1,72 @@
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use serde::Serialize;
+use serde_json::{json, Value};
+use tracing::debug;
+use uuid::Uuid;
+
+use crate::{
+    db::{get_db_conn, DbPool},
+    model::{
+        access_token::{self, AccessToken, AccessTokenNewFields},
+        audit::AuditKind,
+    },
+    service::audit::AuditContext,
+    web::{ctx::Ctx, error::MainError},
+};
+
+#[derive(Debug, Serialize)]
+pub struct AccessTokenCreated {
+    #[serde(flatten)]
+    access_token: AccessToken,
+    /// Only ever shown here, the server keeps a hash of it
+    token: String,
+}
+
+pub async fn list_tokens(
+    State(db_pool): State<DbPool>,
+    ctx: Ctx,
+) -> Result<Json<Vec<AccessToken>>, MainError> {
+    let conn = get_db_conn(&db_pool)?;
+    let tokens = access_token::list(conn, ctx.account_id as i32).await?;
+    Ok(Json(tokens))
+}
+
+pub async fn create_token(
+    State(db_pool): State<DbPool>,
+    ctx: Ctx,
+    Json(payload): Json<AccessTokenNewFields>,
+) -> Result<Json<AccessTokenCreated>, MainError> {
+    let conn = get_db_conn(&db_pool)?;
+    let (access_token, token) = access_token::create(conn, ctx.account_id as i32, payload).await?;
+    debug!(
+        "✅ Access token {} created for {}",
+        access_token.id, ctx.account_id
+    );
+    Ok(Json(AccessTokenCreated {
+        access_token,
+        token,
+    }))
+}
+
+pub async fn revoke_token(
+    State(db_pool): State<DbPool>,
+    ctx: Ctx,
+    audit: AuditContext,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Value>, MainError> {
+    let conn = get_db_conn(&db_pool)?;
+    access_token::revoke(conn, ctx.account_id as i32, id).await?;
+    audit
+        .record(
+            &db_pool,
+            AuditKind::AccessTokenRevoked,
+            Some(ctx.account_id as i32),
+            json!({ "access_token_id": id }),
+        )
+        .await;
+    debug!("✅ Access token {id} revoked by {}", ctx.account_id);
+    Ok(Json(json!({ "revoked": true })))
+}
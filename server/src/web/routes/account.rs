@@ -0,0 +1,328 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Query, State},
+    http::header,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tower_cookies::Cookies;
+use tracing::{debug, warn};
+
+use crate::{
+    db::{get_db_conn, DbPool},
+    model::{
+        account::{self, Reauth},
+        audit::{self, AuditEvent, AuditFilter, AuditKind},
+        email_verification, mfa,
+        session::{self, SessionClient},
+        user::{self, ErrorUser, User, UserPublic},
+    },
+    service::{
+        audit::AuditContext,
+        mailer::{Email, Mailer},
+        throttle::{ThrottleController, ThrottleKey},
+    },
+    web::{self, config::Config, ctx::Ctx, error::MainError},
+};
+
+use super::user::{check_throttle, record_failure, throttle_keys};
+
+#[derive(Debug, Deserialize)]
+pub struct PayloadVerify {
+    token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PayloadResendVerification {
+    email: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PayloadMfaCode {
+    code: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MfaEnrollment {
+    secret: String,
+    otpauth_uri: String,
+}
+
+/// Accounts with a password confirm with it. Others use a two-factor code or sign in again
+/// just before.
+#[derive(Debug, Deserialize)]
+pub struct PayloadDeleteAccount {
+    password: Option<String>,
+    code: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PayloadChangePassword {
+    current_password: String,
+    new_password: String,
+    #[serde(default)]
+    sign_out_other_sessions: bool,
+}
+
+pub async fn verify_email(
+    State(db_pool): State<DbPool>,
+    Json(payload): Json<PayloadVerify>,
+) -> Result<Json<UserPublic>, MainError> {
+    let conn = get_db_conn(&db_pool)?;
+    let user = email_verification::verify(conn, &payload.token).await?;
+    debug!("✅ Verified email {}", user.email);
+    Ok(Json(user.into()))
+}
+
+/// Always succeeds, so the response can't be used to find out which emails have accounts.
+/// Every request counts against the address and the client, sent or not, so the endpoint
+/// can't be used to flood an inbox either
+pub async fn resend_verification(
+    State(db_pool): State<DbPool>,
+    State(mailer): State<Arc<dyn Mailer>>,
+    State(config): State<Config>,
+    State(ctl_throttle): State<ThrottleController>,
+    client: SessionClient,
+    Json(payload): Json<PayloadResendVerification>,
+) -> Result<Json<Value>, MainError> {
+    let throttle_keys: Vec<ThrottleKey> =
+        std::iter::once(ThrottleKey::VerifyEmail(payload.email.clone()))
+            .chain(client.ip.map(ThrottleKey::VerifyIp))
+            .collect();
+    ctl_throttle.check(&throttle_keys).map_err(|wait| {
+        let retry_after = wait.as_secs_f64().ceil() as u64;
+        debug!("🚦 Verification emails throttled for {retry_after}s");
+        MainError::TooManyAttempts(retry_after)
+    })?;
+    if let Err(e) = ctl_throttle.record_failure(&throttle_keys).await {
+        warn!("⚠️  Failed to record verification email: {e}");
+    }
+
+    let conn = get_db_conn(&db_pool)?;
+    match user::get_by_email(conn, &payload.email).await {
+        Ok(user) if user.email_verified_at.is_none() => {
+            if let Err(e) = send_verification_email(&db_pool, mailer.as_ref(), &config, &user).await
+            {
+                warn!(
+                    "⚠️  Failed to resend verification email to {}: {e}",
+                    user.email
+                );
+            }
+        }
+        _ => debug!("Skip verification email for {}", payload.email),
+    }
+
+    Ok(Json(json!({ "sent": true })))
+}
+
+pub async fn change_password(
+    State(db_pool): State<DbPool>,
+    ctx: Ctx,
+    audit: AuditContext,
+    Json(payload): Json<PayloadChangePassword>,
+) -> Result<Json<UserPublic>, MainError> {
+    let conn = get_db_conn(&db_pool)?;
+    let user = user::change_password(
+        conn,
+        ctx.account_id as i32,
+        &payload.current_password,
+        &payload.new_password,
+    )
+    .await?;
+
+    let mut revoked = 0;
+    if payload.sign_out_other_sessions {
+        let conn = get_db_conn(&db_pool)?;
+        revoked = session::revoke_for_user(conn, user.id, ctx.session_id).await?;
+        debug!("Signed out {revoked} other sessions of user {}", user.id);
+    }
+    audit
+        .record(
+            &db_pool,
+            AuditKind::PasswordChanged,
+            Some(user.id),
+            json!({ "sessions_revoked": revoked }),
+        )
+        .await;
+
+    debug!("✅ Changed password {}", user.email);
+    Ok(Json(user.into()))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuditLogParams {
+    before: Option<i64>,
+    limit: Option<i64>,
+}
+
+/// Security events on the signed in user's account and the ones they caused, newest first
+pub async fn get_audit_log(
+    State(db_pool): State<DbPool>,
+    ctx: Ctx,
+    Query(params): Query<AuditLogParams>,
+) -> Result<Json<Vec<AuditEvent>>, MainError> {
+    let filter = AuditFilter {
+        user_id: Some(ctx.account_id as i32),
+        before: params.before,
+        limit: params.limit,
+        ..Default::default()
+    };
+    let conn = get_db_conn(&db_pool)?;
+    Ok(Json(audit::query(conn, &filter).await?))
+}
+
+/// Download everything stored about the signed in user as a JSON file
+pub async fn export_account(
+    State(db_pool): State<DbPool>,
+    ctx: Ctx,
+) -> Result<Response, MainError> {
+    let conn = get_db_conn(&db_pool)?;
+    let export = account::export(conn, ctx.account_id as i32).await?;
+    debug!("✅ Exported account {}", ctx.account_id);
+
+    let disposition = [(
+        header::CONTENT_DISPOSITION,
+        "attachment; filename=\"account-export.json\"",
+    )];
+    Ok((disposition, Json(export)).into_response())
+}
+
+/// Delete the signed in user's account. Signing in again before `purge_after` restores it.
+/// Wrong passwords and codes count against the account and address as failed logins do.
+pub async fn delete_account(
+    State(db_pool): State<DbPool>,
+    State(ctl_throttle): State<ThrottleController>,
+    cookies: Cookies,
+    ctx: Ctx,
+    audit: AuditContext,
+    Json(payload): Json<PayloadDeleteAccount>,
+) -> Result<Json<Value>, MainError> {
+    let reauth = match (&payload.password, &payload.code, ctx.session_id) {
+        (Some(password), _, _) => Reauth::Password(password),
+        (None, Some(code), _) => Reauth::MfaCode(code),
+        (None, None, Some(session_id)) => Reauth::FreshSession(session_id),
+        (None, None, None) => return Err(ErrorUser::ReauthRequired.into()),
+    };
+
+    let conn = get_db_conn(&db_pool)?;
+    let user = user::get_by_id(conn, ctx.account_id as i32).await?;
+    let throttle_keys = throttle_keys(&user.email, &audit.client);
+    check_throttle(&ctl_throttle, &throttle_keys)?;
+
+    let conn = get_db_conn(&db_pool)?;
+    let purge_after = match account::delete(conn, user.id, reauth).await {
+        Ok(purge_after) => purge_after,
+        Err(e @ (ErrorUser::WrongPassword | ErrorUser::WrongCode)) => {
+            record_failure(&ctl_throttle, &throttle_keys).await;
+            return Err(e.into());
+        }
+        Err(e) => return Err(e.into()),
+    };
+    audit
+        .record(
+            &db_pool,
+            AuditKind::AccountDeleted,
+            Some(ctx.account_id as i32),
+            json!({ "purge_after": purge_after }),
+        )
+        .await;
+
+    cookies.remove(web::auth_cookie_removal());
+    cookies.remove(web::refresh_cookie_removal());
+
+    debug!("✅ Deleted account {}", ctx.account_id);
+    Ok(Json(json!({ "deleted": true, "purge_after": purge_after })))
+}
+
+/// Start setting up two-factor authentication. The secret is usually shown as a QR code of
+/// `otpauth_uri`, with `secret` for typing in by hand.
+pub async fn mfa_enroll(
+    State(db_pool): State<DbPool>,
+    ctx: Ctx,
+) -> Result<Json<MfaEnrollment>, MainError> {
+    let conn = get_db_conn(&db_pool)?;
+    let user = user::get_by_id(conn, ctx.account_id as i32).await?;
+
+    let conn = get_db_conn(&db_pool)?;
+    let totp = mfa::enroll(conn, user.id).await?;
+
+    Ok(Json(MfaEnrollment {
+        secret: totp.secret_base32(),
+        otpauth_uri: totp.uri(mfa::TOTP_ISSUER, &user.email),
+    }))
+}
+
+/// Confirm the authenticator app works, turning two-factor authentication on
+pub async fn mfa_confirm(
+    State(db_pool): State<DbPool>,
+    ctx: Ctx,
+    audit: AuditContext,
+    Json(payload): Json<PayloadMfaCode>,
+) -> Result<Json<Value>, MainError> {
+    let conn = get_db_conn(&db_pool)?;
+    let recovery_codes = mfa::confirm(conn, ctx.account_id as i32, &payload.code).await?;
+    audit
+        .record(
+            &db_pool,
+            AuditKind::MfaEnabled,
+            Some(ctx.account_id as i32),
+            json!({}),
+        )
+        .await;
+    debug!("✅ Two-factor authentication enabled {}", ctx.account_id);
+    Ok(Json(json!({ "recovery_codes": recovery_codes })))
+}
+
+pub async fn mfa_disable(
+    State(db_pool): State<DbPool>,
+    ctx: Ctx,
+    audit: AuditContext,
+    Json(payload): Json<PayloadMfaCode>,
+) -> Result<Json<Value>, MainError> {
+    let conn = get_db_conn(&db_pool)?;
+    mfa::disable(conn, ctx.account_id as i32, &payload.code).await?;
+    audit
+        .record(
+            &db_pool,
+            AuditKind::MfaDisabled,
+            Some(ctx.account_id as i32),
+            json!({}),
+        )
+        .await;
+    debug!("✅ Two-factor authentication disabled {}", ctx.account_id);
+    Ok(Json(json!({ "mfa_enabled": false })))
+}
+
+pub(super) async fn send_verification_email(
+    db_pool: &DbPool,
+    mailer: &dyn Mailer,
+    config: &Config,
+    user: &User,
+) -> Result<(), MainError> {
+    let conn = get_db_conn(db_pool)?;
+    let token = email_verification::create_token(conn, user).await?;
+    let link = format!("{}/verify?token={token}", config.app_url);
+
+    let email = Email {
+        to: user.email.clone(),
+        subject: "Verify your email address".to_string(),
+        body: format!(
+            "Hi {},\n\n\
+            Please confirm this is your email address by opening the link below:\n\n\
+            {link}\n\n\
+            The link expires in 24 hours. If you did not sign up, you can ignore this email.\n",
+            user.display_name
+        ),
+    };
+
+    mailer.send(email).await.map_err(|e| {
+        warn!(
+            "⚠️  Failed to send verification email to {}: {e}",
+            user.email
+        );
+        MainError::Internal(e.to_string())
+    })
+}
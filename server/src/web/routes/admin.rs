@@ -0,0 +1,151 @@
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::info;
+
+use crate::{
+    db::{get_db_conn, DbPool},
+    model::{
+        audit::{self, AuditEvent, AuditFilter, AuditKind},
+        role::Role,
+        user::{self, UserPublic},
+    },
+    service::{
+        audit::AuditContext,
+        jwt::{Claims, JwtController},
+        throttle::{ErrorThrottle, Lockout, ThrottleController, ThrottleKey},
+    },
+    web::{ctx::Ctx, error::MainError},
+};
+
+#[derive(Debug, Deserialize)]
+pub struct PayloadSetRole {
+    role: Role,
+}
+
+pub async fn set_user_role(
+    State(db_pool): State<DbPool>,
+    ctx: Ctx,
+    audit: AuditContext,
+    Path(user_id): Path<i32>,
+    Json(payload): Json<PayloadSetRole>,
+) -> Result<Json<UserPublic>, MainError> {
+    // Keeps the last admin from locking everyone out
+    if user_id as u64 == ctx.account_id {
+        return Err(MainError::ClientError(
+            "You can't change your own role".to_string(),
+        ));
+    }
+
+    let conn = get_db_conn(&db_pool)?;
+    let previous = user::get_by_id(conn, user_id).await?;
+    let conn = get_db_conn(&db_pool)?;
+    let user = user::set_role(conn, user_id, payload.role).await?;
+    audit
+        .record(
+            &db_pool,
+            AuditKind::RoleChanged,
+            Some(user_id),
+            json!({ "from": previous.role, "to": user.role }),
+        )
+        .await;
+    info!(
+        "👮 User {user_id} is now {} (set by {})",
+        user.role, ctx.account_id
+    );
+    Ok(Json(user.into()))
+}
+
+/// Get a short lived bearer token to act as another user, e.g. to reproduce a bug they
+/// reported. Every request made with it is audited, and it can't touch their credentials.
+pub async fn impersonate(
+    State(ctl_jwt): State<JwtController>,
+    State(db_pool): State<DbPool>,
+    ctx: Ctx,
+    audit: AuditContext,
+    Path(user_id): Path<i32>,
+) -> Result<Json<Value>, MainError> {
+    if ctx.impersonator.is_some() || user_id as u64 == ctx.account_id {
+        return Err(MainError::ClientError(
+            "You can only impersonate someone else, as yourself".to_string(),
+        ));
+    }
+
+    let conn = get_db_conn(&db_pool)?;
+    let user = user::get_by_id(conn, user_id).await?;
+    if user.deleted_at.is_some() {
+        return Err(user::ErrorUser::NotFound.into());
+    }
+    // One admin must not be able to borrow another's rights
+    if user.role.has(Role::Admin) {
+        return Err(MainError::ClientError(
+            "Admins can't be impersonated".to_string(),
+        ));
+    }
+
+    let claims = Claims::from(&user).impersonated_by(ctx.account_id);
+    let token = ctl_jwt.sign(&claims).map_err(MainError::Internal)?;
+    audit
+        .record(
+            &db_pool,
+            AuditKind::ImpersonationStarted,
+            Some(user_id),
+            json!({ "jti": claims.jti, "expires_at": claims.exp }),
+        )
+        .await;
+    info!("🎭 User {user_id} impersonated by {}", ctx.account_id);
+
+    Ok(Json(json!({
+        "access_token": token,
+        "token_type": "Bearer",
+        "expires_in": claims.exp - claims.iat,
+        "claims": claims,
+    })))
+}
+
+pub async fn get_lockouts(
+    State(ctl_throttle): State<ThrottleController>,
+) -> Result<Json<Vec<Lockout>>, MainError> {
+    let lockouts = ctl_throttle
+        .lockouts()
+        .map_err(|e| MainError::Internal(e.to_string()))?;
+    Ok(Json(lockouts))
+}
+
+pub async fn clear_lockout(
+    State(ctl_throttle): State<ThrottleController>,
+    State(db_pool): State<DbPool>,
+    ctx: Ctx,
+    audit: AuditContext,
+    Path(key): Path<String>,
+) -> Result<Json<Value>, MainError> {
+    let key: ThrottleKey = key
+        .parse()
+        .map_err(|e: ErrorThrottle| MainError::ClientError(e.to_string()))?;
+    ctl_throttle
+        .clear(&key)
+        .await
+        .map_err(|e| MainError::Internal(e.to_string()))?;
+    audit
+        .record(
+            &db_pool,
+            AuditKind::LockoutCleared,
+            None,
+            json!({ "key": key.to_string() }),
+        )
+        .await;
+    info!("👮 Lockout {key} cleared by {}", ctx.account_id);
+    Ok(Json(json!({ "cleared": true })))
+}
+
+/// Search every account's security events, newest first
+pub async fn get_audit_events(
+    State(db_pool): State<DbPool>,
+    Query(filter): Query<AuditFilter>,
+) -> Result<Json<Vec<AuditEvent>>, MainError> {
+    let conn = get_db_conn(&db_pool)?;
+    Ok(Json(audit::query(conn, &filter).await?))
+}
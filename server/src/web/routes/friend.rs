@@ -0,0 +1,45 @@
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use serde_json::{json, Value};
+use tracing::debug;
+
+use crate::{
+    db::{get_db_conn, DbPool},
+    model::friendship::{self, Friend},
+    web::{ctx::Ctx, error::MainError},
+};
+
+pub async fn list_friends(
+    State(db_pool): State<DbPool>,
+    ctx: Ctx,
+) -> Result<Json<Vec<Friend>>, MainError> {
+    let conn = get_db_conn(&db_pool)?;
+    let friends = friendship::list(conn, ctx.account_id as i32).await?;
+    Ok(Json(friends))
+}
+
+pub async fn add_friend(
+    State(db_pool): State<DbPool>,
+    ctx: Ctx,
+    Path(friend_id): Path<i32>,
+) -> Result<Json<Value>, MainError> {
+    let conn = get_db_conn(&db_pool)?;
+    let added = friendship::add(conn, ctx.account_id as i32, friend_id).await?;
+
+    debug!("🤝 User {} added friend {friend_id}", ctx.account_id);
+    Ok(Json(json!({ "added": added })))
+}
+
+pub async fn remove_friend(
+    State(db_pool): State<DbPool>,
+    ctx: Ctx,
+    Path(friend_id): Path<i32>,
+) -> Result<Json<Value>, MainError> {
+    let conn = get_db_conn(&db_pool)?;
+    friendship::remove(conn, ctx.account_id as i32, friend_id).await?;
+
+    debug!("✅ User {} removed friend {friend_id}", ctx.account_id);
+    Ok(Json(json!({ "removed": true })))
+}
@@ -0,0 +1,11 @@
+use axum::{extract::State, http::header, response::IntoResponse, Json};
+
+use crate::service::jwt::JwtController;
+
+/// Public keys other services can use to verify our tokens
+pub async fn get_jwks(State(ctl_jwt): State<JwtController>) -> impl IntoResponse {
+    (
+        [(header::CACHE_CONTROL, "public, max-age=300")],
+        Json(ctl_jwt.jwks()),
+    )
+}
@@ -1,31 +1,179 @@
-use axum::{extract::State, Json};
-use tracing::trace;
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use serde_json::{json, Value};
+use tracing::{debug, trace, warn};
 
 use crate::{
-    model::lobby::{Lobby, LobbyController, LobbyForCreate},
-    web::{ctx::Ctx, error::MainError},
+    db::{get_db_conn, DbPool},
+    model::{
+        audit::AuditKind,
+        lobby::{
+            Departure, ErrorLobby, Lobby, LobbyController, LobbyDetails, LobbyForCreate,
+            LobbyForUpdate,
+        },
+        session::SessionClient,
+        user,
+    },
+    service::{
+        audit::AuditContext,
+        throttle::{ThrottleController, ThrottleKey},
+    },
+    web::{config::Config, ctx::Ctx, error::MainError},
 };
 
 pub async fn create_lobby(
     ctx: Ctx,
     State(ctl_lobby): State<LobbyController>,
+    State(db_pool): State<DbPool>,
+    State(config): State<Config>,
     Json(lobby_create): Json<LobbyForCreate>,
 ) -> Result<Json<Lobby>, MainError> {
     trace!("✅ CREATE LOBBY: by user: {ctx:?}");
-    let lobby = ctl_lobby
-        .create_lobby(lobby_create)
-        .await
-        .map_err(|e| MainError::Internal(e.to_string()))?;
+
+    if config.require_verified_email.lobby {
+        let conn = get_db_conn(&db_pool)?;
+        let user = user::get_by_id(conn, ctx.account_id as i32).await?;
+        if user.email_verified_at.is_none() {
+            return Err(MainError::EmailNotVerified);
+        }
+    }
+
+    let lobby = ctl_lobby.create_lobby(&(&ctx).into(), lobby_create).await?;
     Ok(Json(lobby))
 }
 
 pub async fn get_lobbies(
-    _ctx: Ctx,
+    ctx: Ctx,
     State(lobbies): State<LobbyController>,
 ) -> Result<Json<Vec<Lobby>>, MainError> {
-    let lobbies = lobbies
-        .get_lobbies()
-        .await
-        .map_err(|e| MainError::Internal(e.to_string()))?;
+    let lobbies = lobbies.get_lobbies(&(&ctx).into()).await?;
     Ok(Json(lobbies))
 }
+
+pub async fn get_lobby(
+    ctx: Ctx,
+    State(ctl_lobby): State<LobbyController>,
+    Path(id): Path<i32>,
+) -> Result<Json<LobbyDetails>, MainError> {
+    let lobby = ctl_lobby.get_lobby(&(&ctx).into(), id).await?;
+    Ok(Json(lobby))
+}
+
+/// Admins may change anyone's lobby. When they do, it's audited against the owner.
+pub async fn patch_lobby(
+    ctx: Ctx,
+    audit: AuditContext,
+    State(ctl_lobby): State<LobbyController>,
+    State(db_pool): State<DbPool>,
+    Path(id): Path<i32>,
+    Json(lobby_update): Json<LobbyForUpdate>,
+) -> Result<Json<Lobby>, MainError> {
+    let lobby = ctl_lobby
+        .update_lobby(&(&ctx).into(), id, lobby_update)
+        .await?;
+    if lobby.owner_id as u64 != ctx.account_id {
+        audit
+            .record(
+                &db_pool,
+                AuditKind::LobbyUpdatedByAdmin,
+                Some(lobby.owner_id),
+                json!({ "lobby_id": id, "name": lobby.name, "visibility": lobby.visibility }),
+            )
+            .await;
+    }
+    Ok(Json(lobby))
+}
+
+pub async fn delete_lobby(
+    ctx: Ctx,
+    audit: AuditContext,
+    State(ctl_lobby): State<LobbyController>,
+    State(db_pool): State<DbPool>,
+    Path(id): Path<i32>,
+) -> Result<Json<Value>, MainError> {
+    let lobby = ctl_lobby.delete_lobby(&(&ctx).into(), id).await?;
+    if lobby.owner_id as u64 != ctx.account_id {
+        audit
+            .record(
+                &db_pool,
+                AuditKind::LobbyDeletedByAdmin,
+                Some(lobby.owner_id),
+                json!({ "lobby_id": id, "name": lobby.name }),
+            )
+            .await;
+    }
+    debug!("🗑️  Lobby {id} deleted by user {}", ctx.account_id);
+    Ok(Json(json!({ "deleted": true })))
+}
+
+pub async fn join_lobby(
+    ctx: Ctx,
+    State(ctl_lobby): State<LobbyController>,
+    Path(id): Path<i32>,
+) -> Result<Json<LobbyDetails>, MainError> {
+    let lobby = ctl_lobby.join_lobby(&(&ctx).into(), id).await?;
+    debug!("🚪 User {} joined lobby {id}", ctx.account_id);
+    Ok(Json(lobby))
+}
+
+pub async fn leave_lobby(
+    ctx: Ctx,
+    State(ctl_lobby): State<LobbyController>,
+    Path(id): Path<i32>,
+) -> Result<Json<Departure>, MainError> {
+    let departure = ctl_lobby.leave_lobby(&(&ctx).into(), id).await?;
+    debug!("🚪 User {} left lobby {id}: {departure:?}", ctx.account_id);
+    Ok(Json(departure))
+}
+
+pub async fn rotate_invite(
+    ctx: Ctx,
+    State(ctl_lobby): State<LobbyController>,
+    Path(id): Path<i32>,
+) -> Result<Json<Value>, MainError> {
+    let invite_code = ctl_lobby.rotate_invite_code(&(&ctx).into(), id).await?;
+    debug!(
+        "🎟️  Lobby {id} invite code rotated by user {}",
+        ctx.account_id
+    );
+    Ok(Json(json!({ "invite_code": invite_code })))
+}
+
+/// Join by invite code. Codes that don't match count against the user and their address the
+/// way failed logins do, so they can't be guessed at speed.
+pub async fn join_lobby_with_invite(
+    ctx: Ctx,
+    State(ctl_lobby): State<LobbyController>,
+    State(ctl_throttle): State<ThrottleController>,
+    client: SessionClient,
+    Path(code): Path<String>,
+) -> Result<Json<LobbyDetails>, MainError> {
+    let throttle_keys: Vec<ThrottleKey> =
+        std::iter::once(ThrottleKey::InviteUser(ctx.account_id as i32))
+            .chain(client.ip.map(ThrottleKey::InviteIp))
+            .collect();
+    ctl_throttle.check(&throttle_keys).map_err(|wait| {
+        let retry_after = wait.as_secs_f64().ceil() as u64;
+        debug!("🚦 Invite codes throttled for {retry_after}s");
+        MainError::TooManyAttempts(retry_after)
+    })?;
+
+    match ctl_lobby.join_with_invite(&(&ctx).into(), &code).await {
+        Err(ErrorLobby::InviteNotFound) => {
+            if let Err(e) = ctl_throttle.record_failure(&throttle_keys).await {
+                warn!("⚠️  Failed to record invite code guess: {e}");
+            }
+            Err(ErrorLobby::InviteNotFound.into())
+        }
+        result => {
+            let lobby = result?;
+            debug!(
+                "🚪 User {} joined lobby {} with an invite",
+                ctx.account_id, lobby.lobby.id
+            );
+            Ok(Json(lobby))
+        }
+    }
+}
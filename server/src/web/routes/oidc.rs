@@ -0,0 +1,192 @@
+use axum::{
+    extract::{Path, Query, State},
+    response::Redirect,
+    Json,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tower_cookies::Cookies;
+use tracing::debug;
+
+use crate::{
+    db::{get_db_conn, DbPool},
+    model::{
+        identity::{self, Identity},
+        mfa,
+    },
+    service::{
+        audit::AuditContext,
+        jwt::{Claims, JwtController, MfaPendingClaims, OidcPendingClaims},
+        oidc::{ErrorOidc, OidcController},
+    },
+    web::{self, config::Config, ctx::Ctx, error::MainError},
+};
+
+use super::user::start_session;
+
+#[derive(Debug, Deserialize)]
+pub struct CallbackParams {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+/// Identity providers the client can offer a button for
+pub async fn get_providers(State(ctl_oidc): State<OidcController>) -> Json<Vec<String>> {
+    Json(ctl_oidc.provider_names())
+}
+
+/// Send the browser to the provider to log in
+pub async fn login(
+    State(ctl_oidc): State<OidcController>,
+    State(ctl_jwt): State<JwtController>,
+    cookies: Cookies,
+    Path(provider): Path<String>,
+) -> Result<Redirect, MainError> {
+    let (authorize_url, pending) = ctl_oidc.authorize(&provider, None).await?;
+    set_pending_cookie(&ctl_jwt, &cookies, &pending)?;
+    Ok(Redirect::to(&authorize_url))
+}
+
+/// Start linking a provider to the signed in account. The client sends the browser to the
+/// returned url.
+pub async fn link(
+    State(ctl_oidc): State<OidcController>,
+    State(ctl_jwt): State<JwtController>,
+    cookies: Cookies,
+    ctx: Ctx,
+    Path(provider): Path<String>,
+) -> Result<Json<Value>, MainError> {
+    let (authorize_url, pending) = ctl_oidc.authorize(&provider, Some(ctx.account_id)).await?;
+    set_pending_cookie(&ctl_jwt, &cookies, &pending)?;
+    Ok(Json(json!({ "authorize_url": authorize_url })))
+}
+
+/// Where the provider sends the browser back to. Always redirects to the client, with an
+/// `error` query parameter when the login failed.
+#[allow(clippy::too_many_arguments)]
+pub async fn callback(
+    State(ctl_oidc): State<OidcController>,
+    State(ctl_jwt): State<JwtController>,
+    State(db_pool): State<DbPool>,
+    State(config): State<Config>,
+    audit: AuditContext,
+    cookies: Cookies,
+    Path(provider): Path<String>,
+    Query(params): Query<CallbackParams>,
+) -> Redirect {
+    // Each login gets one callback
+    let pending = cookies
+        .get(web::OIDC_COOKIE)
+        .and_then(|cookie| ctl_jwt.verify_oidc_pending(cookie.value()).ok());
+    cookies.remove(web::oidc_cookie_removal());
+
+    let linking = pending.as_ref().is_some_and(|p| p.link_sub.is_some());
+    let result = complete_callback(
+        &ctl_oidc, &ctl_jwt, &db_pool, &config, &cookies, &audit, &provider, pending, params,
+    )
+    .await;
+
+    match result {
+        Ok(location) => Redirect::to(&location),
+        Err(e) => {
+            debug!("❌ {provider} callback failed: {e:?}");
+            let page = if linking { "profile" } else { "login" };
+            let message = e.client_response().1.to_string();
+            Redirect::to(&format!(
+                "{}/{page}?error={}",
+                config.app_url,
+                urlencoding::encode(&message)
+            ))
+        }
+    }
+}
+
+/// Finish the login or link, returning where the client should go next
+#[allow(clippy::too_many_arguments)]
+async fn complete_callback(
+    ctl_oidc: &OidcController,
+    ctl_jwt: &JwtController,
+    db_pool: &DbPool,
+    config: &Config,
+    cookies: &Cookies,
+    audit: &AuditContext,
+    provider: &str,
+    pending: Option<OidcPendingClaims>,
+    params: CallbackParams,
+) -> Result<String, MainError> {
+    let pending = pending
+        .filter(|pending| pending.provider == provider)
+        .ok_or(ErrorOidc::StateMismatch)?;
+    if let Some(error) = params.error {
+        return Err(ErrorOidc::Provider(params.error_description.unwrap_or(error)).into());
+    }
+    let (Some(code), Some(state)) = (params.code, params.state) else {
+        return Err(ErrorOidc::StateMismatch.into());
+    };
+
+    let profile = ctl_oidc.callback(&pending, &state, &code).await?;
+
+    if let Some(sub) = pending.link_sub {
+        let conn = get_db_conn(db_pool)?;
+        identity::link(conn, sub as i32, &profile).await?;
+        return Ok(format!("{}/profile?linked={provider}", config.app_url));
+    }
+
+    let conn = get_db_conn(db_pool)?;
+    let user = identity::login(conn, &profile).await?;
+    let claims = Claims::from(&user);
+
+    if config.require_verified_email.login && !claims.email_verified {
+        return Err(MainError::EmailNotVerified);
+    }
+
+    // The provider vouches for the first factor, the second is still ours to ask for
+    let conn = get_db_conn(db_pool)?;
+    if mfa::is_enabled(conn, user.id).await? {
+        let mfa_token = ctl_jwt
+            .sign_mfa_pending(&MfaPendingClaims::new(claims.sub))
+            .map_err(MainError::Internal)?;
+        return Ok(format!(
+            "{}/login?mfa_token={}",
+            config.app_url,
+            urlencoding::encode(&mfa_token)
+        ));
+    }
+
+    let _claims = start_session(ctl_jwt, db_pool, Some(cookies), audit, claims).await?;
+    Ok(format!("{}/", config.app_url))
+}
+
+fn set_pending_cookie(
+    ctl_jwt: &JwtController,
+    cookies: &Cookies,
+    pending: &OidcPendingClaims,
+) -> Result<(), MainError> {
+    let token = ctl_jwt
+        .sign_oidc_pending(pending)
+        .map_err(MainError::Internal)?;
+    cookies.add(web::oidc_cookie(token));
+    Ok(())
+}
+
+pub async fn list_identities(
+    State(db_pool): State<DbPool>,
+    ctx: Ctx,
+) -> Result<Json<Vec<Identity>>, MainError> {
+    let conn = get_db_conn(&db_pool)?;
+    let identities = identity::list(conn, ctx.account_id as i32).await?;
+    Ok(Json(identities))
+}
+
+pub async fn unlink_identity(
+    State(db_pool): State<DbPool>,
+    ctx: Ctx,
+    Path(id): Path<i32>,
+) -> Result<Json<Value>, MainError> {
+    let conn = get_db_conn(&db_pool)?;
+    identity::unlink(conn, ctx.account_id as i32, id).await?;
+    debug!("✅ Identity {id} unlinked by {}", ctx.account_id);
+    Ok(Json(json!({ "unlinked": true })))
+}
@@ -0,0 +1,93 @@
+use std::sync::Arc;
+
+use axum::{extract::State, Json};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::{debug, warn};
+
+use crate::{
+    db::{get_db_conn, DbPool},
+    model::{audit::AuditKind, password_reset, user},
+    service::{
+        audit::AuditContext,
+        mailer::{Email, Mailer},
+    },
+    web::{config::Config, error::MainError},
+};
+
+#[derive(Debug, Deserialize)]
+pub struct PayloadForgot {
+    email: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PayloadReset {
+    token: String,
+    password: String,
+}
+
+/// Email a reset link if the account exists. The response is the same either way, and the
+/// lookup and email happen after responding so the timing doesn't give the answer away either.
+pub async fn forgot(
+    State(db_pool): State<DbPool>,
+    State(mailer): State<Arc<dyn Mailer>>,
+    State(config): State<Config>,
+    Json(payload): Json<PayloadForgot>,
+) -> Result<Json<Value>, MainError> {
+    tokio::spawn(async move {
+        if let Err(e) = send_reset_email(&db_pool, mailer.as_ref(), &config, &payload.email).await {
+            warn!("⚠️  Failed to send password reset email: {e}");
+        }
+    });
+
+    Ok(Json(json!({ "sent": true })))
+}
+
+pub async fn reset(
+    State(db_pool): State<DbPool>,
+    audit: AuditContext,
+    Json(payload): Json<PayloadReset>,
+) -> Result<Json<Value>, MainError> {
+    let conn = get_db_conn(&db_pool)?;
+    let user = password_reset::reset(conn, &payload.token, &payload.password).await?;
+    debug!("✅ Password reset {}", user.email);
+    audit
+        .record(&db_pool, AuditKind::PasswordReset, Some(user.id), json!({}))
+        .await;
+    Ok(Json(json!({ "reset": true })))
+}
+
+async fn send_reset_email(
+    db_pool: &DbPool,
+    mailer: &dyn Mailer,
+    config: &Config,
+    email: &str,
+) -> Result<(), MainError> {
+    let conn = get_db_conn(db_pool)?;
+    let Ok(user) = user::get_by_email(conn, email).await else {
+        debug!("Skip password reset email for {email}");
+        return Ok(());
+    };
+
+    let conn = get_db_conn(db_pool)?;
+    let token = password_reset::create_token(conn, &user).await?;
+    let link = format!("{}/reset-password?token={token}", config.app_url);
+
+    let email = Email {
+        to: user.email.clone(),
+        subject: "Reset your password".to_string(),
+        body: format!(
+            "Hi {},\n\n\
+            Someone asked to reset the password for your account. To choose a new password, \
+            open the link below:\n\n\
+            {link}\n\n\
+            The link expires in 30 minutes. If you did not ask for this, you can ignore this email.\n",
+            user.display_name
+        ),
+    };
+
+    mailer
+        .send(email)
+        .await
+        .map_err(|e| MainError::Internal(e.to_string()))
+}
@@ -0,0 +1,93 @@
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use serde::Serialize;
+use serde_json::{json, Value};
+use tower_cookies::Cookies;
+use tracing::debug;
+use uuid::Uuid;
+
+use crate::{
+    db::{get_db_conn, DbPool},
+    model::{
+        audit::AuditKind,
+        session::{self, Session},
+    },
+    service::audit::AuditContext,
+    web::{self, ctx::Ctx, error::MainError},
+};
+
+#[derive(Debug, Serialize)]
+pub struct SessionListed {
+    #[serde(flatten)]
+    session: Session,
+    /// Whether this is the session the request was made with
+    current: bool,
+}
+
+/// Where the user is signed in
+pub async fn list_sessions(
+    State(db_pool): State<DbPool>,
+    ctx: Ctx,
+) -> Result<Json<Vec<SessionListed>>, MainError> {
+    let conn = get_db_conn(&db_pool)?;
+    let sessions = session::list_active(conn, ctx.account_id as i32)
+        .await?
+        .into_iter()
+        .map(|session| SessionListed {
+            current: ctx.session_id == Some(session.id),
+            session,
+        })
+        .collect();
+    Ok(Json(sessions))
+}
+
+/// Sign out one session, e.g. on a lost device
+pub async fn revoke_session(
+    State(db_pool): State<DbPool>,
+    cookies: Cookies,
+    ctx: Ctx,
+    audit: AuditContext,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Value>, MainError> {
+    let conn = get_db_conn(&db_pool)?;
+    session::revoke_owned(conn, ctx.account_id as i32, id).await?;
+    audit
+        .record(
+            &db_pool,
+            AuditKind::SessionRevoked,
+            Some(ctx.account_id as i32),
+            json!({ "session_id": id }),
+        )
+        .await;
+
+    // Signing out this very session is a logout
+    if ctx.session_id == Some(id) {
+        cookies.remove(web::auth_cookie_removal());
+        cookies.remove(web::refresh_cookie_removal());
+    }
+
+    debug!("✅ Session {id} revoked by {}", ctx.account_id);
+    Ok(Json(json!({ "revoked": true })))
+}
+
+/// Sign out every session but the one making the request
+pub async fn revoke_other_sessions(
+    State(db_pool): State<DbPool>,
+    ctx: Ctx,
+    audit: AuditContext,
+) -> Result<Json<Value>, MainError> {
+    let conn = get_db_conn(&db_pool)?;
+    let revoked = session::revoke_for_user(conn, ctx.account_id as i32, ctx.session_id).await?;
+    audit
+        .record(
+            &db_pool,
+            AuditKind::SessionRevoked,
+            Some(ctx.account_id as i32),
+            json!({ "others": true, "revoked": revoked }),
+        )
+        .await;
+    debug!("✅ {revoked} other sessions revoked by {}", ctx.account_id);
+    Ok(Json(json!({ "revoked": revoked })))
+}
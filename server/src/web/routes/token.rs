@@ -0,0 +1,93 @@
+use axum::{extract::State, Json};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tower_cookies::Cookies;
+use tracing::debug;
+
+use crate::{
+    db::{get_db_conn, DbPool},
+    model::{
+        audit::AuditKind,
+        session::{self, ErrorSession},
+        user,
+    },
+    service::{
+        audit::AuditContext,
+        jwt::{Claims, JwtController},
+    },
+    web::{self, error::MainError},
+};
+
+/// For clients that logged in at `/login/token` and keep their tokens themselves
+#[derive(Debug, Deserialize)]
+pub struct PayloadRefresh {
+    refresh_token: String,
+}
+
+/// Rotate the refresh cookie and mint a new access token for the same session. A refresh
+/// token sent in the body is answered in the body instead, without cookies.
+pub async fn refresh(
+    State(ctl_jwt): State<JwtController>,
+    State(db_pool): State<DbPool>,
+    cookies: Cookies,
+    audit: AuditContext,
+    payload: Option<Json<PayloadRefresh>>,
+) -> Result<Json<Value>, MainError> {
+    let in_body = payload.is_some();
+    let refresh_token = match payload {
+        Some(Json(payload)) => payload.refresh_token,
+        None => cookies
+            .get(web::REFRESH_COOKIE)
+            .map(|c| c.value().to_string())
+            .ok_or(MainError::AuthFailNoRefreshTokenCookie)?,
+    };
+
+    let conn = get_db_conn(&db_pool)?;
+    let (session, refresh_token) = match session::refresh(conn, &refresh_token).await {
+        Ok(refreshed) => refreshed,
+        Err(e) => {
+            if let ErrorSession::Reused {
+                user_id,
+                session_id,
+            } = e
+            {
+                audit
+                    .record(
+                        &db_pool,
+                        AuditKind::RefreshTokenReused,
+                        Some(user_id),
+                        json!({ "session_id": session_id }),
+                    )
+                    .await;
+            }
+            if !in_body {
+                cookies.remove(web::refresh_cookie_removal());
+            }
+            return Err(e.into());
+        }
+    };
+
+    let conn = get_db_conn(&db_pool)?;
+    let user = user::get_by_id(conn, session.user_id).await?;
+    let claims = Claims::from(&user).with_session(session.id);
+
+    let token = ctl_jwt.sign(&claims).map_err(|jwt_error| {
+        debug!("❌ Refresh JWT Signing Error {jwt_error}");
+        MainError::Internal(jwt_error)
+    })?;
+
+    debug!("✅ Refresh session {}", session.id);
+    if in_body {
+        return Ok(Json(json!({
+            "access_token": token,
+            "token_type": "Bearer",
+            "expires_in": claims.exp - claims.iat,
+            "refresh_token": refresh_token,
+            "claims": claims,
+        })));
+    }
+
+    cookies.add(web::auth_cookie(token));
+    cookies.add(web::refresh_cookie(refresh_token));
+    Ok(Json(json!(claims)))
+}
@@ -1,59 +1,354 @@
+use std::sync::Arc;
+
 use crate::web::ctx::Ctx;
 use axum::{extract::State, Json};
 use serde::Deserialize;
 use serde_json::{json, Value};
-use tower_cookies::{cookie::SameSite, Cookie, Cookies};
-use tracing::debug;
+use tower_cookies::Cookies;
+use tracing::{debug, warn};
 
 use crate::{
     db::{get_db_conn, DbPool},
-    model::user::{self, UserPublic},
-    service::{self, jwt::Claims},
-    web::{self, error::MainError},
+    model::{
+        account,
+        audit::AuditKind,
+        mfa::{self, ErrorMfa},
+        session::{self, SessionClient},
+        user::{self, UserPublic},
+    },
+    mw::csrf,
+    service::{
+        audit::AuditContext,
+        jwt::{Claims, JwtController, MfaPendingClaims},
+        mailer::Mailer,
+        revocation::RevocationController,
+        throttle::{ThrottleController, ThrottleKey},
+    },
+    web::{self, config::Config, error::MainError},
 };
 
+use super::account::send_verification_email;
+
 #[derive(Debug, Deserialize)]
 pub struct PayloadLogin {
     email: String,
     password: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct PayloadLoginMfa {
+    mfa_token: String,
+    code: String,
+}
+
 pub async fn login(
-    State(ctl_jwt): State<service::jwt::JwtController>,
+    State(ctl_jwt): State<JwtController>,
+    State(ctl_throttle): State<ThrottleController>,
     State(db_pool): State<DbPool>,
+    State(config): State<Config>,
+    audit: AuditContext,
     cookies: Cookies,
-    payload: Json<PayloadLogin>,
+    Json(payload): Json<PayloadLogin>,
 ) -> Result<Json<Value>, MainError> {
+    login_with(
+        &ctl_jwt,
+        &ctl_throttle,
+        &db_pool,
+        &config,
+        audit,
+        Some(&cookies),
+        payload,
+    )
+    .await
+}
+
+/// Log in without cookies, for CLI tools and native clients. The access token is returned in
+/// the body, to be sent back as `Authorization: Bearer <token>`, along with a refresh token to
+/// exchange for new ones at `POST /api/token/refresh` once it expires.
+pub async fn login_token(
+    State(ctl_jwt): State<JwtController>,
+    State(ctl_throttle): State<ThrottleController>,
+    State(db_pool): State<DbPool>,
+    State(config): State<Config>,
+    audit: AuditContext,
+    Json(payload): Json<PayloadLogin>,
+) -> Result<Json<Value>, MainError> {
+    login_with(
+        &ctl_jwt,
+        &ctl_throttle,
+        &db_pool,
+        &config,
+        audit,
+        None,
+        payload,
+    )
+    .await
+}
+
+/// Check the password, then either start a session or ask for the second factor. Without
+/// `cookies` the token is delivered in the body.
+async fn login_with(
+    ctl_jwt: &JwtController,
+    ctl_throttle: &ThrottleController,
+    db_pool: &DbPool,
+    config: &Config,
+    audit: AuditContext,
+    cookies: Option<&Cookies>,
+    payload: PayloadLogin,
+) -> Result<Json<Value>, MainError> {
+    let throttle_keys = throttle_keys(&payload.email, &audit.client);
+    check_throttle(ctl_throttle, &throttle_keys)?;
+
+    let conn = get_db_conn(db_pool)?;
+    let claims: Claims = match user::login(conn, &payload.email, &payload.password).await {
+        Ok(claims) => claims,
+        Err(MainError::LoginFail) => {
+            record_failure(ctl_throttle, &throttle_keys).await;
+            audit_login_failure(db_pool, &audit, &payload.email, "password").await;
+            return Err(MainError::LoginFail);
+        }
+        Err(e) => return Err(e),
+    };
+
+    if config.require_verified_email.login && !claims.email_verified {
+        return Err(MainError::EmailNotVerified);
+    }
+
+    // The password was right, but the session waits for the second factor
+    let conn = get_db_conn(db_pool)?;
+    if mfa::is_enabled(conn, claims.sub as i32).await? {
+        let pending = MfaPendingClaims::new(claims.sub).with_token_in_body(cookies.is_none());
+        let mfa_token = ctl_jwt.sign_mfa_pending(&pending).map_err(|jwt_error| {
+            debug!("❌ Login JWT Signing Error {jwt_error}");
+            MainError::LoginFail
+        })?;
+
+        debug!("🔐 Login {} needs a second factor", claims.email);
+        return Ok(Json(
+            json!({ "mfa_required": true, "mfa_token": mfa_token }),
+        ));
+    }
+
+    clear_failures(ctl_throttle, &claims.email).await;
+    start_session(ctl_jwt, db_pool, cookies, &audit, claims).await
+}
+
+/// Second step of logging in to an account with two-factor authentication
+pub async fn login_mfa(
+    State(ctl_jwt): State<JwtController>,
+    State(ctl_revocation): State<RevocationController>,
+    State(ctl_throttle): State<ThrottleController>,
+    State(db_pool): State<DbPool>,
+    audit: AuditContext,
+    cookies: Cookies,
+    Json(payload): Json<PayloadLoginMfa>,
+) -> Result<Json<Value>, MainError> {
+    let pending = ctl_jwt
+        .verify_mfa_pending(&payload.mfa_token)
+        .map_err(|e| MainError::AuthFailToken(e.to_string()))?;
+    if ctl_revocation.is_revoked(&pending.jti) {
+        return Err(MainError::AuthFailTokenRevoked);
+    }
+
+    let conn = get_db_conn(&db_pool)?;
+    let user = user::get_by_id(conn, pending.sub as i32).await?;
+
+    // Guessing codes counts against the account just like guessing passwords
+    let throttle_keys = throttle_keys(&user.email, &audit.client);
+    check_throttle(&ctl_throttle, &throttle_keys)?;
+
     let conn = get_db_conn(&db_pool)?;
+    match mfa::verify(conn, user.id, &payload.code).await {
+        Ok(()) => {}
+        Err(ErrorMfa::InvalidCode) => {
+            record_failure(&ctl_throttle, &throttle_keys).await;
+            audit_login_failure(&db_pool, &audit, &user.email, "mfa_code").await;
+            return Err(ErrorMfa::InvalidCode.into());
+        }
+        Err(e) => return Err(e.into()),
+    }
+
+    // Each pending token completes one login
+    ctl_revocation
+        .revoke(pending.jti, pending.exp)
+        .await
+        .map_err(|e| MainError::Internal(e.to_string()))?;
+
+    clear_failures(&ctl_throttle, &user.email).await;
+    let cookies = (!pending.token_in_body).then_some(&cookies);
+    start_session(&ctl_jwt, &db_pool, cookies, &audit, Claims::from(&user)).await
+}
 
-    let claims: Claims = user::login(conn, &payload.email, &payload.password).await?;
+/// Record a failed login against the account it was for, if there is one
+async fn audit_login_failure(db_pool: &DbPool, audit: &AuditContext, email: &str, reason: &str) {
+    let target_id = match get_db_conn(db_pool) {
+        Ok(conn) => user::get_by_email(conn, email)
+            .await
+            .ok()
+            .map(|user| user.id),
+        Err(_) => None,
+    };
+    audit
+        .record(
+            db_pool,
+            AuditKind::LoginFailed,
+            target_id,
+            json!({ "email": email, "reason": reason }),
+        )
+        .await;
+}
+
+pub(super) fn throttle_keys(email: &str, client: &SessionClient) -> Vec<ThrottleKey> {
+    std::iter::once(ThrottleKey::Email(email.to_string()))
+        .chain(client.ip.map(ThrottleKey::Ip))
+        .collect()
+}
+
+pub(super) fn check_throttle(
+    ctl_throttle: &ThrottleController,
+    keys: &[ThrottleKey],
+) -> Result<(), MainError> {
+    ctl_throttle.check(keys).map_err(|wait| {
+        let retry_after = wait.as_secs_f64().ceil() as u64;
+        debug!("🚦 Login throttled for {retry_after}s");
+        MainError::TooManyAttempts(retry_after)
+    })
+}
+
+pub(super) async fn record_failure(ctl_throttle: &ThrottleController, keys: &[ThrottleKey]) {
+    if let Err(e) = ctl_throttle.record_failure(keys).await {
+        warn!("⚠️  Failed to record failed login: {e}");
+    }
+}
+
+/// A successful login forgets the account's failures, but not the address's: one good
+/// password must not reset the count for someone trying many accounts
+async fn clear_failures(ctl_throttle: &ThrottleController, email: &str) {
+    if let Err(e) = ctl_throttle
+        .clear(&ThrottleKey::Email(email.to_string()))
+        .await
+    {
+        warn!("⚠️  Failed to clear failed logins: {e}");
+    }
+}
+
+/// Start a session for a fully authenticated user and set its cookies, or without `cookies`
+/// hand the access and refresh tokens over in the body
+pub(super) async fn start_session(
+    ctl_jwt: &JwtController,
+    db_pool: &DbPool,
+    cookies: Option<&Cookies>,
+    audit: &AuditContext,
+    claims: Claims,
+) -> Result<Json<Value>, MainError> {
+    // Signing in during the grace period takes back a deletion
+    let conn = get_db_conn(db_pool)?;
+    account::restore(conn, claims.sub as i32).await?;
+
+    let conn = get_db_conn(db_pool)?;
+    let (session, refresh_token) = session::create(conn, claims.sub as i32, &audit.client).await?;
+    let claims = claims.with_session(session.id);
+
+    let user_id = claims.sub as i32;
+    audit
+        .with_actor(user_id)
+        .record(
+            db_pool,
+            AuditKind::LoginSucceeded,
+            Some(user_id),
+            json!({ "session_id": session.id }),
+        )
+        .await;
 
     let token = ctl_jwt.sign(&claims).map_err(|jwt_error| {
         debug!("❌ Login JWT Signing Error {jwt_error}");
         MainError::LoginFail
     })?;
 
-    let mut auth_cookie = Cookie::new(web::AUTH_HEADER, token);
-    auth_cookie.set_secure(true);
-    auth_cookie.set_same_site(SameSite::Lax);
-    // Set path so that the cookie is sent with every request, not just /api requests
-    auth_cookie.set_path("/");
+    debug!("✅ Login {}", claims.email);
 
-    cookies.add(auth_cookie);
+    let Some(cookies) = cookies else {
+        return Ok(Json(json!({
+            "access_token": token,
+            "token_type": "Bearer",
+            "expires_in": claims.exp - claims.iat,
+            "refresh_token": refresh_token,
+            "claims": claims,
+        })));
+    };
 
-    debug!("✅ Login {}", claims.email);
+    cookies.add(web::auth_cookie(token));
+    cookies.add(web::refresh_cookie(refresh_token));
+    // A new session gets a token nobody could have planted before it
+    cookies.add(csrf::new_csrf_cookie());
 
     // Create success body
     Ok(Json(json!(claims)))
 }
 
+/// Revoke the token used for this request and end its session
+pub async fn logout(
+    State(ctl_revocation): State<RevocationController>,
+    State(db_pool): State<DbPool>,
+    cookies: Cookies,
+    ctx: Ctx,
+    audit: AuditContext,
+) -> Result<Json<Value>, MainError> {
+    if let (Some(jti), Some(exp)) = (ctx.jti, ctx.exp) {
+        ctl_revocation
+            .revoke(jti, exp)
+            .await
+            .map_err(|e| MainError::Internal(e.to_string()))?;
+    }
+
+    if let Some(session_id) = ctx.session_id {
+        let conn = get_db_conn(&db_pool)?;
+        session::revoke(conn, session_id).await?;
+    }
+    audit
+        .record(
+            &db_pool,
+            AuditKind::LoggedOut,
+            Some(ctx.account_id as i32),
+            json!({ "session_id": ctx.session_id }),
+        )
+        .await;
+
+    cookies.remove(web::auth_cookie_removal());
+    cookies.remove(web::refresh_cookie_removal());
+
+    debug!("✅ Logout {}", ctx.account_id);
+    Ok(Json(json!({ "logged_out": true })))
+}
+
 pub async fn register(
     State(db_pool): State<DbPool>,
+    State(mailer): State<Arc<dyn Mailer>>,
+    State(config): State<Config>,
+    audit: AuditContext,
     Json(fields): Json<user::UserNewFields>,
 ) -> Result<Json<UserPublic>, MainError> {
     let conn = get_db_conn(&db_pool)?;
     let user = user::create(conn, fields).await?;
     debug!("✅ Register {}", user.email);
+    audit
+        .with_actor(user.id)
+        .record(
+            &db_pool,
+            AuditKind::Registered,
+            Some(user.id),
+            json!({ "email": user.email }),
+        )
+        .await;
+
+    // The account exists either way, the user can ask for another email
+    if let Err(e) = send_verification_email(&db_pool, mailer.as_ref(), &config, &user).await {
+        warn!(
+            "⚠️  Register {} without verification email: {e}",
+            user.email
+        );
+    }
+
     Ok(Json(user.into()))
 }
 
@@ -73,10 +368,31 @@ pub async fn get_account_me(
 
 pub async fn patch_account_me(
     State(db_pool): State<DbPool>,
+    State(mailer): State<Arc<dyn Mailer>>,
+    State(config): State<Config>,
     ctx: Ctx,
+    audit: AuditContext,
     Json(payload): Json<PatchEmailPayload>,
 ) -> Result<Json<UserPublic>, MainError> {
+    let conn = get_db_conn(&db_pool)?;
+    let previous = user::get_by_id(conn, ctx.account_id as i32).await?;
     let conn = get_db_conn(&db_pool)?;
     let user = user::update_email(conn, ctx.account_id as i32, &payload.email).await?;
+    audit
+        .record(
+            &db_pool,
+            AuditKind::EmailChanged,
+            Some(user.id),
+            json!({ "from": previous.email, "to": user.email }),
+        )
+        .await;
+
+    if let Err(e) = send_verification_email(&db_pool, mailer.as_ref(), &config, &user).await {
+        warn!(
+            "⚠️  Changed email to {} without verification email: {e}",
+            user.email
+        );
+    }
+
     Ok(Json(user.into()))
 }
@@ -0,0 +1,48 @@
+use std::convert::Infallible;
+
+use axum::{
+    extract::{FromRef, FromRequestParts},
+    http::{header::USER_AGENT, request::Parts},
+};
+
+use crate::{
+    model::session::SessionClient,
+    web::{client_ip::ClientIp, config::Config},
+};
+
+/// The `User-Agent` a request was sent with, if any
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserAgent(pub Option<String>);
+
+#[async_trait::async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for UserAgent {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Infallible> {
+        let user_agent = parts
+            .headers
+            .get(USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(String::from);
+
+        Ok(UserAgent(user_agent))
+    }
+}
+
+/// The device a request comes from, recorded on the sessions it starts
+#[async_trait::async_trait]
+impl<S> FromRequestParts<S> for SessionClient
+where
+    Config: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Infallible> {
+        let ClientIp(ip) = ClientIp::from_request_parts(parts, state).await?;
+        let UserAgent(user_agent) = UserAgent::from_request_parts(parts, state).await?;
+        Ok(SessionClient { user_agent, ip })
+    }
+}
@@ -0,0 +1,106 @@
+use rustwebapp::model::access_token::{self, AccessTokenNewFields, ErrorAccessToken, Scope};
+
+use crate::shared::{db::TestDb, user::new_user};
+
+fn fields(scopes: &[&str]) -> AccessTokenNewFields {
+    AccessTokenNewFields {
+        name: "ci".into(),
+        scopes: scopes.iter().map(|s| s.to_string()).collect(),
+        expires_in_days: None,
+    }
+}
+
+#[tokio::test]
+async fn create_and_authenticate() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let user = new_user(&db, "bob").await?;
+
+    let (created, secret) = access_token::create(
+        db.conn()?,
+        user.id,
+        fields(&["lobby:write", "lobby:read", "lobby:read"]),
+    )
+    .await?;
+    assert!(secret.starts_with(access_token::TOKEN_PREFIX));
+    assert_eq!(created.scopes, vec![Scope::LobbyRead, Scope::LobbyWrite]);
+    assert_eq!(created.last_used_at, None);
+
+    let authenticated = access_token::authenticate(db.conn()?, &secret).await?;
+    assert_eq!(authenticated.id, created.id);
+    assert_eq!(authenticated.user_id, user.id);
+
+    let listed = access_token::list(db.conn()?, user.id).await?;
+    assert_eq!(listed.len(), 1);
+    assert!(listed[0].last_used_at.is_some());
+    Ok(())
+}
+
+#[tokio::test]
+async fn create_validates_fields() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let user = new_user(&db, "bob").await?;
+
+    let result = access_token::create(db.conn()?, user.id, fields(&["lobby:delete"])).await;
+    assert_eq!(
+        result,
+        Err(ErrorAccessToken::UnknownScope("lobby:delete".into()))
+    );
+
+    let result = access_token::create(db.conn()?, user.id, fields(&[])).await;
+    assert_eq!(result, Err(ErrorAccessToken::NoScopes));
+
+    let blank_name = AccessTokenNewFields {
+        name: "  ".into(),
+        ..fields(&["lobby:read"])
+    };
+    let result = access_token::create(db.conn()?, user.id, blank_name).await;
+    assert_eq!(result, Err(ErrorAccessToken::InvalidName));
+
+    let forever = AccessTokenNewFields {
+        expires_in_days: Some(access_token::MAX_LIFETIME_DAYS + 1),
+        ..fields(&["lobby:read"])
+    };
+    let result = access_token::create(db.conn()?, user.id, forever).await;
+    assert_eq!(result, Err(ErrorAccessToken::InvalidLifetime));
+    Ok(())
+}
+
+#[tokio::test]
+async fn revoked_token_is_rejected() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let user = new_user(&db, "bob").await?;
+    let (created, secret) =
+        access_token::create(db.conn()?, user.id, fields(&["lobby:read"])).await?;
+
+    access_token::revoke(db.conn()?, user.id, created.id).await?;
+
+    let result = access_token::authenticate(db.conn()?, &secret).await;
+    assert_eq!(result, Err(ErrorAccessToken::InvalidToken));
+    assert!(access_token::list(db.conn()?, user.id).await?.is_empty());
+
+    let result = access_token::revoke(db.conn()?, user.id, created.id).await;
+    assert_eq!(result, Err(ErrorAccessToken::NotFound));
+    Ok(())
+}
+
+#[tokio::test]
+async fn only_the_owner_can_revoke() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let user = new_user(&db, "bob").await?;
+    let alice = new_user(&db, "alice").await?;
+    let (created, secret) =
+        access_token::create(db.conn()?, user.id, fields(&["lobby:read"])).await?;
+
+    let result = access_token::revoke(db.conn()?, alice.id, created.id).await;
+    assert_eq!(result, Err(ErrorAccessToken::NotFound));
+    access_token::authenticate(db.conn()?, &secret).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn unknown_token_is_rejected() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let result = access_token::authenticate(db.conn()?, "pat_not-a-real-token").await;
+    assert_eq!(result, Err(ErrorAccessToken::InvalidToken));
+    Ok(())
+}
@@ -0,0 +1,152 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rustwebapp::model::audit::{self, AuditEventNew, AuditFilter, AuditKind};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::shared::{db::TestDb, user::new_user};
+
+fn event(kind: AuditKind, actor_id: Option<i32>, target_id: Option<i32>) -> AuditEventNew {
+    AuditEventNew {
+        kind,
+        actor_id,
+        target_id,
+        ip: Some("203.0.113.7".into()),
+        user_agent: Some("curl/8.5.0".into()),
+        request_id: Uuid::new_v4(),
+        detail: json!({}),
+    }
+}
+
+fn kinds(events: &[audit::AuditEvent]) -> Vec<AuditKind> {
+    events.iter().map(|event| event.kind).collect()
+}
+
+#[tokio::test]
+async fn record_keeps_the_request_details() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let bob = new_user(&db, "bob").await?.id;
+
+    let new = AuditEventNew {
+        detail: json!({ "email": "bob@contoso.com", "reason": "password" }),
+        ..event(AuditKind::LoginFailed, None, Some(bob))
+    };
+    let recorded = audit::record(db.conn()?, new.clone()).await?;
+
+    assert_eq!(recorded.kind, AuditKind::LoginFailed);
+    assert_eq!(recorded.actor_id, None);
+    assert_eq!(recorded.target_id, Some(bob));
+    assert_eq!(recorded.ip, new.ip);
+    assert_eq!(recorded.user_agent, new.user_agent);
+    assert_eq!(recorded.request_id, new.request_id);
+    assert_eq!(recorded.detail["reason"], "password");
+    Ok(())
+}
+
+#[tokio::test]
+async fn user_sees_events_they_caused_or_that_happened_to_them() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let bob = new_user(&db, "bob").await?.id;
+    let alice = new_user(&db, "alice").await?.id;
+
+    audit::record(
+        db.conn()?,
+        event(AuditKind::Registered, Some(bob), Some(bob)),
+    )
+    .await?;
+    audit::record(
+        db.conn()?,
+        event(AuditKind::Registered, Some(alice), Some(alice)),
+    )
+    .await?;
+    audit::record(
+        db.conn()?,
+        event(AuditKind::RoleChanged, Some(alice), Some(bob)),
+    )
+    .await?;
+    audit::record(
+        db.conn()?,
+        event(AuditKind::LockoutCleared, Some(alice), None),
+    )
+    .await?;
+
+    let filter = AuditFilter {
+        user_id: Some(bob),
+        ..Default::default()
+    };
+    let events = audit::query(db.conn()?, &filter).await?;
+    assert_eq!(
+        kinds(&events),
+        vec![AuditKind::RoleChanged, AuditKind::Registered]
+    );
+
+    let filter = AuditFilter {
+        actor_id: Some(alice),
+        kind: Some(AuditKind::LockoutCleared),
+        ..Default::default()
+    };
+    let events = audit::query(db.conn()?, &filter).await?;
+    assert_eq!(kinds(&events), vec![AuditKind::LockoutCleared]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn query_pages_newest_first() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let bob = new_user(&db, "bob").await?.id;
+    for _ in 0..5 {
+        audit::record(
+            db.conn()?,
+            event(AuditKind::LoginSucceeded, Some(bob), Some(bob)),
+        )
+        .await?;
+    }
+
+    let first = AuditFilter {
+        limit: Some(2),
+        ..Default::default()
+    };
+    let page = audit::query(db.conn()?, &first).await?;
+    assert_eq!(page.len(), 2);
+    assert!(page[0].id > page[1].id);
+
+    let next = AuditFilter {
+        before: Some(page[1].id),
+        ..first
+    };
+    let next_page = audit::query(db.conn()?, &next).await?;
+    assert_eq!(next_page.len(), 2);
+    assert!(next_page[0].id < page[1].id);
+    Ok(())
+}
+
+#[tokio::test]
+async fn query_filters_by_time_and_request() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let bob = new_user(&db, "bob").await?.id;
+    let recorded = audit::record(
+        db.conn()?,
+        event(AuditKind::PasswordChanged, Some(bob), Some(bob)),
+    )
+    .await?;
+    audit::record(
+        db.conn()?,
+        event(AuditKind::EmailChanged, Some(bob), Some(bob)),
+    )
+    .await?;
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let future = AuditFilter {
+        since: Some(now + 60),
+        ..Default::default()
+    };
+    assert!(audit::query(db.conn()?, &future).await?.is_empty());
+
+    let by_request = AuditFilter {
+        request_id: Some(recorded.request_id),
+        until: Some(now + 60),
+        ..Default::default()
+    };
+    assert_eq!(audit::query(db.conn()?, &by_request).await?, vec![recorded]);
+    Ok(())
+}
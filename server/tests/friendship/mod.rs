@@ -0,0 +1,67 @@
+use rustwebapp::model::friendship::{self, ErrorFriendship};
+
+use crate::shared::{db::TestDb, user::new_user};
+
+#[tokio::test]
+async fn friendship_is_one_way() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let alice = new_user(&db, "alice").await?.id;
+    let bob = new_user(&db, "bob").await?.id;
+
+    assert!(friendship::add(db.conn()?, alice, bob).await?);
+    assert!(!friendship::add(db.conn()?, alice, bob).await?);
+
+    let friends = friendship::list(db.conn()?, alice).await?;
+    assert_eq!(friends.len(), 1);
+    assert_eq!(friends[0].user_id, bob);
+    assert_eq!(friends[0].display_name, None);
+    assert!(friendship::list(db.conn()?, bob).await?.is_empty());
+
+    friendship::remove(db.conn()?, alice, bob).await?;
+    assert!(friendship::list(db.conn()?, alice).await?.is_empty());
+    assert_eq!(
+        friendship::remove(db.conn()?, alice, bob).await,
+        Err(ErrorFriendship::NotFound)
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn names_only_for_mutual_friends() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let alice = new_user(&db, "alice").await?.id;
+    let bob = new_user(&db, "bob").await?.id;
+    let carol = new_user(&db, "carol").await?.id;
+
+    friendship::add(db.conn()?, alice, carol).await?;
+    friendship::add(db.conn()?, alice, bob).await?;
+    friendship::add(db.conn()?, bob, alice).await?;
+
+    // Oldest first, not by the names hidden among them
+    let friends = friendship::list(db.conn()?, alice).await?;
+    let names: Vec<_> = friends
+        .iter()
+        .map(|friend| (friend.user_id, friend.display_name.as_deref()))
+        .collect();
+    assert_eq!(names, vec![(carol, None), (bob, Some("bob"))]);
+
+    let friends = friendship::list(db.conn()?, bob).await?;
+    assert_eq!(friends[0].display_name.as_deref(), Some("alice"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn only_other_users_can_be_friends() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let alice = new_user(&db, "alice").await?.id;
+
+    assert_eq!(
+        friendship::add(db.conn()?, alice, alice).await,
+        Err(ErrorFriendship::SelfFriend)
+    );
+    assert_eq!(
+        friendship::add(db.conn()?, alice, alice + 1000).await,
+        Err(ErrorFriendship::NotFound)
+    );
+    Ok(())
+}
@@ -0,0 +1,129 @@
+use axum::http::{Method, StatusCode};
+use rustwebapp::{
+    model::{
+        audit::{self, AuditFilter, AuditKind},
+        role::Role,
+        session::{self, SessionClient},
+        user::{self, User},
+    },
+    web::error::ErrorClient,
+};
+use serde_json::{json, Value};
+
+use crate::shared::{app::App, db::TestDb, user::new_user};
+
+impl App {
+    /// Impersonate `user` as `token`'s user, returning the impersonation token
+    async fn impersonate(&self, token: &str, user: &User) -> anyhow::Result<(StatusCode, Value)> {
+        let uri = format!("/api/admin/impersonate/{}", user.id);
+        self.send(Method::POST, &uri, token, json!({})).await
+    }
+
+    async fn impersonation_token(&self, user: &User) -> anyhow::Result<String> {
+        let (status, body) = self.impersonate(&self.token(&self.admin)?, user).await?;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        Ok(body["access_token"].as_str().unwrap().to_string())
+    }
+}
+
+#[tokio::test]
+async fn impersonation_cannot_touch_credentials() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let app = App::new(&db).await?;
+    let bob = new_user(&db, "bob").await?;
+    let (bob_session, _) = session::create(db.conn()?, bob.id, &SessionClient::default()).await?;
+    let token = app.impersonation_token(&bob).await?;
+
+    let password = json!({ "current_password": "x", "new_password": "y" });
+    let revoke_session = format!("/api/account/sessions/{}", bob_session.id);
+    for (method, uri, body) in [
+        (Method::POST, "/api/account/password", password),
+        (Method::DELETE, "/api/account/sessions", json!({})),
+        (Method::DELETE, revoke_session.as_str(), json!({})),
+        (
+            Method::DELETE,
+            "/api/account/tokens/00000000-0000-0000-0000-000000000000",
+            json!({}),
+        ),
+    ] {
+        let (status, body) = app.send(method, uri, &token, body).await?;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{uri}");
+        assert_eq!(
+            body["msg"],
+            ErrorClient::ImpersonationForbidden.to_string(),
+            "{uri}"
+        );
+    }
+    assert_eq!(session::list_active(db.conn()?, bob.id).await?.len(), 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn impersonated_requests_are_audited() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let app = App::new(&db).await?;
+    let bob = new_user(&db, "bob").await?;
+    let token = app.impersonation_token(&bob).await?;
+
+    let (status, body) = app
+        .send(Method::GET, "/api/account/me", &token, json!({}))
+        .await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["id"], bob.id);
+
+    let filter = AuditFilter {
+        kind: Some(AuditKind::ImpersonatedRequest),
+        ..Default::default()
+    };
+    let events = audit::query(db.conn()?, &filter).await?;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].actor_id, Some(app.admin.id));
+    assert_eq!(events[0].target_id, Some(bob.id));
+    assert_eq!(events[0].detail["path"], "/api/account/me");
+    assert_eq!(events[0].detail["status"], 200);
+    Ok(())
+}
+
+#[tokio::test]
+async fn only_admins_impersonate() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let app = App::new(&db).await?;
+    let bob = new_user(&db, "bob").await?;
+    let alice = new_user(&db, "alice").await?;
+
+    let (status, body) = app.impersonate(&app.token(&bob)?, &alice).await?;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["msg"], ErrorClient::Forbidden.to_string());
+    Ok(())
+}
+
+#[tokio::test]
+async fn admins_cannot_be_impersonated() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let app = App::new(&db).await?;
+    let other = new_user(&db, "other").await?;
+    let other = user::set_role(db.conn()?, other.id, Role::Admin).await?;
+
+    let (status, _) = app.impersonate(&app.token(&app.admin)?, &other).await?;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    Ok(())
+}
+
+#[tokio::test]
+async fn impersonation_tokens_cannot_impersonate() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let app = App::new(&db).await?;
+    let bob = new_user(&db, "bob").await?;
+    let alice = new_user(&db, "alice").await?;
+    let token = app.impersonation_token(&bob).await?;
+
+    let (status, _) = app.impersonate(&token, &alice).await?;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let filter = AuditFilter {
+        kind: Some(AuditKind::ImpersonationStarted),
+        ..Default::default()
+    };
+    assert_eq!(audit::query(db.conn()?, &filter).await?.len(), 1);
+    Ok(())
+}
@@ -0,0 +1,651 @@
+use std::time::{Duration, SystemTime};
+
+use axum::{
+    body::Body,
+    http::{header, Method, StatusCode},
+};
+use rustwebapp::model::{
+    audit::{self, AuditEvent, AuditFilter, AuditKind},
+    friendship,
+    lobby::{
+        Departure, ErrorLobby, LobbyActor, LobbyController, LobbyForCreate, LobbyForInsert,
+        LobbyForUpdate, LobbyMember, LobbyStore, MemoryLobbyStore, PgLobbyStore, Visibility,
+        DEFAULT_MAX_PLAYERS,
+    },
+    role::Role,
+};
+use rustwebapp::service::throttle::ThrottlePolicy;
+use serde_json::json;
+
+use crate::shared::time::assert_within;
+use crate::shared::{app::App, db::TestDb, user::new_user};
+
+const CLIENT_IP: &str = "x-client-ip";
+
+fn fields(name: &str, visibility: Visibility) -> LobbyForCreate {
+    LobbyForCreate {
+        name: name.into(),
+        visibility,
+        max_players: DEFAULT_MAX_PLAYERS,
+    }
+}
+
+fn user_ids(members: &[LobbyMember]) -> Vec<i32> {
+    members.iter().map(|member| member.user_id).collect()
+}
+
+async fn user(db: &TestDb, name: &str, role: Role) -> anyhow::Result<LobbyActor> {
+    Ok(LobbyActor {
+        user_id: new_user(db, name).await?.id,
+        role,
+    })
+}
+
+/// An owner, someone the owner counts as a friend, someone else and an admin
+struct Actors {
+    owner: LobbyActor,
+    friend: LobbyActor,
+    other: LobbyActor,
+    admin: LobbyActor,
+}
+
+/// A controller over each store with the same cast of users in it
+async fn postgres(db: &TestDb) -> anyhow::Result<(LobbyController, Actors)> {
+    let actors = Actors {
+        owner: user(db, "owner", Role::User).await?,
+        friend: user(db, "friend", Role::User).await?,
+        other: user(db, "other", Role::User).await?,
+        admin: user(db, "admin", Role::Admin).await?,
+    };
+    friendship::add(db.conn()?, actors.owner.user_id, actors.friend.user_id).await?;
+    Ok((LobbyController::new(db.pool.clone()), actors))
+}
+
+fn in_memory() -> (LobbyController, Actors) {
+    let actor = |user_id, role| LobbyActor { user_id, role };
+    let actors = Actors {
+        owner: actor(1, Role::User),
+        friend: actor(2, Role::User),
+        other: actor(3, Role::User),
+        admin: actor(4, Role::Admin),
+    };
+    let store = MemoryLobbyStore::default().with_friendship(1, 2);
+    (LobbyController::with_store(store), actors)
+}
+
+/// The same checks run against every store, so the in-memory one stays a faithful stand-in
+async fn create_get_delete(ctl_lobby: LobbyController, actors: Actors) -> anyhow::Result<()> {
+    let Actors { owner, .. } = actors;
+    let now = SystemTime::now();
+    let public = ctl_lobby
+        .create_lobby(&owner, fields("Friday Night", Visibility::Public))
+        .await?;
+    let private = ctl_lobby
+        .create_lobby(&owner, fields("Secret Club", Visibility::Private))
+        .await?;
+
+    assert_eq!(public.name, "Friday Night");
+    assert_eq!(public.owner_id, owner.user_id);
+    assert_eq!(public.visibility, Visibility::Public);
+    assert_within(public.created_at, now, Duration::from_secs(5));
+    assert_ne!(public.id, private.id);
+
+    assert_eq!(
+        ctl_lobby.get_lobby(&owner, private.id).await?.lobby,
+        private
+    );
+    assert_eq!(ctl_lobby.get_lobbies(&owner).await?, vec![public.clone()]);
+
+    ctl_lobby.delete_lobby(&owner, public.id).await?;
+    assert_eq!(
+        ctl_lobby.get_lobby(&owner, public.id).await,
+        Err(ErrorLobby::NotFound)
+    );
+    assert_eq!(
+        ctl_lobby.delete_lobby(&owner, public.id).await,
+        Err(ErrorLobby::NotFound)
+    );
+    assert_eq!(
+        ctl_lobby.get_lobby(&owner, 0).await,
+        Err(ErrorLobby::NotFound)
+    );
+    assert_eq!(ctl_lobby.get_lobbies(&owner).await?, vec![]);
+    Ok(())
+}
+
+async fn only_owner_or_admin_mutates(
+    ctl_lobby: LobbyController,
+    actors: Actors,
+) -> anyhow::Result<()> {
+    let Actors {
+        owner,
+        other,
+        admin,
+        ..
+    } = actors;
+    let lobby = ctl_lobby
+        .create_lobby(&owner, fields("Friday Night", Visibility::Public))
+        .await?;
+    let rename = |name: &str| LobbyForUpdate {
+        name: Some(name.into()),
+        visibility: None,
+    };
+
+    assert_eq!(
+        ctl_lobby
+            .update_lobby(&other, lobby.id, rename("Mine Now"))
+            .await,
+        Err(ErrorLobby::Forbidden)
+    );
+    assert_eq!(
+        ctl_lobby.delete_lobby(&other, lobby.id).await,
+        Err(ErrorLobby::Forbidden)
+    );
+    assert_eq!(ctl_lobby.get_lobby(&other, lobby.id).await?.lobby, lobby);
+
+    let renamed = ctl_lobby
+        .update_lobby(&owner, lobby.id, rename(" Saturday Night "))
+        .await?;
+    assert_eq!(renamed.name, "Saturday Night");
+    assert_eq!(renamed.visibility, Visibility::Public);
+
+    let hidden = ctl_lobby
+        .update_lobby(
+            &admin,
+            lobby.id,
+            LobbyForUpdate {
+                name: None,
+                visibility: Some(Visibility::Private),
+            },
+        )
+        .await?;
+    assert_eq!(hidden.name, "Saturday Night");
+    assert_eq!(hidden.visibility, Visibility::Private);
+    assert_eq!(hidden.owner_id, owner.user_id);
+
+    assert_eq!(
+        ctl_lobby
+            .update_lobby(&owner, lobby.id, LobbyForUpdate::default())
+            .await?,
+        hidden
+    );
+    assert_eq!(
+        ctl_lobby.update_lobby(&owner, lobby.id, rename("  ")).await,
+        Err(ErrorLobby::InvalidName)
+    );
+    assert_eq!(
+        ctl_lobby.update_lobby(&owner, 0, rename("Gone")).await,
+        Err(ErrorLobby::NotFound)
+    );
+
+    // Once private, the lobby is out of sight for others rather than off limits
+    assert_eq!(
+        ctl_lobby.delete_lobby(&other, lobby.id).await,
+        Err(ErrorLobby::NotFound)
+    );
+
+    ctl_lobby.delete_lobby(&admin, lobby.id).await?;
+    assert_eq!(
+        ctl_lobby.get_lobby(&admin, lobby.id).await,
+        Err(ErrorLobby::NotFound)
+    );
+    Ok(())
+}
+
+async fn join_and_leave(ctl_lobby: LobbyController, actors: Actors) -> anyhow::Result<()> {
+    let Actors {
+        owner,
+        other,
+        admin,
+        ..
+    } = actors;
+    assert_eq!(
+        ctl_lobby
+            .create_lobby(
+                &owner,
+                LobbyForCreate {
+                    max_players: 1,
+                    ..fields("Solo", Visibility::Public)
+                },
+            )
+            .await,
+        Err(ErrorLobby::InvalidMaxPlayers)
+    );
+    let lobby = ctl_lobby
+        .create_lobby(
+            &owner,
+            LobbyForCreate {
+                max_players: 2,
+                ..fields("Duel", Visibility::Public)
+            },
+        )
+        .await?;
+    let details = ctl_lobby.get_lobby(&other, lobby.id).await?;
+    assert_eq!(details.lobby, lobby);
+    assert_eq!(user_ids(&details.members), vec![owner.user_id]);
+
+    let joined = ctl_lobby.join_lobby(&other, lobby.id).await?;
+    assert_eq!(
+        user_ids(&joined.members),
+        vec![owner.user_id, other.user_id]
+    );
+    assert!(joined.members[0].joined_at <= joined.members[1].joined_at);
+    assert_eq!(
+        ctl_lobby.join_lobby(&other, lobby.id).await,
+        Err(ErrorLobby::AlreadyMember)
+    );
+    assert_eq!(
+        ctl_lobby.join_lobby(&admin, lobby.id).await,
+        Err(ErrorLobby::Full)
+    );
+    assert_eq!(
+        ctl_lobby.join_lobby(&admin, 0).await,
+        Err(ErrorLobby::NotFound)
+    );
+
+    assert_eq!(
+        ctl_lobby.leave_lobby(&other, lobby.id).await?,
+        Departure::Left
+    );
+    assert_eq!(
+        ctl_lobby.leave_lobby(&other, lobby.id).await,
+        Err(ErrorLobby::NotMember)
+    );
+
+    // The owner leaving hands the lobby to whoever has been there longest
+    ctl_lobby.join_lobby(&admin, lobby.id).await?;
+    assert_eq!(
+        ctl_lobby.leave_lobby(&owner, lobby.id).await?,
+        Departure::HostTransferred {
+            owner_id: admin.user_id
+        }
+    );
+    let details = ctl_lobby.get_lobby(&admin, lobby.id).await?;
+    assert_eq!(details.lobby.owner_id, admin.user_id);
+    assert_eq!(user_ids(&details.members), vec![admin.user_id]);
+
+    // And the last one out closes it
+    assert_eq!(
+        ctl_lobby.leave_lobby(&admin, lobby.id).await?,
+        Departure::Closed
+    );
+    assert_eq!(
+        ctl_lobby.get_lobby(&admin, lobby.id).await,
+        Err(ErrorLobby::NotFound)
+    );
+    assert_eq!(
+        ctl_lobby.join_lobby(&other, lobby.id).await,
+        Err(ErrorLobby::NotFound)
+    );
+    Ok(())
+}
+
+async fn visibility_is_enforced(ctl_lobby: LobbyController, actors: Actors) -> anyhow::Result<()> {
+    let Actors {
+        owner,
+        friend,
+        other,
+        admin,
+    } = actors;
+    let public = ctl_lobby
+        .create_lobby(&owner, fields("Open Table", Visibility::Public))
+        .await?;
+    let friends = ctl_lobby
+        .create_lobby(&owner, fields("Close Friends", Visibility::Friends))
+        .await?;
+    let private = ctl_lobby
+        .create_lobby(&owner, fields("Secret Club", Visibility::Private))
+        .await?;
+    let one_way = ctl_lobby
+        .create_lobby(&friend, fields("One Way", Visibility::Friends))
+        .await?;
+
+    let everyone = vec![public.clone()];
+    let with_friends = vec![public.clone(), friends.clone()];
+    assert_eq!(ctl_lobby.get_lobbies(&owner).await?, with_friends);
+    assert_eq!(
+        ctl_lobby.get_lobbies(&friend).await?,
+        vec![public.clone(), friends.clone(), one_way.clone()]
+    );
+    assert_eq!(ctl_lobby.get_lobbies(&other).await?, everyone);
+    assert_eq!(ctl_lobby.get_lobbies(&admin).await?, everyone);
+
+    // Hidden lobbies look like they don't exist
+    for (actor, id) in [
+        (&other, friends.id),
+        (&other, private.id),
+        (&friend, private.id),
+        (&owner, one_way.id),
+    ] {
+        assert_eq!(
+            ctl_lobby.get_lobby(actor, id).await,
+            Err(ErrorLobby::NotFound)
+        );
+        assert_eq!(
+            ctl_lobby.join_lobby(actor, id).await,
+            Err(ErrorLobby::NotFound)
+        );
+        assert_eq!(
+            ctl_lobby.leave_lobby(actor, id).await,
+            Err(ErrorLobby::NotFound)
+        );
+    }
+    assert_eq!(
+        ctl_lobby.get_lobby(&admin, private.id).await?.lobby,
+        private
+    );
+    assert_eq!(
+        ctl_lobby.join_lobby(&admin, private.id).await,
+        Err(ErrorLobby::InviteRequired)
+    );
+
+    // Members keep their lobby when it turns private, it just stops being listed
+    ctl_lobby.join_lobby(&friend, friends.id).await?;
+    ctl_lobby
+        .update_lobby(
+            &owner,
+            friends.id,
+            LobbyForUpdate {
+                name: None,
+                visibility: Some(Visibility::Private),
+            },
+        )
+        .await?;
+    let details = ctl_lobby.get_lobby(&friend, friends.id).await?;
+    assert_eq!(details.lobby.visibility, Visibility::Private);
+    assert_eq!(ctl_lobby.get_lobbies(&friend).await?, vec![public, one_way]);
+    assert_eq!(
+        ctl_lobby.leave_lobby(&friend, friends.id).await?,
+        Departure::Left
+    );
+    assert_eq!(
+        ctl_lobby.get_lobby(&friend, friends.id).await,
+        Err(ErrorLobby::NotFound)
+    );
+    Ok(())
+}
+
+async fn invites(ctl_lobby: LobbyController, actors: Actors) -> anyhow::Result<()> {
+    let Actors {
+        owner,
+        friend,
+        other,
+        ..
+    } = actors;
+    let lobby = ctl_lobby
+        .create_lobby(&owner, fields("Secret Club", Visibility::Private))
+        .await?;
+    assert_eq!(
+        ctl_lobby.get_lobby(&owner, lobby.id).await?.invite_code,
+        None
+    );
+    assert_eq!(
+        ctl_lobby.rotate_invite_code(&other, lobby.id).await,
+        Err(ErrorLobby::NotFound)
+    );
+
+    let code = ctl_lobby.rotate_invite_code(&owner, lobby.id).await?;
+    assert_eq!(code.split('-').count(), 3);
+    assert_eq!(
+        ctl_lobby.get_lobby(&owner, lobby.id).await?.invite_code,
+        Some(code.clone())
+    );
+
+    // Codes are read out loud, so case and spacing don't matter
+    let spoken = code.replace('-', " ").to_uppercase();
+    let joined = ctl_lobby.join_with_invite(&other, &spoken).await?;
+    assert_eq!(
+        user_ids(&joined.members),
+        vec![owner.user_id, other.user_id]
+    );
+    assert_eq!(joined.invite_code, Some(code.clone()));
+    assert_eq!(
+        ctl_lobby.join_with_invite(&other, &code).await,
+        Err(ErrorLobby::AlreadyMember)
+    );
+
+    let rotated = ctl_lobby.rotate_invite_code(&owner, lobby.id).await?;
+    assert_ne!(rotated, code);
+    for stale in [code.as_str(), "not-a-code", "acid able"] {
+        assert_eq!(
+            ctl_lobby.join_with_invite(&friend, stale).await,
+            Err(ErrorLobby::InviteNotFound)
+        );
+    }
+
+    // Codes go when the lobby closes
+    ctl_lobby.leave_lobby(&other, lobby.id).await?;
+    ctl_lobby.leave_lobby(&owner, lobby.id).await?;
+    assert_eq!(
+        ctl_lobby.join_with_invite(&friend, &rotated).await,
+        Err(ErrorLobby::InviteNotFound)
+    );
+    Ok(())
+}
+
+async fn invite_codes_are_unique(store: impl LobbyStore, owner_id: i32) -> anyhow::Result<()> {
+    let insert = |name: &str| LobbyForInsert {
+        owner_id,
+        name: name.into(),
+        visibility: Visibility::Private,
+        max_players: DEFAULT_MAX_PLAYERS,
+    };
+    let first = store.create_lobby(insert("First")).await?;
+    let second = store.create_lobby(insert("Second")).await?;
+
+    store.set_invite_code(first.id, "acid-able-ache").await?;
+    assert_eq!(
+        store.set_invite_code(second.id, "acid-able-ache").await,
+        Err(ErrorLobby::InviteCodeTaken)
+    );
+    store.set_invite_code(first.id, "acid-able-ajar").await?;
+    store.set_invite_code(second.id, "acid-able-ache").await?;
+    assert_eq!(
+        store.find_by_invite_code("acid-able-ache").await?.id,
+        second.id
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn postgres_store() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let (ctl_lobby, actors) = postgres(&db).await?;
+    create_get_delete(ctl_lobby, actors).await
+}
+
+#[tokio::test]
+async fn in_memory_store() -> anyhow::Result<()> {
+    let (ctl_lobby, actors) = in_memory();
+    create_get_delete(ctl_lobby, actors).await
+}
+
+#[tokio::test]
+async fn postgres_store_permissions() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let (ctl_lobby, actors) = postgres(&db).await?;
+    only_owner_or_admin_mutates(ctl_lobby, actors).await
+}
+
+#[tokio::test]
+async fn in_memory_store_permissions() -> anyhow::Result<()> {
+    let (ctl_lobby, actors) = in_memory();
+    only_owner_or_admin_mutates(ctl_lobby, actors).await
+}
+
+#[tokio::test]
+async fn postgres_store_membership() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let (ctl_lobby, actors) = postgres(&db).await?;
+    join_and_leave(ctl_lobby, actors).await
+}
+
+#[tokio::test]
+async fn in_memory_store_membership() -> anyhow::Result<()> {
+    let (ctl_lobby, actors) = in_memory();
+    join_and_leave(ctl_lobby, actors).await
+}
+
+#[tokio::test]
+async fn postgres_store_visibility() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let (ctl_lobby, actors) = postgres(&db).await?;
+    visibility_is_enforced(ctl_lobby, actors).await
+}
+
+#[tokio::test]
+async fn in_memory_store_visibility() -> anyhow::Result<()> {
+    let (ctl_lobby, actors) = in_memory();
+    visibility_is_enforced(ctl_lobby, actors).await
+}
+
+#[tokio::test]
+async fn postgres_store_invites() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let (ctl_lobby, actors) = postgres(&db).await?;
+    invites(ctl_lobby, actors).await
+}
+
+#[tokio::test]
+async fn in_memory_store_invites() -> anyhow::Result<()> {
+    let (ctl_lobby, actors) = in_memory();
+    invites(ctl_lobby, actors).await
+}
+
+#[tokio::test]
+async fn postgres_store_invite_codes_are_unique() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let owner = user(&db, "owner", Role::User).await?;
+    invite_codes_are_unique(PgLobbyStore::new(db.pool.clone()), owner.user_id).await
+}
+
+#[tokio::test]
+async fn in_memory_store_invite_codes_are_unique() -> anyhow::Result<()> {
+    invite_codes_are_unique(MemoryLobbyStore::default(), 1).await
+}
+
+#[tokio::test]
+async fn lobbies_outlive_their_controller() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let (ctl_lobby, Actors { owner, .. }) = postgres(&db).await?;
+    let lobby = ctl_lobby
+        .create_lobby(&owner, fields("Persistent", Visibility::Friends))
+        .await?;
+
+    // A fresh controller, as after a deploy or on another machine
+    let ctl_lobby = LobbyController::new(db.pool.clone());
+    assert_eq!(ctl_lobby.get_lobby(&owner, lobby.id).await?.lobby, lobby);
+    Ok(())
+}
+
+async fn audited(db: &TestDb, kind: AuditKind) -> anyhow::Result<Vec<AuditEvent>> {
+    let filter = AuditFilter {
+        kind: Some(kind),
+        ..Default::default()
+    };
+    Ok(audit::query(db.conn()?, &filter).await?)
+}
+
+#[tokio::test]
+async fn admin_changes_to_others_lobbies_are_audited() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let app = App::new(&db).await?;
+    let owner = new_user(&db, "owner").await?;
+    let actor = LobbyActor {
+        user_id: owner.id,
+        role: Role::User,
+    };
+    let lobby = app
+        .app_state
+        .ctl_lobby
+        .create_lobby(&actor, fields("Mine", Visibility::Public))
+        .await?;
+    let uri = format!("/api/lobby/{}", lobby.id);
+
+    // Owners changing their own lobbies isn't worth an event
+    let rename = json!({ "name": "Still mine" });
+    let (status, _) = app
+        .send(Method::PATCH, &uri, &app.token(&owner)?, rename)
+        .await?;
+    assert_eq!(status, StatusCode::OK);
+    assert!(audited(&db, AuditKind::LobbyUpdatedByAdmin)
+        .await?
+        .is_empty());
+
+    let admin_token = app.token(&app.admin)?;
+    let rename = json!({ "name": "Renamed" });
+    let (status, _) = app.send(Method::PATCH, &uri, &admin_token, rename).await?;
+    assert_eq!(status, StatusCode::OK);
+    let events = audited(&db, AuditKind::LobbyUpdatedByAdmin).await?;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].actor_id, Some(app.admin.id));
+    assert_eq!(events[0].target_id, Some(owner.id));
+    assert_eq!(events[0].detail["lobby_id"], lobby.id);
+    assert_eq!(events[0].detail["name"], "Renamed");
+
+    let (status, _) = app
+        .send(Method::DELETE, &uri, &admin_token, json!({}))
+        .await?;
+    assert_eq!(status, StatusCode::OK);
+    let events = audited(&db, AuditKind::LobbyDeletedByAdmin).await?;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].actor_id, Some(app.admin.id));
+    assert_eq!(events[0].target_id, Some(owner.id));
+    assert_eq!(events[0].detail["name"], "Renamed");
+    Ok(())
+}
+
+#[tokio::test]
+async fn invite_code_guesses_are_throttled() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let app = App::with_state(&db, |app_state| {
+        app_state.config.client_ip_header = Some(CLIENT_IP.into());
+    })
+    .await?;
+    let owner = new_user(&db, "owner").await?;
+    let actor = LobbyActor {
+        user_id: owner.id,
+        role: Role::User,
+    };
+    let lobby = app
+        .app_state
+        .ctl_lobby
+        .create_lobby(&actor, fields("Invite only", Visibility::Private))
+        .await?;
+    let code = app
+        .app_state
+        .ctl_lobby
+        .rotate_invite_code(&actor, lobby.id)
+        .await?;
+
+    let join = |token: &str, ip: &str, code: &str| {
+        let uri = format!("/api/lobby/join/{code}");
+        let req = App::request(Method::POST, &uri, token)
+            .header(CLIENT_IP, ip)
+            .body(Body::from("{}"));
+        async { app.send_request(req?).await }
+    };
+
+    let wrong = match code.as_str() {
+        "acid-able-ache" => "able-acid-ache",
+        _ => "acid-able-ache",
+    };
+    let guesser = app.token(&new_user(&db, "guesser").await?)?;
+    for _ in 0..=ThrottlePolicy::default().free_attempts {
+        let (status, _, _) = join(&guesser, "192.0.2.1", wrong).await?;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    // Now even the right code has to wait, from the guesser or anyone else at their address
+    let (status, headers, _) = join(&guesser, "192.0.2.1", &code).await?;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert!(headers.contains_key(header::RETRY_AFTER));
+    let neighbour = app.token(&new_user(&db, "neighbour").await?)?;
+    let (status, _, _) = join(&neighbour, "192.0.2.1", &code).await?;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+
+    let (status, _, body) = join(&neighbour, "198.51.100.1", &code).await?;
+    assert_eq!(status, StatusCode::OK, "{body}");
+    assert_eq!(body["id"], lobby.id);
+    Ok(())
+}
@@ -1,2 +1,10 @@
+mod access_token;
+mod audit;
+mod friendship;
+mod impersonation;
+mod lobby;
+mod oidc;
+mod session;
 mod shared;
+mod throttle;
 mod user;
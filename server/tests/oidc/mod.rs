@@ -0,0 +1,294 @@
+use rustwebapp::{
+    model::{
+        identity::{self, ErrorIdentity},
+        user::{self},
+    },
+    service::oidc::{ErrorOidc, OidcController, OidcProfile},
+};
+
+use crate::shared::{
+    db::TestDb,
+    mock_idp::{MockIdp, MockUser, Tamper, PROVIDER},
+    user::{new_user, PASSWORD},
+};
+
+async fn idp() -> anyhow::Result<(MockIdp, OidcController)> {
+    let idp = MockIdp::start().await?;
+    let ctl_oidc = OidcController::new(vec![idp.provider()])?;
+    Ok((idp, ctl_oidc))
+}
+
+/// Run a whole login through the provider
+async fn sign_in(idp: &MockIdp, ctl_oidc: &OidcController) -> Result<OidcProfile, ErrorOidc> {
+    let (authorize_url, pending) = ctl_oidc.authorize(PROVIDER, None).await?;
+    let (code, state) = idp.sign_in(&authorize_url).await.expect("Sign in failed");
+    ctl_oidc.callback(&pending, &state, &code).await
+}
+
+#[tokio::test]
+async fn callback_returns_profile() -> anyhow::Result<()> {
+    let (idp, ctl_oidc) = idp().await?;
+
+    let profile = sign_in(&idp, &ctl_oidc).await?;
+    let user = MockUser::default();
+    assert_eq!(
+        profile,
+        OidcProfile {
+            provider: PROVIDER.into(),
+            subject: user.sub,
+            email: user.email,
+            email_verified: true,
+            name: user.name,
+        }
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn authorize_url_uses_pkce() -> anyhow::Result<()> {
+    let (idp, ctl_oidc) = idp().await?;
+    let (authorize_url, pending) = ctl_oidc.authorize(PROVIDER, Some(7)).await?;
+
+    assert!(authorize_url.starts_with(&format!("{}/authorize?", idp.issuer)));
+    assert!(authorize_url.contains("code_challenge_method=S256"));
+    assert!(!authorize_url.contains(&pending.code_verifier));
+    assert_eq!(pending.link_sub, Some(7));
+    Ok(())
+}
+
+#[tokio::test]
+async fn state_must_match() -> anyhow::Result<()> {
+    let (idp, ctl_oidc) = idp().await?;
+    let (authorize_url, pending) = ctl_oidc.authorize(PROVIDER, None).await?;
+    let (code, _) = idp.sign_in(&authorize_url).await?;
+
+    let result = ctl_oidc.callback(&pending, "forged-state", &code).await;
+    assert_eq!(result, Err(ErrorOidc::StateMismatch));
+    Ok(())
+}
+
+#[tokio::test]
+async fn code_verifier_must_match() -> anyhow::Result<()> {
+    let (idp, ctl_oidc) = idp().await?;
+    let (authorize_url, mut pending) = ctl_oidc.authorize(PROVIDER, None).await?;
+    let (code, state) = idp.sign_in(&authorize_url).await?;
+
+    // A code intercepted on its way back is useless without the verifier
+    pending.code_verifier = "stolen-code-without-the-verifier".into();
+    let result = ctl_oidc.callback(&pending, &state, &code).await;
+    assert!(matches!(result, Err(ErrorOidc::Provider(_))), "{result:?}");
+    Ok(())
+}
+
+#[tokio::test]
+async fn code_is_single_use() -> anyhow::Result<()> {
+    let (idp, ctl_oidc) = idp().await?;
+    let (authorize_url, pending) = ctl_oidc.authorize(PROVIDER, None).await?;
+    let (code, state) = idp.sign_in(&authorize_url).await?;
+
+    ctl_oidc.callback(&pending, &state, &code).await?;
+    let result = ctl_oidc.callback(&pending, &state, &code).await;
+    assert!(matches!(result, Err(ErrorOidc::Provider(_))), "{result:?}");
+    Ok(())
+}
+
+#[tokio::test]
+async fn tampered_id_tokens_are_rejected() -> anyhow::Result<()> {
+    let (idp, ctl_oidc) = idp().await?;
+
+    for tamper in [
+        Tamper {
+            nonce: Some("replayed-nonce".into()),
+            ..Default::default()
+        },
+        Tamper {
+            audience: Some("another-client".into()),
+            ..Default::default()
+        },
+        Tamper {
+            issuer: Some("https://evil.example.com".into()),
+            ..Default::default()
+        },
+        Tamper {
+            expired: true,
+            ..Default::default()
+        },
+    ] {
+        idp.tamper(tamper.clone());
+        let result = sign_in(&idp, &ctl_oidc).await;
+        assert!(
+            matches!(result, Err(ErrorOidc::IdToken(_))),
+            "{tamper:?} gave {result:?}"
+        );
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn discovery_must_match_issuer() -> anyhow::Result<()> {
+    let (idp, ctl_oidc) = idp().await?;
+    idp.tamper(Tamper {
+        discovery_issuer: Some("https://evil.example.com".into()),
+        ..Default::default()
+    });
+
+    let result = ctl_oidc.authorize(PROVIDER, None).await;
+    assert!(matches!(result, Err(ErrorOidc::Discovery(_))), "{result:?}");
+    Ok(())
+}
+
+#[tokio::test]
+async fn unknown_provider() -> anyhow::Result<()> {
+    let (_idp, ctl_oidc) = idp().await?;
+    let result = ctl_oidc.authorize("nope", None).await;
+    assert_eq!(result, Err(ErrorOidc::UnknownProvider("nope".into())));
+    Ok(())
+}
+
+#[tokio::test]
+async fn login_registers_then_signs_in() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let (idp, ctl_oidc) = idp().await?;
+
+    let profile = sign_in(&idp, &ctl_oidc).await?;
+    let user = identity::login(db.conn()?, &profile).await?;
+    assert_eq!(user.email, "jane@example.com");
+    assert_eq!(user.display_name, "Jane Doe");
+    assert!(user.email_verified_at.is_some());
+    assert!(!user.has_password());
+
+    // Signing in again finds the same user
+    let profile = sign_in(&idp, &ctl_oidc).await?;
+    let again = identity::login(db.conn()?, &profile).await?;
+    assert_eq!(again.id, user.id);
+
+    let identities = identity::list(db.conn()?, user.id).await?;
+    assert_eq!(identities.len(), 1);
+    assert_eq!(identities[0].provider, PROVIDER);
+    assert!(identities[0].last_login_at.is_some());
+    Ok(())
+}
+
+#[tokio::test]
+async fn login_needs_an_email() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let (idp, ctl_oidc) = idp().await?;
+    idp.set_user(MockUser {
+        email: None,
+        ..Default::default()
+    });
+
+    let profile = sign_in(&idp, &ctl_oidc).await?;
+    let result = identity::login(db.conn()?, &profile).await;
+    assert_eq!(result, Err(ErrorIdentity::EmailRequired));
+    Ok(())
+}
+
+#[tokio::test]
+async fn login_needs_a_verified_email() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let (idp, ctl_oidc) = idp().await?;
+    let bob = new_user(&db, "bob").await?;
+
+    for email in ["jane@example.com", &bob.email] {
+        idp.set_user(MockUser {
+            email: Some(email.into()),
+            email_verified: false,
+            ..Default::default()
+        });
+        let profile = sign_in(&idp, &ctl_oidc).await?;
+        let result = identity::login(db.conn()?, &profile).await;
+        assert_eq!(result, Err(ErrorIdentity::EmailUnverified));
+    }
+
+    // Neither a new account nor a link was made
+    assert!(user::get_by_email(db.conn()?, "jane@example.com")
+        .await
+        .is_err());
+    assert!(identity::list(db.conn()?, bob.id).await?.is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn existing_email_is_not_taken_over() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let (idp, ctl_oidc) = idp().await?;
+    let bob = new_user(&db, "bob").await?;
+    idp.set_user(MockUser {
+        email: Some(bob.email.clone()),
+        ..Default::default()
+    });
+
+    let profile = sign_in(&idp, &ctl_oidc).await?;
+    let result = identity::login(db.conn()?, &profile).await;
+    assert_eq!(result, Err(ErrorIdentity::EmailInUse));
+
+    // Once bob links the provider himself, it signs him in
+    identity::link(db.conn()?, bob.id, &profile).await?;
+    let user = identity::login(db.conn()?, &profile).await?;
+    assert_eq!(user.id, bob.id);
+
+    // and his password still works
+    user::login(db.conn()?, &bob.email, PASSWORD).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn identity_links_to_one_user() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let (idp, ctl_oidc) = idp().await?;
+    let bob = new_user(&db, "bob").await?;
+    let alice = new_user(&db, "alice").await?;
+
+    let profile = sign_in(&idp, &ctl_oidc).await?;
+    identity::link(db.conn()?, bob.id, &profile).await?;
+    // Linking again is harmless
+    identity::link(db.conn()?, bob.id, &profile).await?;
+
+    let result = identity::link(db.conn()?, alice.id, &profile).await;
+    assert_eq!(result, Err(ErrorIdentity::IdentityInUse(PROVIDER.into())));
+
+    // A second account at the same provider
+    idp.set_user(MockUser {
+        sub: "another-subject".into(),
+        ..Default::default()
+    });
+    let profile = sign_in(&idp, &ctl_oidc).await?;
+    let result = identity::link(db.conn()?, bob.id, &profile).await;
+    assert_eq!(
+        result,
+        Err(ErrorIdentity::ProviderAlreadyLinked(PROVIDER.into()))
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn last_sign_in_method_cannot_be_unlinked() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let (idp, ctl_oidc) = idp().await?;
+
+    let profile = sign_in(&idp, &ctl_oidc).await?;
+    let user = identity::login(db.conn()?, &profile).await?;
+    let linked = identity::list(db.conn()?, user.id).await?;
+
+    let result = identity::unlink(db.conn()?, user.id, linked[0].id).await;
+    assert_eq!(result, Err(ErrorIdentity::LastSignInMethod));
+
+    let result = identity::unlink(db.conn()?, user.id, linked[0].id + 1).await;
+    assert_eq!(result, Err(ErrorIdentity::NotFound));
+    Ok(())
+}
+
+#[tokio::test]
+async fn unlink_with_password() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let (idp, ctl_oidc) = idp().await?;
+    let bob = new_user(&db, "bob").await?;
+
+    let profile = sign_in(&idp, &ctl_oidc).await?;
+    let linked = identity::link(db.conn()?, bob.id, &profile).await?;
+    identity::unlink(db.conn()?, bob.id, linked.id).await?;
+
+    assert!(identity::list(db.conn()?, bob.id).await?.is_empty());
+    Ok(())
+}
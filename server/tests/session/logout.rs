@@ -0,0 +1,35 @@
+use rustwebapp::{
+    model::session::{self, ErrorSession, SessionClient},
+    service::{jwt::Claims, revocation::RevocationController},
+};
+
+use crate::shared::{db::TestDb, user::new_user};
+
+#[tokio::test]
+async fn revoked_session_cannot_refresh() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let user = new_user(&db, "bob").await?;
+
+    let (created, token) = session::create(db.conn()?, user.id, &SessionClient::default()).await?;
+    session::revoke(db.conn()?, created.id).await?;
+
+    let result = session::refresh(db.conn()?, &token).await;
+    assert_eq!(result, Err(ErrorSession::Revoked));
+    Ok(())
+}
+
+#[tokio::test]
+async fn revocations_survive_restart() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let claims = Claims::new(1, "bob", "bob@contoso.com");
+
+    let ctl = RevocationController::new(db.pool.clone()).await?;
+    assert!(!ctl.is_revoked(&claims.jti));
+    ctl.revoke(claims.jti, claims.exp).await?;
+    assert!(ctl.is_revoked(&claims.jti));
+
+    // A fresh controller (e.g. after a deploy, or on another machine) loads it from Postgres
+    let restarted = RevocationController::new(db.pool.clone()).await?;
+    assert!(restarted.is_revoked(&claims.jti));
+    Ok(())
+}
@@ -0,0 +1,93 @@
+use std::net::{IpAddr, Ipv4Addr};
+
+use rustwebapp::model::session::{self, ErrorSession, SessionClient};
+
+use crate::shared::{db::TestDb, user::new_user};
+
+fn laptop() -> SessionClient {
+    SessionClient {
+        user_agent: Some("Mozilla/5.0 (X11; Linux x86_64) Firefox/131.0".into()),
+        ip: Some(IpAddr::V4(Ipv4Addr::new(203, 0, 113, 7))),
+    }
+}
+
+#[tokio::test]
+async fn session_records_its_client() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let user_id = new_user(&db, "bob").await?.id;
+
+    let (created, _) = session::create(db.conn()?, user_id, &laptop()).await?;
+    assert_eq!(created.user_agent, laptop().user_agent);
+    assert_eq!(created.ip.as_deref(), Some("203.0.113.7"));
+    assert!(created.last_seen_at >= created.created_at);
+
+    let sessions = session::list_active(db.conn()?, user_id).await?;
+    assert_eq!(sessions, vec![created]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn long_user_agents_are_cut() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let user_id = new_user(&db, "bob").await?.id;
+    let client = SessionClient {
+        user_agent: Some("a".repeat(600)),
+        ip: None,
+    };
+
+    let (created, _) = session::create(db.conn()?, user_id, &client).await?;
+    assert_eq!(created.user_agent.unwrap().chars().count(), 512);
+    Ok(())
+}
+
+#[tokio::test]
+async fn revoked_sessions_are_not_listed_or_touched() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let user_id = new_user(&db, "bob").await?.id;
+    let (laptop, _) = session::create(db.conn()?, user_id, &laptop()).await?;
+    let (phone, _) = session::create(db.conn()?, user_id, &SessionClient::default()).await?;
+
+    session::touch(db.conn()?, phone.id).await?;
+    session::revoke_owned(db.conn()?, user_id, phone.id).await?;
+
+    let sessions = session::list_active(db.conn()?, user_id).await?;
+    assert_eq!(sessions, vec![laptop]);
+
+    let result = session::touch(db.conn()?, phone.id).await;
+    assert_eq!(result, Err(ErrorSession::Revoked));
+
+    // Already gone
+    let result = session::revoke_owned(db.conn()?, user_id, phone.id).await;
+    assert_eq!(result, Err(ErrorSession::NotFound));
+    Ok(())
+}
+
+#[tokio::test]
+async fn cannot_revoke_another_users_session() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let user_id = new_user(&db, "bob").await?.id;
+    let alice = new_user(&db, "alice").await?;
+    let (created, _) = session::create(db.conn()?, user_id, &laptop()).await?;
+
+    let result = session::revoke_owned(db.conn()?, alice.id, created.id).await;
+    assert_eq!(result, Err(ErrorSession::NotFound));
+    session::touch(db.conn()?, created.id).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn sign_out_everywhere_else() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let user_id = new_user(&db, "bob").await?.id;
+    let (current, _) = session::create(db.conn()?, user_id, &laptop()).await?;
+    for _ in 0..2 {
+        session::create(db.conn()?, user_id, &SessionClient::default()).await?;
+    }
+
+    let revoked = session::revoke_for_user(db.conn()?, user_id, Some(current.id)).await?;
+    assert_eq!(revoked, 2);
+
+    let sessions = session::list_active(db.conn()?, user_id).await?;
+    assert_eq!(sessions, vec![current]);
+    Ok(())
+}
@@ -0,0 +1,3 @@
+mod logout;
+mod manage;
+mod refresh;
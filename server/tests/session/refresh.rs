@@ -0,0 +1,144 @@
+use axum::{
+    body::Body,
+    http::{header, HeaderMap, Method, Request, StatusCode},
+};
+use rustwebapp::{
+    model::session::{self, ErrorSession, SessionClient},
+    web::{AUTH_HEADER, REFRESH_COOKIE},
+};
+use serde_json::{json, Value};
+
+use crate::shared::{
+    app::App,
+    db::TestDb,
+    user::{new_user, PASSWORD},
+};
+
+#[tokio::test]
+async fn refresh_rotates_token() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let user_id = new_user(&db, "bob").await?.id;
+
+    let (created, token) = session::create(db.conn()?, user_id, &SessionClient::default()).await?;
+    let (refreshed, new_token) = session::refresh(db.conn()?, &token).await?;
+
+    assert_eq!(refreshed.id, created.id);
+    assert_eq!(refreshed.user_id, user_id);
+    assert_ne!(new_token, token);
+    assert!(refreshed.expires_at >= created.expires_at);
+
+    // The new token keeps working
+    session::refresh(db.conn()?, &new_token).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn unknown_token_is_invalid() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+
+    let result = session::refresh(db.conn()?, "not-a-token").await;
+    assert_eq!(result, Err(ErrorSession::InvalidToken));
+    Ok(())
+}
+
+#[tokio::test]
+async fn reused_token_revokes_session() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let user_id = new_user(&db, "bob").await?.id;
+
+    let (created, token) = session::create(db.conn()?, user_id, &SessionClient::default()).await?;
+    let (_, new_token) = session::refresh(db.conn()?, &token).await?;
+
+    // Replaying the first token is treated as theft
+    let result = session::refresh(db.conn()?, &token).await;
+    assert_eq!(
+        result,
+        Err(ErrorSession::Reused {
+            user_id,
+            session_id: created.id
+        })
+    );
+
+    let revoked = session::get_by_id(db.conn()?, created.id).await?;
+    assert!(revoked.revoked_at.is_some());
+
+    // Which also kills the legitimate token from the same family
+    let result = session::refresh(db.conn()?, &new_token).await;
+    assert_eq!(result, Err(ErrorSession::Revoked));
+    Ok(())
+}
+
+#[tokio::test]
+async fn sessions_are_independent() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let user_id = new_user(&db, "bob").await?.id;
+
+    let (a, token_a) = session::create(db.conn()?, user_id, &SessionClient::default()).await?;
+    let (_, token_b) = session::create(db.conn()?, user_id, &SessionClient::default()).await?;
+
+    session::refresh(db.conn()?, &token_a).await?;
+    let result = session::refresh(db.conn()?, &token_a).await;
+    assert_eq!(
+        result,
+        Err(ErrorSession::Reused {
+            user_id,
+            session_id: a.id
+        })
+    );
+
+    // Revoking one login does not affect another
+    session::refresh(db.conn()?, &token_b).await?;
+    Ok(())
+}
+
+/// Whether a response hands over session cookies
+fn sets_session_cookies(headers: &HeaderMap) -> bool {
+    headers.get_all(header::SET_COOKIE).iter().any(|cookie| {
+        let cookie = cookie.to_str().unwrap_or_default();
+        cookie.starts_with(AUTH_HEADER) || cookie.starts_with(REFRESH_COOKIE)
+    })
+}
+
+/// A POST with a JSON body and no credentials
+fn post(uri: &str, body: Value) -> anyhow::Result<Request<Body>> {
+    Ok(Request::builder()
+        .method(Method::POST)
+        .uri(uri)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))?)
+}
+
+#[tokio::test]
+async fn token_login_can_be_refreshed_without_cookies() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let app = App::new(&db).await?;
+    let user = new_user(&db, "bob").await?;
+
+    let login = json!({ "email": user.email, "password": PASSWORD });
+    let (status, headers, body) = app.send_request(post("/api/login/token", login)?).await?;
+    assert_eq!(status, StatusCode::OK, "{body}");
+    assert!(!sets_session_cookies(&headers));
+    let refresh_token = body["refresh_token"].as_str().unwrap().to_string();
+
+    let refresh = json!({ "refresh_token": refresh_token });
+    let (status, headers, body) = app
+        .send_request(post("/api/token/refresh", refresh.clone())?)
+        .await?;
+    assert_eq!(status, StatusCode::OK, "{body}");
+    assert!(!sets_session_cookies(&headers));
+    assert_ne!(body["refresh_token"], refresh["refresh_token"]);
+
+    let access_token = body["access_token"].as_str().unwrap();
+    let (status, me) = app
+        .send(Method::GET, "/api/account/me", access_token, json!({}))
+        .await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(me["id"], user.id);
+
+    // Refresh tokens are single use, in the body as in the cookie
+    let (status, _, _) = app
+        .send_request(post("/api/token/refresh", refresh)?)
+        .await?;
+    assert!(status.is_client_error());
+    Ok(())
+}
@@ -0,0 +1,100 @@
+use axum::{
+    body::{to_bytes, Body},
+    http::{header, request, HeaderMap, Method, Request, StatusCode},
+    Router,
+};
+use rustwebapp::{
+    model::{
+        role::Role,
+        user::{self, User},
+    },
+    service::jwt::Claims,
+    web::{app_state::AppState, routes},
+};
+use serde_json::Value;
+use tower::ServiceExt;
+
+use crate::shared::{db::TestDb, user::new_user};
+
+/// The whole app, middleware and all, with a user signed in as an admin
+pub struct App {
+    pub router: Router,
+    pub app_state: AppState,
+    pub admin: User,
+}
+
+impl App {
+    pub async fn new(db: &TestDb) -> anyhow::Result<Self> {
+        Self::with_state(db, |_| {}).await
+    }
+
+    /// The app with its state changed from the defaults, e.g. to trust a client IP header
+    /// or to swap the mailer
+    pub async fn with_state(
+        db: &TestDb,
+        configure: impl FnOnce(&mut AppState),
+    ) -> anyhow::Result<Self> {
+        let mut app_state = AppState::new(db.pool.clone()).await?;
+        configure(&mut app_state);
+        let router = routes::get_app(Router::new(), app_state.clone()).await?;
+        let admin = new_user(db, "admin").await?;
+        let admin = user::set_role(db.conn()?, admin.id, Role::Admin).await?;
+        Ok(Self {
+            router,
+            app_state,
+            admin,
+        })
+    }
+
+    /// A bearer token for `user`, as if they had just signed in
+    pub fn token(&self, user: &User) -> anyhow::Result<String> {
+        self.app_state
+            .ctl_jwt
+            .sign(&Claims::from(user))
+            .map_err(anyhow::Error::msg)
+    }
+
+    pub async fn send(
+        &self,
+        method: Method,
+        uri: &str,
+        token: &str,
+        body: Value,
+    ) -> anyhow::Result<(StatusCode, Value)> {
+        let (status, _, body) = self.send_with_headers(method, uri, token, body).await?;
+        Ok((status, body))
+    }
+
+    /// [`App::send`], also handing back the response headers
+    pub async fn send_with_headers(
+        &self,
+        method: Method,
+        uri: &str,
+        token: &str,
+        body: Value,
+    ) -> anyhow::Result<(StatusCode, HeaderMap, Value)> {
+        let req = Self::request(method, uri, token).body(Body::from(body.to_string()))?;
+        self.send_request(req).await
+    }
+
+    /// A JSON request as `token`'s user, to add headers to before [`App::send_request`]
+    pub fn request(method: Method, uri: &str, token: &str) -> request::Builder {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::AUTHORIZATION, format!("Bearer {token}"))
+            .header(header::CONTENT_TYPE, "application/json")
+    }
+
+    pub async fn send_request(
+        &self,
+        req: Request<Body>,
+    ) -> anyhow::Result<(StatusCode, HeaderMap, Value)> {
+        let res = self.router.clone().oneshot(req).await?;
+        let status = res.status();
+        let headers = res.headers().clone();
+        let body = to_bytes(res.into_body(), usize::MAX).await?;
+        let body = serde_json::from_slice(&body).unwrap_or(Value::Null);
+        Ok((status, headers, body))
+    }
+}
@@ -0,0 +1,282 @@
+//! An OpenID Connect provider running in-process, just enough of one to sign users in
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Redirect, Response},
+    routing::{get, post},
+    Form, Json, Router,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{EncodingKey, Header};
+use lazy_static::lazy_static;
+use rsa::{pkcs1::EncodeRsaPrivateKey, pkcs8::LineEnding, traits::PublicKeyParts, RsaPrivateKey};
+use rustwebapp::service::oidc::{pkce_challenge, OidcProvider};
+use serde_json::{json, Value};
+
+pub const PROVIDER: &str = "mock";
+pub const CLIENT_ID: &str = "rustwebapp";
+pub const CLIENT_SECRET: &str = "mock-secret";
+pub const REDIRECT_URI: &str = "http://localhost:5173/api/oidc/mock/callback";
+const KID: &str = "mock-key";
+
+lazy_static! {
+    static ref KEY: RsaPrivateKey =
+        RsaPrivateKey::new(&mut rand::thread_rng(), 2048).expect("Failed to generate IdP key");
+}
+
+/// The account signed in at the provider
+#[derive(Debug, Clone)]
+pub struct MockUser {
+    pub sub: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub name: Option<String>,
+}
+
+impl Default for MockUser {
+    fn default() -> Self {
+        Self {
+            sub: "248289761001".into(),
+            email: Some("jane@example.com".into()),
+            email_verified: true,
+            name: Some("Jane Doe".into()),
+        }
+    }
+}
+
+/// Ways for the provider to misbehave
+#[derive(Debug, Clone, Default)]
+pub struct Tamper {
+    pub nonce: Option<String>,
+    pub audience: Option<String>,
+    pub issuer: Option<String>,
+    pub discovery_issuer: Option<String>,
+    pub expired: bool,
+}
+
+#[derive(Debug)]
+struct AuthRequest {
+    nonce: String,
+    code_challenge: String,
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    issuer: String,
+    user: MockUser,
+    tamper: Tamper,
+    codes: HashMap<String, AuthRequest>,
+}
+
+type SharedState = Arc<Mutex<MockState>>;
+
+pub struct MockIdp {
+    pub issuer: String,
+    state: SharedState,
+    http: reqwest::Client,
+}
+
+impl MockIdp {
+    pub async fn start() -> anyhow::Result<Self> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let issuer = format!("http://{}", listener.local_addr()?);
+        let state = SharedState::new(Mutex::new(MockState {
+            issuer: issuer.clone(),
+            ..Default::default()
+        }));
+
+        let app = Router::new()
+            .route("/.well-known/openid-configuration", get(discovery))
+            .route("/jwks", get(jwks))
+            .route("/authorize", get(authorize))
+            .route("/token", post(token))
+            .with_state(state.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        Ok(Self {
+            issuer,
+            state,
+            http: reqwest::Client::builder()
+                .redirect(reqwest::redirect::Policy::none())
+                .build()?,
+        })
+    }
+
+    /// Our client at this provider
+    pub fn provider(&self) -> OidcProvider {
+        OidcProvider {
+            name: PROVIDER.into(),
+            issuer: self.issuer.clone(),
+            client_id: CLIENT_ID.into(),
+            client_secret: Some(CLIENT_SECRET.into()),
+            redirect_uri: REDIRECT_URI.into(),
+        }
+    }
+
+    pub fn set_user(&self, user: MockUser) {
+        self.state.lock().unwrap().user = user;
+    }
+
+    pub fn tamper(&self, tamper: Tamper) {
+        self.state.lock().unwrap().tamper = tamper;
+    }
+
+    /// Follow the authorization url like a browser already signed in at the provider, returning
+    /// the code and state it is redirected back with
+    pub async fn sign_in(&self, authorize_url: &str) -> anyhow::Result<(String, String)> {
+        let response = self.http.get(authorize_url).send().await?;
+        anyhow::ensure!(
+            response.status().is_redirection(),
+            "Authorize failed: {}",
+            response.status()
+        );
+
+        let location = response
+            .headers()
+            .get("location")
+            .and_then(|l| l.to_str().ok())
+            .ok_or_else(|| anyhow::anyhow!("Authorize did not redirect"))?;
+        let location = reqwest::Url::parse(location)?;
+        anyhow::ensure!(location.as_str().starts_with(REDIRECT_URI));
+
+        let params: HashMap<_, _> = location.query_pairs().into_owned().collect();
+        Ok((params["code"].clone(), params["state"].clone()))
+    }
+}
+
+async fn discovery(State(state): State<SharedState>) -> Json<Value> {
+    let state = state.lock().unwrap();
+    let issuer = &state.issuer;
+    Json(json!({
+        "issuer": state.tamper.discovery_issuer.as_ref().unwrap_or(issuer),
+        "authorization_endpoint": format!("{issuer}/authorize"),
+        "token_endpoint": format!("{issuer}/token"),
+        "jwks_uri": format!("{issuer}/jwks"),
+        "response_types_supported": ["code"],
+        "subject_types_supported": ["public"],
+        "id_token_signing_alg_values_supported": ["RS256"],
+        "code_challenge_methods_supported": ["S256"],
+    }))
+}
+
+async fn jwks() -> Json<Value> {
+    Json(json!({
+        "keys": [{
+            "kty": "RSA",
+            "use": "sig",
+            "alg": "RS256",
+            "kid": KID,
+            "n": URL_SAFE_NO_PAD.encode(KEY.n().to_bytes_be()),
+            "e": URL_SAFE_NO_PAD.encode(KEY.e().to_bytes_be()),
+        }]
+    }))
+}
+
+async fn authorize(
+    State(state): State<SharedState>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {
+    let param = |key: &str| params.get(key).map(String::as_str);
+    if param("response_type") != Some("code")
+        || param("client_id") != Some(CLIENT_ID)
+        || param("redirect_uri") != Some(REDIRECT_URI)
+        || param("code_challenge_method") != Some("S256")
+        || !param("scope").is_some_and(|scope| scope.split(' ').any(|s| s == "openid"))
+    {
+        return (StatusCode::BAD_REQUEST, "invalid_request").into_response();
+    }
+    let (Some(nonce), Some(code_challenge), Some(client_state)) =
+        (param("nonce"), param("code_challenge"), param("state"))
+    else {
+        return (StatusCode::BAD_REQUEST, "invalid_request").into_response();
+    };
+
+    let code = uuid::Uuid::new_v4().to_string();
+    state.lock().unwrap().codes.insert(
+        code.clone(),
+        AuthRequest {
+            nonce: nonce.into(),
+            code_challenge: code_challenge.into(),
+        },
+    );
+
+    Redirect::to(&format!(
+        "{REDIRECT_URI}?code={code}&state={}",
+        urlencoding::encode(client_state)
+    ))
+    .into_response()
+}
+
+async fn token(
+    State(state): State<SharedState>,
+    Form(form): Form<HashMap<String, String>>,
+) -> Response {
+    let invalid_grant = |description: &str| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": "invalid_grant", "error_description": description })),
+        )
+            .into_response()
+    };
+    let param = |key: &str| form.get(key).map(String::as_str);
+
+    if param("grant_type") != Some("authorization_code")
+        || param("client_id") != Some(CLIENT_ID)
+        || param("client_secret") != Some(CLIENT_SECRET)
+        || param("redirect_uri") != Some(REDIRECT_URI)
+    {
+        return invalid_grant("bad client or redirect");
+    }
+
+    let mut state = state.lock().unwrap();
+    // Codes are single use
+    let Some(request) = param("code").and_then(|code| state.codes.remove(code)) else {
+        return invalid_grant("unknown code");
+    };
+    let verified = param("code_verifier")
+        .is_some_and(|verifier| pkce_challenge(verifier) == request.code_challenge);
+    if !verified {
+        return invalid_grant("code_verifier does not match");
+    }
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let exp = match state.tamper.expired {
+        true => now - 600,
+        false => now + 600,
+    };
+    let claims = json!({
+        "iss": state.tamper.issuer.as_ref().unwrap_or(&state.issuer),
+        "sub": state.user.sub,
+        "aud": state.tamper.audience.as_deref().unwrap_or(CLIENT_ID),
+        "iat": now,
+        "exp": exp,
+        "nonce": state.tamper.nonce.as_ref().unwrap_or(&request.nonce),
+        "email": state.user.email,
+        "email_verified": state.user.email_verified,
+        "name": state.user.name,
+    });
+
+    let mut header = Header::new(jsonwebtoken::Algorithm::RS256);
+    header.kid = Some(KID.into());
+    let pem = KEY.to_pkcs1_pem(LineEnding::LF).unwrap();
+    let key = EncodingKey::from_rsa_pem(pem.as_bytes()).unwrap();
+    let id_token = jsonwebtoken::encode(&header, &claims, &key).unwrap();
+
+    Json(json!({
+        "access_token": "mock-access-token",
+        "token_type": "Bearer",
+        "expires_in": 600,
+        "id_token": id_token,
+    }))
+    .into_response()
+}
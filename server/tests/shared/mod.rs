@@ -1,2 +1,5 @@
+pub mod app;
 pub mod db;
+pub mod mock_idp;
 pub mod time;
+pub mod user;
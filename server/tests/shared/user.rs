@@ -0,0 +1,18 @@
+//! Users for tests to act as
+
+use rustwebapp::model::user::{create, User, UserNewFields};
+
+use crate::shared::db::TestDb;
+
+/// The password every [`new_user`] signs in with
+pub const PASSWORD: &str = "violet-harbor-tuesday-42";
+
+/// Create a user called `name`, signing in as `<name>@contoso.com` with [`PASSWORD`]
+pub async fn new_user(db: &TestDb, name: &str) -> anyhow::Result<User> {
+    let fields = UserNewFields {
+        display_name: name.into(),
+        email: format!("{name}@contoso.com"),
+        password: PASSWORD.into(),
+    };
+    Ok(create(db.conn()?, fields).await?)
+}
@@ -0,0 +1,59 @@
+use std::net::{IpAddr, Ipv4Addr};
+
+use rustwebapp::service::throttle::{ThrottleController, ThrottleKey, ThrottlePolicy};
+
+use crate::shared::db::TestDb;
+
+fn keys() -> Vec<ThrottleKey> {
+    vec![
+        ThrottleKey::Email("Bob@Contoso.com".into()),
+        ThrottleKey::Ip(IpAddr::V4(Ipv4Addr::new(203, 0, 113, 7))),
+    ]
+}
+
+#[tokio::test]
+async fn lockout_survives_restart() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let policy = ThrottlePolicy::default();
+
+    let ctl = ThrottleController::new(policy, Some(db.pool.clone())).await?;
+    assert!(ctl.check(&keys()).is_ok());
+    for _ in 0..policy.lockout_after {
+        ctl.record_failure(&keys()).await?;
+    }
+    let wait = ctl.check(&keys()).expect_err("Should be locked out");
+    assert!(wait > policy.max_delay);
+
+    let lockouts = ctl.lockouts()?;
+    assert_eq!(lockouts.len(), 2);
+    assert!(lockouts.iter().all(|l| l.locked_out));
+
+    // A fresh controller (e.g. after a deploy) loads the counters from Postgres
+    let restarted = ThrottleController::new(policy, Some(db.pool.clone())).await?;
+    assert!(restarted.check(&keys()).is_err());
+
+    // Clearing the account leaves the address locked out
+    restarted
+        .clear(&ThrottleKey::Email("bob@contoso.com".into()))
+        .await?;
+    let restarted = ThrottleController::new(policy, Some(db.pool.clone())).await?;
+    assert!(restarted.check(&keys()[..1]).is_ok());
+    assert!(restarted.check(&keys()).is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn memory_store_is_not_persisted() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let policy = ThrottlePolicy::default();
+
+    let ctl = ThrottleController::new(policy, None).await?;
+    for _ in 0..policy.lockout_after {
+        ctl.record_failure(&keys()).await?;
+    }
+    assert!(ctl.check(&keys()).is_err());
+
+    let persisted = ThrottleController::new(policy, Some(db.pool.clone())).await?;
+    assert!(persisted.check(&keys()).is_ok());
+    Ok(())
+}
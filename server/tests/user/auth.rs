@@ -1,9 +1,12 @@
+use diesel::prelude::*;
 use rustwebapp::{
-    model::user::{self, create, UserNewFields},
+    model::user::{self, create, User, UserNewFields},
+    schema::users,
     service::jwt::Claims,
 };
+use sha2::Digest;
 
-use crate::shared::db::TestDb;
+use crate::shared::{db::TestDb, user::PASSWORD};
 
 #[tokio::test]
 async fn login_new_user() -> anyhow::Result<()> {
@@ -11,16 +14,51 @@ async fn login_new_user() -> anyhow::Result<()> {
     let fields = UserNewFields {
         display_name: "bob".into(),
         email: "bob@gmail.com".into(),
-        password: "password1234".into(),
+        password: PASSWORD.into(),
     };
 
     // Create the user
     create(db.conn()?, fields).await?;
 
     // Login the user
-    let user_claims: Claims = user::login(db.conn()?, "bob@gmail.com", "password1234").await?;
+    let user_claims: Claims = user::login(db.conn()?, "bob@gmail.com", PASSWORD).await?;
     assert_eq!(user_claims.sub, 1);
     assert_eq!(user_claims.display_name, "bob");
     assert_eq!(user_claims.email, "bob@gmail.com");
     Ok(())
 }
+
+#[tokio::test]
+async fn login_upgrades_legacy_sha384_hash() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+
+    // A row as it was stored before Argon2id
+    let salt = "legacysaltlegacysaltlegacysalt12";
+    let legacy_hash = sha2::Sha384::digest(format!("{PASSWORD}{salt}").as_bytes()).to_vec();
+    diesel::insert_into(users::table)
+        .values((
+            users::display_name.eq("old timer"),
+            users::email.eq("old@contoso.com"),
+            users::password_salt.eq(salt),
+            users::password_hash.eq(legacy_hash),
+        ))
+        .execute(&mut db.conn()?)?;
+
+    let result = user::login(db.conn()?, "old@contoso.com", "wrong password").await;
+    assert!(result.is_err(), "Wrong password must not log in");
+
+    user::login(db.conn()?, "old@contoso.com", PASSWORD).await?;
+
+    let upgraded: User = users::table
+        .filter(users::email.eq("old@contoso.com"))
+        .get_result(&mut db.conn()?)?;
+    assert_eq!(upgraded.password_salt, None);
+    assert_eq!(upgraded.password_hash, None);
+    assert!(upgraded
+        .password_phc
+        .is_some_and(|phc| phc.starts_with("$argon2id$")));
+
+    // Logging in again now goes through Argon2id
+    user::login(db.conn()?, "old@contoso.com", PASSWORD).await?;
+    Ok(())
+}
@@ -2,8 +2,8 @@ use std::time::{Duration, SystemTime};
 
 use rustwebapp::model::user::{create, ErrorPassword, ErrorUser, UserNewFields};
 
-use crate::shared::db::TestDb;
 use crate::shared::time::assert_within;
+use crate::shared::{db::TestDb, user::PASSWORD};
 
 #[tokio::test]
 async fn create_user() -> anyhow::Result<()> {
@@ -11,7 +11,7 @@ async fn create_user() -> anyhow::Result<()> {
     let fields = UserNewFields {
         display_name: "john22".into(),
         email: "john@contoso.com".into(),
-        password: "password1234".into(),
+        password: PASSWORD.into(),
     };
 
     let now = SystemTime::now();
@@ -32,7 +32,7 @@ async fn create_user_with_existing_display_name() -> anyhow::Result<()> {
     let fields = UserNewFields {
         display_name: "Bob the Builder".into(),
         email: "bob@contoso.com".into(),
-        password: "password1234".into(),
+        password: PASSWORD.into(),
     };
 
     // Create the first user
@@ -42,7 +42,7 @@ async fn create_user_with_existing_display_name() -> anyhow::Result<()> {
     let fields = UserNewFields {
         display_name: "Bob the Builder".into(),
         email: "joe@contoso.com".into(),
-        password: "password1234".into(),
+        password: PASSWORD.into(),
     };
 
     let joe = create(db.conn()?, fields)
@@ -58,7 +58,7 @@ async fn create_user_with_existing_email() -> anyhow::Result<()> {
     let fields = UserNewFields {
         display_name: "bob".into(),
         email: "bob@contoso.com".into(),
-        password: "password1234".into(),
+        password: PASSWORD.into(),
     };
 
     // Create the first user
@@ -68,7 +68,7 @@ async fn create_user_with_existing_email() -> anyhow::Result<()> {
     let fields = UserNewFields {
         display_name: "joe".into(),
         email: "BoB@Contoso.com".into(),
-        password: "password1234".into(),
+        password: PASSWORD.into(),
     };
 
     let result = create(db.conn()?, fields).await;
@@ -82,7 +82,7 @@ async fn create_user_with_invalid_email() -> anyhow::Result<()> {
     let fields = UserNewFields {
         display_name: "bob".into(),
         email: "bob".into(),
-        password: "password1234".into(),
+        password: PASSWORD.into(),
     };
 
     let result = create(db.conn()?, fields).await;
@@ -100,6 +100,67 @@ async fn create_user_with_short_password() -> anyhow::Result<()> {
     };
 
     let result = create(db.conn()?, fields).await;
-    assert_eq!(result, Err(ErrorUser::Password(ErrorPassword::TooShort)));
+    assert_eq!(
+        result,
+        Err(ErrorUser::Password(ErrorPassword::TooShort(12)))
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn create_user_with_weak_password() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+
+    for (password, expected) in [
+        ("password1234", ErrorPassword::Common),
+        ("bob@contoso.com rocks", ErrorPassword::ContainsEmail),
+        ("the builder 2024!", ErrorPassword::ContainsDisplayName),
+    ] {
+        let fields = UserNewFields {
+            display_name: "The Builder".into(),
+            email: "bob@contoso.com".into(),
+            password: password.into(),
+        };
+        let result = create(db.conn()?, fields).await;
+        assert_eq!(result, Err(ErrorUser::Password(expected)), "{password}");
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn create_user_normalizes_display_name() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let fields = UserNewFields {
+        display_name: "  Ｂｏｂ   Smith ".into(),
+        email: "bob@contoso.com".into(),
+        password: PASSWORD.into(),
+    };
+
+    let user = create(db.conn()?, fields).await?;
+    assert_eq!(user.display_name, "Bob Smith");
+    Ok(())
+}
+
+#[tokio::test]
+async fn create_user_with_invalid_display_name() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+
+    for (display_name, expected) in [
+        ("al", ErrorUser::DisplayNameTooShort(3)),
+        (
+            "Bob the Builder of Everything Else",
+            ErrorUser::DisplayNameTooLong(32),
+        ),
+        ("<b>bob</b>", ErrorUser::DisplayNameInvalidCharacters),
+        ("sh1t_l0rd", ErrorUser::DisplayNameNotAllowed),
+    ] {
+        let fields = UserNewFields {
+            display_name: display_name.into(),
+            email: "bob@contoso.com".into(),
+            password: PASSWORD.into(),
+        };
+        let result = create(db.conn()?, fields).await;
+        assert_eq!(result, Err(expected), "{display_name}");
+    }
     Ok(())
 }
@@ -0,0 +1,303 @@
+use std::time::{Duration, SystemTime};
+
+use axum::http::{header, Method, StatusCode};
+use diesel::prelude::*;
+use rustwebapp::{
+    model::{
+        access_token::{self, AccessTokenNewFields, ErrorAccessToken},
+        account::{self, Reauth, DELETION_GRACE_PERIOD, REAUTH_WINDOW},
+        lobby::{ErrorLobby, LobbyActor, LobbyController, LobbyForCreate, Visibility},
+        mfa,
+        role::Role,
+        session::{self, ErrorSession, SessionClient},
+        user::{self, ErrorUser, User},
+    },
+    schema::{sessions, users},
+    service::{throttle::ThrottlePolicy, time},
+};
+use serde_json::json;
+
+use crate::shared::{
+    app::App,
+    db::TestDb,
+    user::{new_user, PASSWORD},
+};
+
+/// A user who only ever signs in through a provider
+async fn without_password(db: &TestDb) -> anyhow::Result<User> {
+    let user = new_user(db, "bob").await?;
+    diesel::update(users::table.find(user.id))
+        .set(users::password_phc.eq(None::<String>))
+        .execute(&mut db.conn()?)?;
+    Ok(user::get_by_id(db.conn()?, user.id).await?)
+}
+
+fn actor(user: &User) -> LobbyActor {
+    LobbyActor {
+        user_id: user.id,
+        role: Role::User,
+    }
+}
+
+fn lobby_fields(name: &str) -> LobbyForCreate {
+    LobbyForCreate {
+        name: name.into(),
+        visibility: Visibility::Public,
+        max_players: 4,
+    }
+}
+
+fn token_fields() -> AccessTokenNewFields {
+    AccessTokenNewFields {
+        name: "ci".into(),
+        scopes: vec!["lobby:read".into()],
+        expires_in_days: None,
+    }
+}
+
+#[tokio::test]
+async fn delete_needs_the_password() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let user = new_user(&db, "bob").await?;
+
+    let result = account::delete(db.conn()?, user.id, Reauth::Password("not my password")).await;
+    assert_eq!(result, Err(ErrorUser::WrongPassword));
+
+    let user = user::get_by_id(db.conn()?, user.id).await?;
+    assert!(user.deleted_at.is_none());
+    Ok(())
+}
+
+#[tokio::test]
+async fn delete_with_a_two_factor_code() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let user = without_password(&db).await?;
+    let totp = mfa::enroll(db.conn()?, user.id).await?;
+    let code = totp.code_for_step(totp.step_at(time::now_unix()?));
+    let recovery_codes = mfa::confirm(db.conn()?, user.id, &code).await?;
+
+    let result = account::delete(db.conn()?, user.id, Reauth::MfaCode("123456")).await;
+    assert_eq!(result, Err(ErrorUser::WrongCode));
+
+    account::delete(db.conn()?, user.id, Reauth::MfaCode(&recovery_codes[0])).await?;
+    let user = user::get_by_id(db.conn()?, user.id).await?;
+    assert!(user.deleted_at.is_some());
+    Ok(())
+}
+
+#[tokio::test]
+async fn two_factor_code_is_not_enough_with_a_password() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let user = new_user(&db, "bob").await?;
+    let totp = mfa::enroll(db.conn()?, user.id).await?;
+    let code = totp.code_for_step(totp.step_at(time::now_unix()?));
+    let recovery_codes = mfa::confirm(db.conn()?, user.id, &code).await?;
+
+    let result = account::delete(db.conn()?, user.id, Reauth::MfaCode(&recovery_codes[0])).await;
+    assert_eq!(result, Err(ErrorUser::ReauthRequired));
+
+    let user = user::get_by_id(db.conn()?, user.id).await?;
+    assert!(user.deleted_at.is_none());
+    Ok(())
+}
+
+#[tokio::test]
+async fn wrong_passwords_are_throttled() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let app = App::new(&db).await?;
+    let user = new_user(&db, "bob").await?;
+    let token = app.token(&user)?;
+    let wrong = json!({ "password": "not my password" });
+
+    let free_attempts = ThrottlePolicy::default().free_attempts;
+    for _ in 0..=free_attempts {
+        let (status, _) = app
+            .send(Method::DELETE, "/api/account/me", &token, wrong.clone())
+            .await?;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    // Even the right password has to wait now
+    let right = json!({ "password": PASSWORD });
+    let (status, headers, _) = app
+        .send_with_headers(Method::DELETE, "/api/account/me", &token, right)
+        .await?;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert!(headers.contains_key(header::RETRY_AFTER));
+
+    let user = user::get_by_id(db.conn()?, user.id).await?;
+    assert!(user.deleted_at.is_none());
+    Ok(())
+}
+
+#[tokio::test]
+async fn delete_without_password_after_signing_in() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let user = without_password(&db).await?;
+    let (stale, _) = session::create(db.conn()?, user.id, &SessionClient::default()).await?;
+    let (fresh, _) = session::create(db.conn()?, user.id, &SessionClient::default()).await?;
+
+    // Signed in too long ago
+    diesel::update(sessions::table.find(stale.id))
+        .set(sessions::created_at.eq(SystemTime::now() - REAUTH_WINDOW * 2))
+        .execute(&mut db.conn()?)?;
+    let result = account::delete(db.conn()?, user.id, Reauth::FreshSession(stale.id)).await;
+    assert_eq!(result, Err(ErrorUser::ReauthRequired));
+
+    account::delete(db.conn()?, user.id, Reauth::FreshSession(fresh.id)).await?;
+    let user = user::get_by_id(db.conn()?, user.id).await?;
+    assert!(user.deleted_at.is_some());
+    Ok(())
+}
+
+#[tokio::test]
+async fn password_is_needed_when_there_is_one() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let user = new_user(&db, "bob").await?;
+    let (fresh, _) = session::create(db.conn()?, user.id, &SessionClient::default()).await?;
+
+    let result = account::delete(db.conn()?, user.id, Reauth::FreshSession(fresh.id)).await;
+    assert_eq!(result, Err(ErrorUser::ReauthRequired));
+    Ok(())
+}
+
+#[tokio::test]
+async fn delete_signs_out_everywhere() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let user = new_user(&db, "bob").await?;
+    let (created, _) = session::create(db.conn()?, user.id, &SessionClient::default()).await?;
+    let (_, secret) = access_token::create(db.conn()?, user.id, token_fields()).await?;
+
+    let purge_after = account::delete(db.conn()?, user.id, Reauth::Password(PASSWORD)).await?;
+    assert!(purge_after > SystemTime::now() + DELETION_GRACE_PERIOD - Duration::from_secs(60));
+
+    let user = user::get_by_id(db.conn()?, user.id).await?;
+    assert!(user.deleted_at.is_some());
+    assert_eq!(
+        session::touch(db.conn()?, created.id).await,
+        Err(ErrorSession::Revoked)
+    );
+    assert_eq!(
+        access_token::authenticate(db.conn()?, &secret).await,
+        Err(ErrorAccessToken::InvalidToken)
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn restore_during_grace_period() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let user = new_user(&db, "bob").await?;
+    account::delete(db.conn()?, user.id, Reauth::Password(PASSWORD)).await?;
+
+    assert!(account::restore(db.conn()?, user.id).await?);
+    assert!(!account::restore(db.conn()?, user.id).await?);
+
+    // Nothing left to purge
+    let purged = account::purge_deleted(db.conn()?, SystemTime::now()).await?;
+    assert_eq!(purged, 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn purge_after_grace_period() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let user = new_user(&db, "bob").await?;
+    session::create(db.conn()?, user.id, &SessionClient::default()).await?;
+    account::delete(db.conn()?, user.id, Reauth::Password(PASSWORD)).await?;
+
+    // Still in the grace period
+    let before = SystemTime::now() - DELETION_GRACE_PERIOD;
+    assert_eq!(account::purge_deleted(db.conn()?, before).await?, 0);
+
+    assert_eq!(
+        account::purge_deleted(db.conn()?, SystemTime::now()).await?,
+        1
+    );
+    assert_eq!(
+        user::get_by_id(db.conn()?, user.id).await,
+        Err(ErrorUser::NotFound)
+    );
+    assert!(session::list_active(db.conn()?, user.id).await?.is_empty());
+
+    // The email address is free again
+    new_user(&db, "bob").await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn delete_leaves_lobbies() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let ctl_lobby = LobbyController::new(db.pool.clone());
+    let bob = new_user(&db, "bob").await?;
+    let alice = new_user(&db, "alice").await?;
+    let shared = ctl_lobby
+        .create_lobby(&actor(&bob), lobby_fields("Shared"))
+        .await?;
+    ctl_lobby.join_lobby(&actor(&alice), shared.id).await?;
+    let alone = ctl_lobby
+        .create_lobby(&actor(&bob), lobby_fields("Alone"))
+        .await?;
+
+    account::delete(db.conn()?, bob.id, Reauth::Password(PASSWORD)).await?;
+
+    let shared = ctl_lobby.get_lobby(&actor(&alice), shared.id).await?;
+    assert_eq!(shared.lobby.owner_id, alice.id);
+    assert_eq!(shared.members.len(), 1);
+    assert_eq!(
+        ctl_lobby.get_lobby(&actor(&alice), alone.id).await,
+        Err(ErrorLobby::NotFound)
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn purge_hands_over_lobbies() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let ctl_lobby = LobbyController::new(db.pool.clone());
+    let bob = new_user(&db, "bob").await?;
+    let alice = new_user(&db, "alice").await?;
+    let lobby = ctl_lobby
+        .create_lobby(&actor(&bob), lobby_fields("Shared"))
+        .await?;
+    ctl_lobby.join_lobby(&actor(&alice), lobby.id).await?;
+
+    // Deleted without going through account::delete, so still hosting
+    diesel::update(users::table.find(bob.id))
+        .set(users::deleted_at.eq(SystemTime::now()))
+        .execute(&mut db.conn()?)?;
+    assert_eq!(
+        account::purge_deleted(db.conn()?, SystemTime::now()).await?,
+        1
+    );
+
+    let lobby = ctl_lobby.get_lobby(&actor(&alice), lobby.id).await?;
+    assert_eq!(lobby.lobby.owner_id, alice.id);
+    assert_eq!(lobby.members.len(), 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn export_has_everything() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let user = new_user(&db, "bob").await?;
+    let (first, _) = session::create(db.conn()?, user.id, &SessionClient::default()).await?;
+    session::revoke(db.conn()?, first.id).await?;
+    session::create(db.conn()?, user.id, &SessionClient::default()).await?;
+    access_token::create(db.conn()?, user.id, token_fields()).await?;
+
+    let export = account::export(db.conn()?, user.id).await?;
+    assert_eq!(export.profile.email, "bob@contoso.com");
+    assert!(export.has_password);
+    assert!(!export.mfa_enabled);
+    assert_eq!(export.sessions.len(), 2);
+    assert!(export.sessions[0].revoked_at.is_some());
+    assert_eq!(export.access_tokens.len(), 1);
+    assert!(export.identities.is_empty());
+
+    // Secrets stay out of it
+    let json = serde_json::to_string(&export)?;
+    assert!(!json.contains("argon2"));
+    assert!(!json.contains("token_hash"));
+    Ok(())
+}
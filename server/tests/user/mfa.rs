@@ -0,0 +1,93 @@
+use rustwebapp::{
+    model::mfa::{self, ErrorMfa},
+    service::{time, totp::Totp},
+};
+
+use crate::shared::{db::TestDb, user::new_user};
+
+/// The code the authenticator app shows `steps` time steps from now
+fn code(totp: &Totp, steps: i64) -> String {
+    let now = totp.step_at(time::now_unix().unwrap());
+    totp.code_for_step(now.saturating_add_signed(steps))
+}
+
+#[tokio::test]
+async fn enroll_and_confirm() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let user = new_user(&db, "bob").await?;
+
+    let totp = mfa::enroll(db.conn()?, user.id).await?;
+    assert!(!mfa::is_enabled(db.conn()?, user.id).await?);
+
+    let result = mfa::confirm(db.conn()?, user.id, "000000x").await;
+    assert_eq!(result, Err(ErrorMfa::InvalidCode));
+
+    let recovery_codes = mfa::confirm(db.conn()?, user.id, &code(&totp, 0)).await?;
+    assert_eq!(recovery_codes.len(), 10);
+    assert!(mfa::is_enabled(db.conn()?, user.id).await?);
+
+    let result = mfa::enroll(db.conn()?, user.id).await;
+    assert_eq!(result.err(), Some(ErrorMfa::AlreadyEnabled));
+    Ok(())
+}
+
+#[tokio::test]
+async fn totp_code_cannot_be_replayed() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let user = new_user(&db, "bob").await?;
+    let totp = mfa::enroll(db.conn()?, user.id).await?;
+    mfa::confirm(db.conn()?, user.id, &code(&totp, -1)).await?;
+
+    let current = code(&totp, 0);
+    mfa::verify(db.conn()?, user.id, &current).await?;
+    let result = mfa::verify(db.conn()?, user.id, &current).await;
+    assert_eq!(result, Err(ErrorMfa::InvalidCode));
+
+    // An older code is no good once a newer one was used
+    let result = mfa::verify(db.conn()?, user.id, &code(&totp, -1)).await;
+    assert_eq!(result, Err(ErrorMfa::InvalidCode));
+    Ok(())
+}
+
+#[tokio::test]
+async fn recovery_codes_are_single_use() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let user = new_user(&db, "bob").await?;
+    let totp = mfa::enroll(db.conn()?, user.id).await?;
+    let recovery_codes = mfa::confirm(db.conn()?, user.id, &code(&totp, 0)).await?;
+
+    let shouted = recovery_codes[0].to_uppercase().replace('-', " ");
+    mfa::verify(db.conn()?, user.id, &shouted).await?;
+    let result = mfa::verify(db.conn()?, user.id, &recovery_codes[0]).await;
+    assert_eq!(result, Err(ErrorMfa::InvalidCode));
+
+    mfa::verify(db.conn()?, user.id, &recovery_codes[1]).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn unconfirmed_enrollment_is_not_enabled() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let user = new_user(&db, "bob").await?;
+    let totp = mfa::enroll(db.conn()?, user.id).await?;
+
+    let result = mfa::verify(db.conn()?, user.id, &code(&totp, 0)).await;
+    assert_eq!(result, Err(ErrorMfa::NotEnrolled));
+    Ok(())
+}
+
+#[tokio::test]
+async fn disable_needs_a_code() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let user = new_user(&db, "bob").await?;
+    let totp = mfa::enroll(db.conn()?, user.id).await?;
+    let recovery_codes = mfa::confirm(db.conn()?, user.id, &code(&totp, 0)).await?;
+
+    let result = mfa::disable(db.conn()?, user.id, "not-a-code").await;
+    assert_eq!(result, Err(ErrorMfa::InvalidCode));
+    assert!(mfa::is_enabled(db.conn()?, user.id).await?);
+
+    mfa::disable(db.conn()?, user.id, &recovery_codes[0]).await?;
+    assert!(!mfa::is_enabled(db.conn()?, user.id).await?);
+    Ok(())
+}
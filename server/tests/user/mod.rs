@@ -1,2 +1,8 @@
 mod auth;
 mod create;
+mod delete;
+mod mfa;
+mod password;
+mod reset;
+mod role;
+mod verify;
@@ -0,0 +1,70 @@
+use std::time::Duration;
+
+use rustwebapp::model::{
+    session::{self, SessionClient},
+    user::{self, ErrorPassword, ErrorUser},
+};
+
+use crate::shared::{
+    db::TestDb,
+    user::{new_user, PASSWORD},
+};
+
+#[tokio::test]
+async fn change_password() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let user = new_user(&db, "bob").await?;
+    tokio::time::sleep(Duration::from_millis(10)).await;
+
+    let changed =
+        user::change_password(db.conn()?, user.id, PASSWORD, "a brand new password").await?;
+    assert!(changed.updated_at > user.updated_at);
+    assert_ne!(changed.password_phc, user.password_phc);
+
+    assert!(user::login(db.conn()?, "bob@contoso.com", PASSWORD)
+        .await
+        .is_err());
+    user::login(db.conn()?, "bob@contoso.com", "a brand new password").await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn change_password_needs_current_password() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let user = new_user(&db, "bob").await?;
+
+    let result = user::change_password(
+        db.conn()?,
+        user.id,
+        "not my password",
+        "a brand new password",
+    )
+    .await;
+    assert_eq!(result, Err(ErrorUser::WrongPassword));
+
+    let result = user::change_password(db.conn()?, user.id, PASSWORD, "short").await;
+    assert_eq!(
+        result,
+        Err(ErrorUser::Password(ErrorPassword::TooShort(12)))
+    );
+
+    user::login(db.conn()?, "bob@contoso.com", PASSWORD).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn sign_out_other_sessions() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let user = new_user(&db, "bob").await?;
+    let (current, _) = session::create(db.conn()?, user.id, &SessionClient::default()).await?;
+    let (other, _) = session::create(db.conn()?, user.id, &SessionClient::default()).await?;
+
+    let revoked = session::revoke_for_user(db.conn()?, user.id, Some(current.id)).await?;
+    assert_eq!(revoked, 1);
+
+    let current = session::get_by_id(db.conn()?, current.id).await?;
+    let other = session::get_by_id(db.conn()?, other.id).await?;
+    assert_eq!(current.revoked_at, None);
+    assert!(other.revoked_at.is_some());
+    Ok(())
+}
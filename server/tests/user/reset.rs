@@ -0,0 +1,78 @@
+use rustwebapp::{
+    model::{
+        password_reset,
+        session::{self, SessionClient},
+        user::{self, ErrorPassword, ErrorUser},
+    },
+    web::error::MainError,
+};
+
+use crate::shared::{
+    db::TestDb,
+    user::{new_user, PASSWORD},
+};
+
+#[tokio::test]
+async fn reset_password_with_token() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let user = new_user(&db, "bob").await?;
+
+    let token = password_reset::create_token(db.conn()?, &user).await?;
+    password_reset::reset(db.conn()?, &token, "a brand new password").await?;
+
+    let result = user::login(db.conn()?, "bob@contoso.com", PASSWORD).await;
+    assert!(matches!(result, Err(MainError::LoginFail)));
+    user::login(db.conn()?, "bob@contoso.com", "a brand new password").await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn reset_token_is_single_use() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let user = new_user(&db, "bob").await?;
+
+    let first = password_reset::create_token(db.conn()?, &user).await?;
+    let second = password_reset::create_token(db.conn()?, &user).await?;
+    password_reset::reset(db.conn()?, &first, "a brand new password").await?;
+
+    // Using one token burns every other outstanding token too
+    for token in [first, second] {
+        let result = password_reset::reset(db.conn()?, &token, "another new password").await;
+        assert_eq!(result, Err(ErrorUser::InvalidToken));
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn reset_rejects_weak_password() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let user = new_user(&db, "bob").await?;
+
+    let token = password_reset::create_token(db.conn()?, &user).await?;
+    let result = password_reset::reset(db.conn()?, &token, "short").await;
+    assert_eq!(
+        result,
+        Err(ErrorUser::Password(ErrorPassword::TooShort(12)))
+    );
+
+    // A rejected password doesn't use up the token
+    password_reset::reset(db.conn()?, &token, "a brand new password").await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn reset_revokes_all_sessions() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let user = new_user(&db, "bob").await?;
+    let (laptop, _) = session::create(db.conn()?, user.id, &SessionClient::default()).await?;
+    let (phone, _) = session::create(db.conn()?, user.id, &SessionClient::default()).await?;
+
+    let token = password_reset::create_token(db.conn()?, &user).await?;
+    password_reset::reset(db.conn()?, &token, "a brand new password").await?;
+
+    for session_id in [laptop.id, phone.id] {
+        let session = session::get_by_id(db.conn()?, session_id).await?;
+        assert!(session.revoked_at.is_some());
+    }
+    Ok(())
+}
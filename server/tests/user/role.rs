@@ -0,0 +1,45 @@
+use rustwebapp::model::{
+    role::Role,
+    user::{self, ErrorUser},
+};
+
+use crate::shared::{
+    db::TestDb,
+    user::{new_user, PASSWORD},
+};
+
+#[tokio::test]
+async fn new_users_are_regular_users() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let user = new_user(&db, "bob").await?;
+    assert_eq!(user.role, Role::User);
+
+    let claims = user::login(db.conn()?, "bob@contoso.com", PASSWORD).await?;
+    assert_eq!(claims.role, Role::User);
+    Ok(())
+}
+
+#[tokio::test]
+async fn set_role_reaches_claims() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let user = new_user(&db, "bob").await?;
+
+    let user = user::set_role(db.conn()?, user.id, Role::Moderator).await?;
+    assert_eq!(user.role, Role::Moderator);
+    assert_eq!(
+        user::get_by_id(db.conn()?, user.id).await?.role,
+        Role::Moderator
+    );
+
+    let claims = user::login(db.conn()?, "bob@contoso.com", PASSWORD).await?;
+    assert_eq!(claims.role, Role::Moderator);
+    Ok(())
+}
+
+#[tokio::test]
+async fn set_role_of_missing_user() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let result = user::set_role(db.conn()?, 42, Role::Admin).await;
+    assert_eq!(result, Err(ErrorUser::NotFound));
+    Ok(())
+}
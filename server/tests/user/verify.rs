@@ -0,0 +1,166 @@
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    http::{header, Method, Request, StatusCode},
+};
+use rustwebapp::{
+    model::{
+        email_verification,
+        user::{self, ErrorUser},
+    },
+    service::{
+        jwt::Claims,
+        mailer::{Email, ErrorMailer, Mailer},
+        throttle::ThrottlePolicy,
+    },
+};
+use serde_json::json;
+
+use crate::shared::{
+    app::App,
+    db::TestDb,
+    user::{new_user, PASSWORD},
+};
+
+const CLIENT_IP: &str = "x-client-ip";
+
+#[derive(Debug)]
+struct BrokenMailer;
+
+#[async_trait::async_trait]
+impl Mailer for BrokenMailer {
+    async fn send(&self, email: Email) -> Result<(), ErrorMailer> {
+        Err(ErrorMailer::Send(format!("Can't reach {}", email.to)))
+    }
+}
+
+/// A resend request for `email` from `ip`, with no credentials
+fn resend(email: &str, ip: &str) -> anyhow::Result<Request<Body>> {
+    Ok(Request::builder()
+        .method(Method::POST)
+        .uri("/api/account/verify/resend")
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(CLIENT_IP, ip)
+        .body(Body::from(json!({ "email": email }).to_string()))?)
+}
+
+#[tokio::test]
+async fn verify_email_with_token() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let user = new_user(&db, "bob").await?;
+    assert_eq!(user.email_verified_at, None);
+
+    let claims: Claims = user::login(db.conn()?, "bob@contoso.com", PASSWORD).await?;
+    assert!(!claims.email_verified);
+
+    let token = email_verification::create_token(db.conn()?, &user).await?;
+    let verified = email_verification::verify(db.conn()?, &token).await?;
+    assert!(verified.email_verified_at.is_some());
+
+    let claims: Claims = user::login(db.conn()?, "bob@contoso.com", PASSWORD).await?;
+    assert!(claims.email_verified);
+    Ok(())
+}
+
+#[tokio::test]
+async fn verification_token_is_single_use() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let user = new_user(&db, "bob").await?;
+
+    let token = email_verification::create_token(db.conn()?, &user).await?;
+    email_verification::verify(db.conn()?, &token).await?;
+
+    let result = email_verification::verify(db.conn()?, &token).await;
+    assert_eq!(result, Err(ErrorUser::InvalidToken));
+
+    let verified = user::get_by_id(db.conn()?, user.id).await?;
+    let result = email_verification::create_token(db.conn()?, &verified).await;
+    assert_eq!(result, Err(ErrorUser::EmailAlreadyVerified));
+    Ok(())
+}
+
+#[tokio::test]
+async fn changing_email_needs_verifying_again() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let user = new_user(&db, "bob").await?;
+
+    // A token for the old address must not verify the new one
+    let stale = email_verification::create_token(db.conn()?, &user).await?;
+    let user = user::update_email(db.conn()?, user.id, "robert@contoso.com").await?;
+    let result = email_verification::verify(db.conn()?, &stale).await;
+    assert_eq!(result, Err(ErrorUser::InvalidToken));
+
+    let token = email_verification::create_token(db.conn()?, &user).await?;
+    email_verification::verify(db.conn()?, &token).await?;
+
+    let user = user::update_email(db.conn()?, user.id, "bobby@contoso.com").await?;
+    assert_eq!(user.email_verified_at, None);
+    Ok(())
+}
+
+#[tokio::test]
+async fn unknown_token_is_rejected() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let result = email_verification::verify(db.conn()?, "not-a-token").await;
+    assert_eq!(result, Err(ErrorUser::InvalidToken));
+    Ok(())
+}
+
+#[tokio::test]
+async fn resending_verification_does_not_reveal_accounts() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let app = App::with_state(&db, |app_state| {
+        app_state.config.client_ip_header = Some(CLIENT_IP.into());
+        app_state.mailer = Arc::new(BrokenMailer);
+    })
+    .await?;
+    new_user(&db, "bob").await?;
+
+    // A mailer failure for a real account looks the same as an unknown address
+    for email in ["bob@contoso.com", "nobody@contoso.com"] {
+        let (status, _, body) = app.send_request(resend(email, "192.0.2.1")?).await?;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body, json!({ "sent": true }));
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn resending_verification_is_throttled() -> anyhow::Result<()> {
+    let db = TestDb::new().await?;
+    let app = App::with_state(&db, |app_state| {
+        app_state.config.client_ip_header = Some(CLIENT_IP.into());
+    })
+    .await?;
+    new_user(&db, "bob").await?;
+
+    let free_attempts = ThrottlePolicy::default().free_attempts;
+    for _ in 0..=free_attempts {
+        let (status, _, body) = app
+            .send_request(resend("bob@contoso.com", "192.0.2.1")?)
+            .await?;
+        assert_eq!(status, StatusCode::OK, "{body}");
+    }
+    let (status, headers, _) = app
+        .send_request(resend("bob@contoso.com", "192.0.2.1")?)
+        .await?;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert!(headers.contains_key(header::RETRY_AFTER));
+
+    // Throttled by the address from anywhere, and by the client for any address
+    let (status, _, _) = app
+        .send_request(resend("bob@contoso.com", "198.51.100.1")?)
+        .await?;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    let (status, _, _) = app
+        .send_request(resend("alice@contoso.com", "192.0.2.1")?)
+        .await?;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+
+    let (status, _, _) = app
+        .send_request(resend("alice@contoso.com", "198.51.100.1")?)
+        .await?;
+    assert_eq!(status, StatusCode::OK);
+    Ok(())
+}